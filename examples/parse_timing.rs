@@ -0,0 +1,58 @@
+//! Times `LineParser::parse`, which holds the line pattern compiled once,
+//! against compiling the pattern again for every line.
+//!
+//! ```sh
+//! cargo run --release --example parse_timing -- 20000
+//! ```
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use loglyzer::{InputFormat, LineParser, Parsed, compile_pattern, parse_log_line};
+
+const LEVELS: [&str; 4] = ["INFO", "DEBUG", "WARNING", "ERROR"];
+
+fn lines(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            format!(
+                "2024-01-15 10:{:02}:{:02} [{}] request {} served in {}ms",
+                i / 60 % 60,
+                i % 60,
+                LEVELS[i % LEVELS.len()],
+                i,
+                i % 997
+            )
+        })
+        .collect()
+}
+
+fn time(lines: &[String], mut parse: impl FnMut(&str, usize) -> bool) -> (Duration, usize) {
+    let start = Instant::now();
+    let parsed = lines.iter().enumerate().filter(|(i, line)| parse(line, i + 1)).count();
+    (start.elapsed(), parsed)
+}
+
+fn main() {
+    let count = match std::env::args().nth(1) {
+        Some(arg) => arg.parse().expect("usage: parse_timing [LINES]"),
+        None => 10_000,
+    };
+    let lines = lines(count);
+
+    let mut parser = LineParser::new(InputFormat::Default, compile_pattern(None).unwrap());
+    let (once, once_parsed) = time(&lines, |line, number| {
+        matches!(black_box(parser.parse(line, number)), Parsed::Entry(_))
+    });
+
+    let (per_line, per_line_parsed) = time(&lines, |line, _| {
+        let pattern = compile_pattern(None).unwrap();
+        black_box(parse_log_line(line, &pattern, false)).is_some()
+    });
+
+    assert_eq!(once_parsed, per_line_parsed);
+    println!("{} lines, {} parsed", count, once_parsed);
+    println!("compiled once:     {:>10.1?}", once);
+    println!("compiled per line: {:>10.1?}", per_line);
+    println!("speedup:           {:>9.1}x", per_line.as_secs_f64() / once.as_secs_f64());
+}
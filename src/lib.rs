@@ -0,0 +1,7149 @@
+//! Parsing and analysis behind the `loglyzer` command.
+//!
+//! `Parser` turns a log file into `LogEntry` values, `Filter` applies the
+//! command's entry filters and `Analyzer` computes the `LogStats` that the
+//! report prints. The lower-level functions the command is built from are
+//! public too.
+//!
+//! ```no_run
+//! use loglyzer::{Analyzer, Filter, InputFormat, Parser};
+//!
+//! let log = Parser::new(InputFormat::Auto).parse_file("app.log".as_ref())?;
+//! let filter = Filter::new(false, Some("timeout"), &[]);
+//! let entries: Vec<_> = log.entries.into_iter().filter(|e| filter.matches(e)).collect();
+//! let stats = Analyzer::new().top(10).analyze(&entries);
+//! println!("{} entries, {} top errors", stats.total_entries, stats.top_errors.len());
+//! # Ok::<(), loglyzer::ReadError>(())
+//! ```
+
+#![warn(missing_docs)]
+
+use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{LazyLock, OnceLock};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::rc::Rc;
+
+use chrono::{DateTime, Datelike, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+
+/// Text encoding of an input; `Auto` guesses from its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Detect from a BOM or the first bytes; see `sniff_encoding`.
+    Auto,
+    /// UTF-8, with or without a BOM.
+    Utf8,
+    /// UTF-16, little-endian.
+    Utf16le,
+    /// UTF-16, big-endian.
+    Utf16be,
+    /// ISO 8859-1: every byte is the code point of the same value.
+    Latin1,
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Encoding::Auto => "auto",
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16le => "UTF-16LE",
+            Encoding::Utf16be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        })
+    }
+}
+
+/// Which top error `--show-error` drills into.
+#[derive(Debug, Clone)]
+pub enum ErrorSelector {
+    /// 1-based rank in `LogStats::top_errors`.
+    Rank(usize),
+    /// An error message, matched exactly or by normalized template.
+    Message(String),
+}
+
+/// Parses a level name as `LogLevel::from_name` reads it, for command-line flags.
+pub fn parse_level(s: &str) -> Result<LogLevel, String> {
+    LogLevel::from_name(s).ok_or_else(|| format!("unknown level '{}'", s))
+}
+
+/// Compiles a command-line regex, with the error as a message.
+pub fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| e.to_string())
+}
+
+/// Parses durations such as `30s`, `5m`, `1h` or `2d`.
+pub fn parse_duration(s: &str) -> Result<TimeDelta, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration '{}': expected e.g. 30s, 5m, 1h", s))?;
+
+    match unit {
+        "s" => Ok(TimeDelta::seconds(amount)),
+        "m" => Ok(TimeDelta::minutes(amount)),
+        "h" => Ok(TimeDelta::hours(amount)),
+        "d" => Ok(TimeDelta::days(amount)),
+        _ => Err(format!("invalid duration unit in '{}': use s, m, h or d", s)),
+    }
+}
+
+/// Checks a `--time-format` strftime string, e.g. `%d/%m/%Y %H:%M:%S`.
+pub fn parse_time_format(s: &str) -> Result<String, String> {
+    chrono::format::StrftimeItems::new(s)
+        .parse()
+        .map_err(|_| format!("invalid time format '{}': see chrono's strftime specifiers", s))?;
+    Ok(s.to_string())
+}
+
+/// A point in time as a user writes it, such as `--since` / `--until`
+/// take it: absolute, or relative to now.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBound {
+    /// A date and time that carried a UTC offset.
+    Utc(NaiveDateTime),
+    /// A date and time in the log's own time zone.
+    Local(NaiveDateTime),
+    /// This long before now; `now` itself is zero.
+    Ago(TimeDelta),
+    /// Midnight starting the day this many days before today.
+    DaysAgo(i64),
+}
+
+impl TimeBound {
+    /// The UTC time of the bound, with `tz` as the zone of `Local` times and
+    /// of calendar days (UTC when `None`).
+    pub fn resolve(self, now: DateTime<Utc>, tz: Option<Tz>) -> NaiveDateTime {
+        let local = |datetime| tz.map_or(datetime, |tz| local_to_utc(datetime, tz));
+        match self {
+            TimeBound::Utc(datetime) => datetime,
+            TimeBound::Local(datetime) => local(datetime),
+            TimeBound::Ago(delta) => now.naive_utc() - delta,
+            TimeBound::DaysAgo(days) => {
+                let today = tz.map_or(now.date_naive(), |tz| now.with_timezone(&tz).date_naive());
+                local((today - TimeDelta::days(days)).and_time(NaiveTime::MIN))
+            }
+        }
+    }
+}
+
+/// Parses `2024-05-01 00:00` (seconds, a `T` separator and an offset are
+/// optional), a bare date, a duration before now such as `2h`, or `now`,
+/// `today` or `yesterday`.
+impl std::str::FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "now" => return Ok(TimeBound::Ago(TimeDelta::zero())),
+            "today" => return Ok(TimeBound::DaysAgo(0)),
+            "yesterday" => return Ok(TimeBound::DaysAgo(1)),
+            _ => {}
+        }
+        if s.ends_with(|c: char| c.is_ascii_alphabetic()) && s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return parse_duration(s).map(TimeBound::Ago);
+        }
+        if let Some(datetime) = parse_timestamp(s).filter(|_| has_utc_offset(s)) {
+            return Ok(TimeBound::Utc(datetime));
+        }
+        let normalized = s.replacen('T', " ", 1);
+        ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%d %H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(&normalized, format).ok())
+            .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|date| date.and_time(NaiveTime::MIN)))
+            .map(TimeBound::Local)
+            .ok_or_else(|| {
+                format!("invalid time '{}': expected e.g. '2024-05-01 00:00', 2024-05-01, 2h or yesterday", s)
+            })
+    }
+}
+
+/// The line format of a log, which picks the parser `LineParser` uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// Whichever format parses most of the first lines; see `detect_format`
+    Auto,
+    /// `TIMESTAMP [LEVEL] message`, or whatever the parser's pattern describes
+    Default,
+    /// PostgreSQL server log with `log_line_prefix = '%m [%p] '` (optionally `%e`)
+    Postgres,
+    /// MySQL slow query log; one entry per query record
+    MysqlSlow,
+    /// Python `logging` output in its common layouts, tracebacks included
+    Python,
+    /// Ruby on Rails request log; one entry per request
+    Rails,
+    /// ArcSight Common Event Format, bare or behind a syslog header
+    Cef,
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs, as Go services often log them
+    Logfmt,
+    /// Graylog Extended Log Format messages, one per line
+    Gelf,
+    /// HAProxy HTTP log (`option httplog`), behind a syslog header or not
+    Haproxy,
+    /// Syslog, RFC 5424 or the classic BSD layout of RFC 3164
+    Syslog,
+    /// Apache / nginx access log in the Common or Combined Log Format
+    Access,
+    /// IIS / W3C extended log, columns given by `#Fields:` directives
+    W3c,
+}
+
+/// A `--require-fields` entry; without a type any value is accepted.
+#[derive(Debug, Clone)]
+pub struct FieldRequirement {
+    /// Top-level key of the record.
+    pub name: String,
+    /// Type the value must have, if any.
+    pub kind: Option<JsonType>,
+}
+
+/// The type of a JSON value, as `--require-fields` names it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonType {
+    /// A JSON string.
+    String,
+    /// A JSON number.
+    Number,
+    /// `true` or `false`.
+    Bool,
+    /// A JSON object.
+    Object,
+    /// A JSON array.
+    Array,
+    /// `null`.
+    Null,
+}
+
+impl JsonType {
+    /// The type of `value`.
+    pub fn of(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::String(_) => JsonType::String,
+            serde_json::Value::Number(_) => JsonType::Number,
+            serde_json::Value::Bool(_) => JsonType::Bool,
+            serde_json::Value::Object(_) => JsonType::Object,
+            serde_json::Value::Array(_) => JsonType::Array,
+            serde_json::Value::Null => JsonType::Null,
+        }
+    }
+
+    /// Lowercase name, as written after `name:` in `--require-fields`.
+    pub fn name(self) -> &'static str {
+        match self {
+            JsonType::String => "string",
+            JsonType::Number => "number",
+            JsonType::Bool => "bool",
+            JsonType::Object => "object",
+            JsonType::Array => "array",
+            JsonType::Null => "null",
+        }
+    }
+}
+
+/// A `--json-key` entry: the key that holds the timestamp, level or message
+/// of JSON and logfmt records, instead of the usual names.
+#[derive(Debug, Clone)]
+pub struct JsonKey {
+    /// What the key holds.
+    pub role: JsonRole,
+    /// Top-level key of the record.
+    pub key: String,
+}
+
+/// The part of a JSON or logfmt record a `JsonKey` locates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonRole {
+    /// The entry's timestamp.
+    Timestamp,
+    /// The entry's level.
+    Level,
+    /// The entry's message.
+    Message,
+}
+
+impl JsonRole {
+    /// Lowercase name, as written before `=` in `--json-key`.
+    pub fn name(self) -> &'static str {
+        match self {
+            JsonRole::Timestamp => "timestamp",
+            JsonRole::Level => "level",
+            JsonRole::Message => "message",
+        }
+    }
+}
+
+/* =========================
+   Log structures — Part 2
+   ========================= */
+
+/// One parsed log entry.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// 1-based line the entry starts on.
+    pub line: usize,
+    /// Timestamp as written in the log; empty when there is none.
+    pub timestamp: String,
+    /// The timestamp as UTC, when it could be parsed.
+    #[serde(skip)]
+    pub datetime: Option<NaiveDateTime>,
+    /// Level, or `Info` when the line gives none.
+    pub level: LogLevel,
+    /// Message text; continuation lines are appended after newlines.
+    pub message: String,
+    /// Named captures and structured fields beyond the four built-in columns.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Timestamp taken from the previous entry (`--inherit-timestamp`).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub inherited: bool,
+}
+
+/// Severity of an entry. Names are title case, as `{:?}` prints them.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub enum LogLevel {
+    /// `INFO`.
+    Info,
+    /// `WARNING` or `WARN`.
+    Warning,
+    /// `ERROR`.
+    Error,
+    /// `DEBUG`.
+    Debug,
+    /// `TRACE`.
+    Trace,
+    /// `FATAL`, `CRITICAL` or `PANIC`; counted as an error everywhere.
+    Fatal,
+    /// A pseudo-level named on the command line, see `--unparsed-level`.
+    #[serde(untagged)]
+    Other(String),
+}
+
+/// Level names are formatted with `{:?}` throughout; a pseudo-level shows
+/// as its bare name.
+impl std::fmt::Debug for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LogLevel::Info => "Info",
+            LogLevel::Warning => "Warning",
+            LogLevel::Error => "Error",
+            LogLevel::Debug => "Debug",
+            LogLevel::Trace => "Trace",
+            LogLevel::Fatal => "Fatal",
+            LogLevel::Other(name) => name,
+        })
+    }
+}
+
+impl LogLevel {
+    /// The built-in levels, most severe first.
+    pub const ALL: [LogLevel; 6] = [
+        LogLevel::Fatal,
+        LogLevel::Error,
+        LogLevel::Warning,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    /// Parses a built-in level name in any case, including `WARN` and `CRITICAL`.
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s.to_uppercase().as_str() {
+            "INFO" => Some(LogLevel::Info),
+            "WARNING" | "WARN" => Some(LogLevel::Warning),
+            "ERROR" => Some(LogLevel::Error),
+            "DEBUG" => Some(LogLevel::Debug),
+            "TRACE" => Some(LogLevel::Trace),
+            "FATAL" | "CRITICAL" => Some(LogLevel::Fatal),
+            _ => None,
+        }
+    }
+
+    /// Rank from `Trace` (0) to `Fatal` (5); pseudo-levels have none.
+    pub fn severity(&self) -> Option<u8> {
+        match self {
+            LogLevel::Trace => Some(0),
+            LogLevel::Debug => Some(1),
+            LogLevel::Info => Some(2),
+            LogLevel::Warning => Some(3),
+            LogLevel::Error => Some(4),
+            LogLevel::Fatal => Some(5),
+            LogLevel::Other(_) => None,
+        }
+    }
+
+    /// `Error` and `Fatal`.
+    pub fn is_error(&self) -> bool {
+        matches!(self, LogLevel::Error | LogLevel::Fatal)
+    }
+}
+
+/// Entries counted as errors in a per-level count: `Error` and `Fatal`.
+pub fn error_count<'a>(by_level: impl IntoIterator<Item = (&'a String, &'a usize)>) -> usize {
+    by_level
+        .into_iter()
+        .filter(|(level, _)| LogLevel::from_name(level).is_some_and(|level| level.is_error()))
+        .map(|(_, count)| count)
+        .sum()
+}
+
+
+
+/// Why reading a log failed.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The input could not be read.
+    Io(std::io::Error),
+    /// More entries than the limit (`--max-entries`) allows.
+    TooManyEntries(usize),
+    /// The `--parser-cmd` decoder died or failed.
+    Decoder(String),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "{}", e),
+            ReadError::TooManyEntries(max) => write!(
+                f,
+                "more than {} entries parsed; split the input or raise --max-entries",
+                max
+            ),
+            ReadError::Decoder(e) => write!(f, "parser command {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(e: std::io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// The result of reading a log: its entries and what could not be parsed.
+#[derive(Default)]
+pub struct ParsedLog {
+    /// Entries in input order.
+    pub entries: Vec<LogEntry>,
+    /// Lines that produced no entry, invalid records included.
+    pub unparsed_lines: usize,
+    /// Records in the expected format that were rejected, such as JSON lines
+    /// missing a required field.
+    pub invalid_records: usize,
+    /// Line and reason of the first few invalid records.
+    pub invalid_samples: Vec<(usize, String)>,
+    /// Lines cut at `--max-line-bytes`.
+    pub truncated_lines: usize,
+    /// Every line read, blank ones included.
+    pub lines: usize,
+}
+
+impl ParsedLog {
+    /// Adds the log of an input read after this one. Line numbers stay
+    /// those of each input.
+    pub fn append(&mut self, other: ParsedLog) {
+        self.entries.extend(other.entries);
+        self.unparsed_lines += other.unparsed_lines;
+        self.invalid_records += other.invalid_records;
+        self.invalid_samples.extend(other.invalid_samples);
+        self.invalid_samples.truncate(INVALID_SAMPLES);
+        self.truncated_lines += other.truncated_lines;
+        self.lines += other.lines;
+    }
+}
+
+/// How many invalid records `ParsedLog` keeps as samples.
+pub const INVALID_SAMPLES: usize = 5;
+
+/// Appended to lines cut at `--max-line-bytes`.
+pub(crate) const TRUNCATED_MARKER: &str = " [truncated]";
+
+/// One input line as handed to `read_log`.
+pub struct RawLine {
+    /// The line without its line ending.
+    pub text: String,
+    /// Level for the line when no parser recognises it (stderr of `run`).
+    pub fallback: Option<LogLevel>,
+    /// Whether the line was cut at `--max-line-bytes`.
+    pub truncated: bool,
+}
+
+/// Splits a reader into lines like `BufRead::split(b'\n')`, minus a
+/// trailing `\r`, but keeps at most `max` bytes of each, so a runaway line
+/// never sits in memory whole. Yields the bytes and whether they were cut.
+pub struct BoundedLines<R> {
+    reader: R,
+    max: Option<usize>,
+}
+
+impl<R: BufRead> BoundedLines<R> {
+    /// Lines of `reader`, each cut to `max` bytes if given.
+    pub fn new(reader: R, max: Option<usize>) -> Self {
+        BoundedLines { reader, max }
+    }
+}
+
+impl<R: BufRead> Iterator for BoundedLines<R> {
+    type Item = std::io::Result<(Vec<u8>, bool)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        let mut truncated = false;
+        let mut read_any = false;
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if available.is_empty() {
+                break;
+            }
+            read_any = true;
+            let end = available.iter().position(|&b| b == b'\n');
+            let chunk = &available[..end.unwrap_or(available.len())];
+            let room = self.max.map_or(chunk.len(), |max| max.saturating_sub(line.len()));
+            truncated |= room < chunk.len();
+            line.extend_from_slice(&chunk[..room.min(chunk.len())]);
+            let used = chunk.len() + usize::from(end.is_some());
+            self.reader.consume(used);
+            if end.is_some() {
+                if line.last() == Some(&b'\r') && !truncated {
+                    line.pop();
+                }
+                break;
+            }
+        }
+        read_any.then_some(Ok((line, truncated)))
+    }
+}
+
+/// Text of a line from `BoundedLines`. Invalid UTF-8 is an error, as with
+/// `BufRead::lines`, except for a character split by the truncation, which
+/// is dropped; truncated lines end with `TRUNCATED_MARKER`.
+pub fn line_text(mut bytes: Vec<u8>, truncated: bool) -> std::io::Result<String> {
+    if truncated
+        && let Err(e) = std::str::from_utf8(&bytes)
+        && e.error_len().is_none()
+    {
+        bytes.truncate(e.valid_up_to());
+    }
+    let mut text = String::from_utf8(bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    if truncated {
+        text.push_str(TRUNCATED_MARKER);
+    }
+    Ok(text)
+}
+
+/// Reads a log file, which may be gzipped, with `parser`; see `read_log`.
+pub fn read_log_file(
+    path: &std::path::Path,
+    parser: &mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    first_errors: Option<usize>,
+    max_line_bytes: Option<usize>,
+) -> Result<ParsedLog, ReadError> {
+    let lines = BoundedLines::new(open_input(path, parser.encoding)?, max_line_bytes).map(|line| {
+        let (bytes, truncated) = line?;
+        Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+    });
+    read_log(lines, parser, max_entries, inherit_timestamp, first_errors)
+}
+
+/// `read_log_file` for `--stream`: each entry goes to `emit` once complete
+/// instead of into `ParsedLog::entries`, which stays empty.
+pub fn read_log_file_each(
+    path: &std::path::Path,
+    parser: &mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    max_line_bytes: Option<usize>,
+    emit: impl FnMut(LogEntry),
+) -> Result<ParsedLog, ReadError> {
+    let lines = BoundedLines::new(open_input(path, parser.encoding)?, max_line_bytes).map(|line| {
+        let (bytes, truncated) = line?;
+        Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+    });
+    read_log_each(lines, parser, max_entries, inherit_timestamp, None, emit)
+}
+
+/// How often `FollowLines` looks for more data once it has read to the
+/// end of the file.
+pub const FOLLOW_POLL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// The lines of a file that is still being written, read like `tail -F`:
+/// at the end of the file it waits for more rather than stopping. A file
+/// that shrinks was truncated and is read again from the start; when the
+/// path names another file, the old one was rotated away, and the new one
+/// is read from its start. A line is only handed out once its newline is
+/// written, unless the caller takes it with `take_partial`. Ends only on an
+/// error.
+pub struct FollowLines {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// Bytes of the current file read so far.
+    offset: u64,
+    /// The line being read, kept to `max` bytes.
+    partial: Vec<u8>,
+    truncated: bool,
+    max: Option<usize>,
+}
+
+impl FollowLines {
+    /// Starts reading `path` from the beginning; lines are cut to `max_line_bytes`.
+    pub fn open(path: &std::path::Path, max_line_bytes: Option<usize>) -> std::io::Result<Self> {
+        Ok(FollowLines {
+            path: path.to_path_buf(),
+            reader: BufReader::new(File::open(path)?),
+            offset: 0,
+            partial: Vec::new(),
+            truncated: false,
+            max: max_line_bytes,
+        })
+    }
+
+    /// Reopens `path` from the start if the file was truncated or replaced.
+    /// The unfinished last line of a replaced file stays in `partial`.
+    fn reopen_if_changed(&mut self) -> std::io::Result<bool> {
+        let meta = match std::fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            // Rotated, and the new file is not created yet.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let replaced = file_id(&meta) != file_id(&self.reader.get_ref().metadata()?);
+        if !replaced && meta.len() >= self.offset {
+            return Ok(false);
+        }
+        self.reader = BufReader::new(File::open(&self.path)?);
+        self.offset = 0;
+        if !replaced {
+            self.partial.clear();
+            self.truncated = false;
+        }
+        Ok(true)
+    }
+
+    fn line(&mut self, mut bytes: Vec<u8>) -> std::io::Result<RawLine> {
+        let truncated = std::mem::take(&mut self.truncated);
+        if bytes.last() == Some(&b'\r') && !truncated {
+            bytes.pop();
+        }
+        Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+    }
+
+    /// The last line read so far though its newline is not written yet, for
+    /// when the file has gone quiet; the rest of it, if any comes, is then
+    /// read as a line of its own.
+    pub fn take_partial(&mut self) -> std::io::Result<Option<RawLine>> {
+        if self.partial.is_empty() {
+            return Ok(None);
+        }
+        let bytes = std::mem::take(&mut self.partial);
+        self.line(bytes).map(Some)
+    }
+
+    /// The next complete line, or `None` when there is nothing new yet.
+    pub fn poll(&mut self) -> std::io::Result<Option<RawLine>> {
+        loop {
+            match self.reader.read_until(b'\n', &mut self.partial) {
+                Ok(read) => self.offset += read as u64,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            let complete = self.partial.last() == Some(&b'\n');
+            if complete {
+                self.partial.pop();
+            }
+            if let Some(max) = self.max.filter(|&max| self.partial.len() > max) {
+                self.partial.truncate(max);
+                self.truncated = true;
+            }
+            if complete {
+                let bytes = std::mem::take(&mut self.partial);
+                return self.line(bytes).map(Some);
+            }
+            if !self.reopen_if_changed()? {
+                return Ok(None);
+            }
+            if !self.partial.is_empty() {
+                let bytes = std::mem::take(&mut self.partial);
+                return self.line(bytes).map(Some);
+            }
+        }
+    }
+}
+
+impl Iterator for FollowLines {
+    type Item = std::io::Result<RawLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.poll() {
+                Ok(Some(line)) => return Some(Ok(line)),
+                Ok(None) => std::thread::sleep(FOLLOW_POLL),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// What tells two files apart for `FollowLines`: device and inode on Unix.
+/// Elsewhere a replaced file is only noticed when it is shorter than the
+/// part of the old one already read.
+#[cfg(unix)]
+fn file_id(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Formats whose records never depend on lines before them other than
+/// their own continuation lines, so a file can be cut before any entry.
+pub const SPLITTABLE_FORMATS: &[InputFormat] = &[
+    InputFormat::Default,
+    InputFormat::Postgres,
+    InputFormat::Python,
+    InputFormat::Cef,
+    InputFormat::Json,
+    InputFormat::Gelf,
+    InputFormat::Haproxy,
+    InputFormat::Syslog,
+    InputFormat::Access,
+    InputFormat::Logfmt,
+];
+
+/// Byte offsets that cut `path` into at most `parts` ranges of similar size
+/// for `read_log_range_each`, from the start of the text to the end of the
+/// file. Each cut is moved forward to a line that `parser` reads as a new
+/// entry, so continuation lines stay with their entry. `None` when the file
+/// cannot be read from the middle: compressed, or not UTF-8.
+pub fn split_points(path: &std::path::Path, parser: &LineParser, parts: usize) -> std::io::Result<Option<Vec<u64>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+    if head.starts_with(&[0x1f, 0x8b]) {
+        return Ok(None);
+    }
+    let (detected, bom) = sniff_encoding(head);
+    let encoding = match parser.encoding {
+        Encoding::Auto => detected,
+        forced => forced,
+    };
+    if encoding != Encoding::Utf8 {
+        return Ok(None);
+    }
+
+    let size = reader.get_ref().metadata()?.len();
+    let mut points = vec![if bom && detected == Encoding::Utf8 { 3 } else { 0 }];
+    let mut probe = parser.fresh();
+    let mut line = Vec::new();
+    for part in 1..parts as u64 {
+        let last = *points.last().expect("starts with the first offset");
+        let target = (size * part / parts as u64).max(last);
+        reader.seek(SeekFrom::Start(target))?;
+        // The rest of the line the cut fell in.
+        line.clear();
+        let mut offset = target + reader.read_until(b'\n', &mut line)? as u64;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            if matches!(probe.parse(text.trim_end_matches(['\n', '\r']), 0), Parsed::Entry(_)) {
+                break;
+            }
+            offset += read as u64;
+        }
+        if offset > last && offset < size {
+            points.push(offset);
+        }
+    }
+    points.push(size);
+    Ok(Some(points))
+}
+
+/// `read_log_file_each` for the bytes `range` of a file cut by
+/// `split_points`. Line numbers count from the start of the range.
+pub fn read_log_range_each(
+    path: &std::path::Path,
+    range: std::ops::Range<u64>,
+    parser: &mut LineParser,
+    inherit_timestamp: bool,
+    max_line_bytes: Option<usize>,
+    emit: impl FnMut(LogEntry),
+) -> Result<ParsedLog, ReadError> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let reader = BufReader::new(file.take(range.end - range.start));
+    let lines = BoundedLines::new(reader, max_line_bytes).map(|line| {
+        let (bytes, truncated) = line?;
+        Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+    });
+    read_log_each(lines, parser, None, inherit_timestamp, None, emit)
+}
+
+/// Parses lines one at a time, so the raw lines are never held alongside
+/// the parsed entries. Stops as soon as `max_entries` is exceeded.
+///
+/// With `inherit_timestamp`, a line that lacks a leading timestamp becomes an
+/// entry with the previous entry's timestamp and level instead of being
+/// dropped. Such lines before the first entry have nothing to inherit and
+/// count as unparsed. A line paired with a level becomes an untimed entry
+/// of that level rather than unparsed.
+///
+/// With `first_errors`, reading stops at the first entry after the Nth
+/// error, so that error keeps its continuation lines.
+pub(crate) fn read_log(
+    lines: impl Iterator<Item = std::io::Result<RawLine>>,
+    parser: &mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    first_errors: Option<usize>,
+) -> Result<ParsedLog, ReadError> {
+    let mut entries = Vec::new();
+    let log = read_log_each(lines, parser, max_entries, inherit_timestamp, first_errors, |entry| {
+        entries.push(entry)
+    })?;
+    Ok(ParsedLog { entries, ..log })
+}
+
+/// `read_log` without keeping the entries: each one goes to `emit` as soon
+/// as the next entry starts, since until then continuation lines may still
+/// be added to it. Only that one entry is held at a time.
+pub(crate) fn read_log_each(
+    lines: impl Iterator<Item = std::io::Result<RawLine>>,
+    parser: &mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    first_errors: Option<usize>,
+    mut emit: impl FnMut(LogEntry),
+) -> Result<ParsedLog, ReadError> {
+    let mut reader = LogReader::new(parser, max_entries, inherit_timestamp, first_errors);
+    for line in lines {
+        if !reader.push(line?, &mut emit)? {
+            break;
+        }
+    }
+    Ok(reader.finish(&mut emit))
+}
+
+/// `read_log_each` one line at a time, for callers that act between lines,
+/// such as `--follow` handing over the held entry once the file goes quiet.
+pub struct LogReader<'a> {
+    parser: &'a mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    first_errors: Option<usize>,
+    pending: Option<LogEntry>,
+    count: usize,
+    errors: usize,
+    /// The counts so far; `entries` stays empty.
+    log: ParsedLog,
+}
+
+impl<'a> LogReader<'a> {
+    /// A reader feeding `parser`, with the limits of `read_log`.
+    pub fn new(
+        parser: &'a mut LineParser,
+        max_entries: Option<usize>,
+        inherit_timestamp: bool,
+        first_errors: Option<usize>,
+    ) -> Self {
+        LogReader {
+            parser,
+            max_entries,
+            inherit_timestamp,
+            first_errors,
+            pending: None,
+            count: 0,
+            errors: 0,
+            log: ParsedLog::default(),
+        }
+    }
+
+    /// Reads the next line, emitting the entry it ends if any. Returns
+    /// false once `first_errors` errors were read and reading should stop.
+    pub fn push(&mut self, line: RawLine, emit: &mut impl FnMut(LogEntry)) -> Result<bool, ReadError> {
+        let RawLine { text: line, fallback, truncated } = line;
+        let index = self.log.lines;
+        self.log.lines += 1;
+        self.log.truncated_lines += usize::from(truncated);
+        if line.trim().is_empty() {
+            return Ok(true);
+        }
+
+        let parsed = match &mut self.parser.decoder {
+            Some(decoder) => decoder.decode(&line, index + 1)?,
+            None => self.parser.parse(&line, index + 1),
+        };
+        let entry = match parsed {
+            Parsed::Entry(entry) => entry,
+            Parsed::Continuation(text) => {
+                match self.pending.as_mut() {
+                    Some(prev) => {
+                        if !prev.message.is_empty() {
+                            prev.message.push('\n');
+                        }
+                        prev.message.push_str(&text);
+                    }
+                    None => self.log.unparsed_lines += 1,
+                }
+                return Ok(true);
+            }
+            Parsed::Fields(fields) => {
+                match self.pending.as_mut() {
+                    Some(prev) => prev.fields.extend(fields),
+                    None => self.log.unparsed_lines += 1,
+                }
+                return Ok(true);
+            }
+            Parsed::Skip => return Ok(true),
+            Parsed::Invalid(reason) => {
+                self.log.unparsed_lines += 1;
+                self.log.invalid_records += 1;
+                if self.log.invalid_samples.len() < INVALID_SAMPLES {
+                    self.log.invalid_samples.push((index + 1, reason));
+                }
+                return Ok(true);
+            }
+            Parsed::Unparsed => match (fallback, self.pending.as_ref()) {
+                (Some(level), _) => LogEntry {
+                    line: 0,
+                    timestamp: String::new(),
+                    datetime: None,
+                    level,
+                    message: line,
+                    fields: BTreeMap::new(),
+                    inherited: false,
+                },
+                (None, Some(prev)) if self.inherit_timestamp && !has_timestamp_prefix(&line) => LogEntry {
+                    line: 0,
+                    message: line,
+                    inherited: true,
+                    ..prev.clone()
+                },
+                _ => {
+                    self.log.unparsed_lines += 1;
+                    let Some(name) = &self.parser.unparsed_level else { return Ok(true) };
+                    LogEntry {
+                        line: 0,
+                        timestamp: String::new(),
+                        datetime: None,
+                        level: LogLevel::Other(name.clone()),
+                        message: line,
+                        fields: BTreeMap::new(),
+                        inherited: false,
+                    }
+                }
+            },
+        };
+
+        if self.first_errors.is_some_and(|n| self.errors >= n) {
+            return Ok(false);
+        }
+        if self.max_entries.is_some_and(|max| self.count >= max) {
+            return Err(ReadError::TooManyEntries(self.count));
+        }
+        if entry.level.is_error() {
+            self.errors += 1;
+        }
+        let line = if entry.line == 0 { index + 1 } else { entry.line };
+        self.count += 1;
+        if let Some(prev) = self.pending.replace(LogEntry { line, ..entry }) {
+            self.emit(prev, emit);
+        }
+        Ok(true)
+    }
+
+    /// Emits the held entry now instead of when the next one starts; lines
+    /// that would have continued it count as unparsed.
+    pub fn flush(&mut self, emit: &mut impl FnMut(LogEntry)) {
+        if let Some(entry) = self.pending.take() {
+            self.emit(entry, emit);
+        }
+    }
+
+    /// Line counts so far, without entries.
+    pub fn progress(&self) -> ParsedLog {
+        ParsedLog {
+            entries: Vec::new(),
+            unparsed_lines: self.log.unparsed_lines,
+            invalid_records: self.log.invalid_records,
+            invalid_samples: self.log.invalid_samples.clone(),
+            truncated_lines: self.log.truncated_lines,
+            lines: self.log.lines,
+        }
+    }
+
+    /// Emits the held entry and whatever the parser still buffers.
+    pub fn finish(mut self, emit: &mut impl FnMut(LogEntry)) -> ParsedLog {
+        self.flush(emit);
+        for entry in self.parser.flush() {
+            self.emit(entry, emit);
+        }
+        self.log
+    }
+
+    fn emit(&mut self, mut entry: LogEntry, emit: &mut impl FnMut(LogEntry)) {
+        self.parser.finish(&mut entry);
+        emit(entry);
+    }
+}
+
+/// Lines handed to a `--parser-cmd` decoder ahead of its answers. Bounds
+/// memory when the decoder is slow, and leaves room for decoders that
+/// block-buffer their output.
+pub const DECODER_QUEUE: usize = 4096;
+
+/// Reads the file through a `--parser-cmd` decoder. A thread feeds the
+/// non-blank lines to the decoder's stdin while `read_log` reads one answer
+/// per line back in order; both block once `DECODER_QUEUE` lines are in
+/// flight. The command is split on whitespace, without a shell.
+pub fn read_log_decoded(
+    path: &std::path::Path,
+    command: &str,
+    parser: &mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    first_errors: Option<usize>,
+    max_line_bytes: Option<usize>,
+) -> Result<ParsedLog, ReadError> {
+    let reader = open_input(path, parser.encoding)?;
+    let mut args = command.split_whitespace();
+    let program = args.next().ok_or_else(|| ReadError::Decoder("is empty".to_string()))?;
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take();
+    let (tx, rx) = std::sync::mpsc::sync_channel(DECODER_QUEUE);
+    let writer = std::thread::spawn(move || {
+        for line in BoundedLines::new(reader, max_line_bytes) {
+            let line = line.and_then(|(bytes, truncated)| {
+                Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+            });
+            if let (Ok(line), Some(pipe)) = (&line, &mut stdin)
+                && !line.text.trim().is_empty()
+                && pipe.write_all(format!("{}\n", line.text).as_bytes()).is_err()
+            {
+                // The decoder is gone; `decode` reports it at this line.
+                stdin = None;
+            }
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    parser.decoder = Some(Decoder { answers: BufReader::new(child.stdout.take().unwrap()) });
+    let log = read_log(rx.into_iter(), parser, max_entries, inherit_timestamp, first_errors);
+    parser.decoder = None;
+    let _ = writer.join();
+    let status = child.wait()?;
+    let log = log?;
+    // Stopping early closes the decoder's pipes, which it may not survive.
+    if !status.success() && first_errors.is_none() {
+        return Err(ReadError::Decoder(format!("failed: {}", status)));
+    }
+    Ok(log)
+}
+
+/// The reading end of a `--parser-cmd` decoder.
+pub(crate) struct Decoder {
+    answers: BufReader<std::process::ChildStdout>,
+}
+
+impl Decoder {
+    /// Reads the answer for `line`: `null` skips it, an object becomes an
+    /// entry like a JSON line (`ts`, `level`, `msg`, ...), with the keys of
+    /// its `fields` object as fields. Anything else is an invalid record;
+    /// a decoder that stops answering is an error.
+    pub fn decode(&mut self, line: &str, number: usize) -> Result<Parsed, ReadError> {
+        let mut answer = String::new();
+        if self.answers.read_line(&mut answer)? == 0 {
+            return Err(ReadError::Decoder(format!("stopped answering at line {}: {}", number, line)));
+        }
+        if answer.trim() == "null" {
+            return Ok(Parsed::Skip);
+        }
+
+        let mut record: serde_json::Map<String, serde_json::Value> = match serde_json::from_str(&answer) {
+            Ok(record) => record,
+            Err(e) => return Ok(Parsed::Invalid(format!("parser command answered {} for: {}", e, line))),
+        };
+        if let Some(serde_json::Value::Object(fields)) = record.remove("fields") {
+            for (key, value) in fields {
+                record.entry(key).or_insert(value);
+            }
+        }
+        Ok(Parsed::Entry(json_entry(record, &[])))
+    }
+}
+
+/// Runs `command` and reads its stdout and stderr as they are written, one
+/// thread per pipe so that neither can fill up and stall the child. Every
+/// line is echoed to stderr unless `quiet`; unparsed stderr lines become
+/// Warning entries. Returns the parsed output and the child's exit code
+/// (1 when it was killed by a signal).
+pub fn run_command(
+    command: &[String],
+    parser: &mut LineParser,
+    max_entries: Option<usize>,
+    inherit_timestamp: bool,
+    first_errors: Option<usize>,
+    max_line_bytes: Option<usize>,
+    quiet: bool,
+) -> Result<(ParsedLog, i32), ReadError> {
+    let mut child = std::process::Command::new(&command[0])
+        .args(&command[1..])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let forward = |pipe: Box<dyn std::io::Read + Send>, fallback: Option<LogLevel>| {
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            for line in BoundedLines::new(BufReader::new(pipe), max_line_bytes) {
+                let line = line.map(|(bytes, truncated)| {
+                    let mut text = String::from_utf8_lossy(&bytes).trim_end_matches('\r').to_string();
+                    if truncated {
+                        text.push_str(TRUNCATED_MARKER);
+                    }
+                    if !quiet {
+                        eprintln!("{}", text);
+                    }
+                    RawLine { text, fallback: fallback.clone(), truncated }
+                });
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        })
+    };
+    let readers = [
+        forward(Box::new(child.stdout.take().unwrap()), None),
+        forward(Box::new(child.stderr.take().unwrap()), Some(LogLevel::Warning)),
+    ];
+    drop(tx);
+
+    let log = read_log(rx.into_iter(), parser, max_entries, inherit_timestamp, first_errors)?;
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let status = child.wait()?;
+    Ok((log, status.code().unwrap_or(1)))
+}
+
+/// Guesses the encoding from the first bytes of the input, and whether
+/// they are a BOM.
+pub(crate) fn sniff_encoding(sample: &[u8]) -> (Encoding, bool) {
+    if sample.starts_with(&[0xef, 0xbb, 0xbf]) {
+        return (Encoding::Utf8, true);
+    }
+    if sample.starts_with(&[0xff, 0xfe]) {
+        return (Encoding::Utf16le, true);
+    }
+    if sample.starts_with(&[0xfe, 0xff]) {
+        return (Encoding::Utf16be, true);
+    }
+    // ASCII text in UTF-16 has a NUL in every other byte.
+    let pairs = sample.len() / 2;
+    let nuls = |offset: usize| (0..pairs).filter(|i| sample[2 * i + offset] == 0).count();
+    let (even, odd) = (nuls(0), nuls(1));
+    if odd > pairs / 4 && odd > 2 * even {
+        return (Encoding::Utf16le, false);
+    }
+    if even > pairs / 4 && even > 2 * odd {
+        return (Encoding::Utf16be, false);
+    }
+    match std::str::from_utf8(sample) {
+        // The sample may end inside a character.
+        Err(e) if e.error_len().is_some() => (Encoding::Latin1, false),
+        _ => (Encoding::Utf8, false),
+    }
+}
+
+/// The input's encoding as `--detect-encoding` reports it: the guess from
+/// its first bytes (after gzip), and whether it starts with a BOM.
+pub fn detect_encoding(path: &std::path::Path) -> std::io::Result<(Encoding, bool)> {
+    let mut reader = BufReader::new(open_raw(path, true)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let mut reader = BufReader::new(MultiGzDecoder::new(reader));
+        return Ok(sniff_encoding(reader.fill_buf()?));
+    }
+    Ok(sniff_encoding(reader.fill_buf()?))
+}
+
+/// Transcodes UTF-16 or Latin-1 input to UTF-8 as it is read. Unpaired
+/// surrogates and a dangling odd byte become U+FFFD.
+pub(crate) struct Utf8Reader<R> {
+    inner: R,
+    encoding: Encoding,
+    decoded: Vec<u8>,
+    /// Start of the bytes in `decoded` not yet handed out.
+    pos: usize,
+    /// A byte of a UTF-16 code unit split across reads.
+    odd_byte: Option<u8>,
+    /// A high surrogate whose pair is in the next read.
+    high_surrogate: Option<u16>,
+}
+
+impl<R: Read> Utf8Reader<R> {
+    /// Reads `inner` as `encoding`, which should be UTF-16 or Latin-1.
+    pub fn new(inner: R, encoding: Encoding) -> Self {
+        Utf8Reader { inner, encoding, decoded: Vec::new(), pos: 0, odd_byte: None, high_surrogate: None }
+    }
+
+    fn decode(&mut self, raw: &[u8]) {
+        let mut text = String::new();
+        if self.encoding == Encoding::Latin1 {
+            text.extend(raw.iter().map(|&b| b as char));
+        } else {
+            let mut bytes: Vec<u8> = self.odd_byte.take().into_iter().collect();
+            bytes.extend_from_slice(raw);
+            if bytes.len() % 2 == 1 {
+                self.odd_byte = bytes.pop();
+            }
+            let mut units: Vec<u16> = self.high_surrogate.take().into_iter().collect();
+            units.extend(bytes.chunks_exact(2).map(|pair| match self.encoding {
+                Encoding::Utf16be => u16::from_be_bytes([pair[0], pair[1]]),
+                _ => u16::from_le_bytes([pair[0], pair[1]]),
+            }));
+            if units.last().is_some_and(|unit| (0xd800..0xdc00).contains(unit)) {
+                self.high_surrogate = units.pop();
+            }
+            text.extend(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+        }
+        self.decoded = text.into_bytes();
+        self.pos = 0;
+    }
+}
+
+impl<R: Read> Read for Utf8Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut raw = [0; 8192];
+        while self.pos == self.decoded.len() {
+            let n = self.inner.read(&mut raw)?;
+            if n == 0 {
+                if self.odd_byte.take().is_none() && self.high_surrogate.take().is_none() {
+                    return Ok(0);
+                }
+                self.decoded = char::REPLACEMENT_CHARACTER.to_string().into_bytes();
+                self.pos = 0;
+                break;
+            }
+            self.decode(&raw[..n]);
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The FILE argument that reads standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// Bytes at the start of standard input that format and encoding detection
+/// may look at before the analysis reads it.
+pub(crate) const STDIN_SAMPLE_BYTES: usize = 64 * 1024;
+
+static STDIN_HEAD: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Whether `path` is `STDIN_PATH`.
+pub fn is_stdin(path: &std::path::Path) -> bool {
+    path.as_os_str() == STDIN_PATH
+}
+
+/// The start of standard input, read on first use and kept for the
+/// readers opened after it: the `DETECT_SAMPLE_LINES` lines that format
+/// detection samples, or `STDIN_SAMPLE_BYTES` if that comes first, so a
+/// slow pipe is not waited on for more.
+fn stdin_head() -> &'static [u8] {
+    STDIN_HEAD.get_or_init(|| {
+        let mut stdin = std::io::stdin().lock();
+        let mut head = Vec::new();
+        let mut chunk = [0; 8192];
+        let mut lines = 0;
+        while head.len() < STDIN_SAMPLE_BYTES && lines < DETECT_SAMPLE_LINES {
+            match stdin.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    lines += chunk[..n].iter().filter(|&&b| b == b'\n').count();
+                    head.extend_from_slice(&chunk[..n]);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                // The error comes back when the analysis reads on.
+                Err(_) => break,
+            }
+        }
+        head
+    })
+}
+
+/// Opens FILE, or standard input for `-`, as raw bytes. A `sample` of
+/// standard input stops after `stdin_head`, leaving the rest unread.
+fn open_raw(path: &std::path::Path, sample: bool) -> std::io::Result<Box<dyn Read + Send>> {
+    if !is_stdin(path) {
+        return Ok(Box::new(File::open(path)?));
+    }
+    let head = std::io::Cursor::new(stdin_head());
+    if sample {
+        Ok(Box::new(head))
+    } else {
+        Ok(Box::new(head.chain(std::io::stdin())))
+    }
+}
+
+/// Opens a log file, decompressing it on the fly when it starts with the
+/// gzip magic bytes, whatever its extension, and `-` as standard input.
+/// The text is decoded as `encoding`, or as the encoding its first bytes
+/// suggest with `Encoding::Auto`; a BOM is dropped and anything other than
+/// UTF-8 is transcoded to it.
+pub fn open_input(path: &std::path::Path, encoding: Encoding) -> std::io::Result<Box<dyn BufRead + Send>> {
+    open_text(open_raw(path, false)?, encoding)
+}
+
+/// `open_input` for a look at the first lines: on standard input, only
+/// what `STDIN_SAMPLE_BYTES` holds, which the analysis still gets to read.
+pub(crate) fn open_sample(path: &std::path::Path, encoding: Encoding) -> std::io::Result<Box<dyn BufRead + Send>> {
+    open_text(open_raw(path, true)?, encoding)
+}
+
+fn open_text(raw: Box<dyn Read + Send>, encoding: Encoding) -> std::io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(raw);
+    let mut reader: Box<dyn BufRead + Send> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    let (detected, bom) = sniff_encoding(reader.fill_buf()?);
+    let encoding = match encoding {
+        Encoding::Auto => detected,
+        forced => forced,
+    };
+    if bom && encoding == detected {
+        reader.consume(if encoding == Encoding::Utf8 { 3 } else { 2 });
+    }
+    match encoding {
+        Encoding::Auto | Encoding::Utf8 => Ok(reader),
+        other => Ok(Box::new(BufReader::new(Utf8Reader::new(reader, other)))),
+    }
+}
+
+/// Expands `*` and `?` in the file name of `path` to the matching files of
+/// its directory, sorted; a path without wildcards is kept as it is. As in
+/// the shell, wildcards do not match a leading dot.
+pub fn expand_glob(path: &std::path::Path) -> std::io::Result<Vec<PathBuf>> {
+    let Some(pattern) = path.file_name().and_then(|name| name.to_str()).filter(|name| name.contains(['*', '?']))
+    else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if (pattern.starts_with('.') || !name.starts_with('.'))
+            && wildcard_match(pattern, name)
+            && entry.file_type()?.is_file()
+        {
+            files.push(path.with_file_name(name));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// `*` matches any run of characters, `?` any one character.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and where in `name` it currently stops.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Loads one regex per line; blank lines and lines starting with `#` are skipped.
+pub fn load_ignore_patterns(path: &std::path::Path) -> Result<RegexSet, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let patterns: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+
+    RegexSet::new(patterns).map_err(|e| e.to_string())
+}
+
+/// Whether `line` starts with an ISO 8601 timestamp.
+pub(crate) fn has_timestamp_prefix(line: &str) -> bool {
+    line.get(..19).and_then(parse_timestamp).is_some()
+}
+
+
+
+/// Line pattern of the default format: `TIMESTAMP [LEVEL] message`.
+pub(crate) const DEFAULT_PATTERN: &str = r"^(?P<timestamp>\d{4}-\d{2}-\d{2}(?:T|\s+)\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:?\d{2})?)\s+\[(?P<level>\w+)\]\s+(?P<message>.+)$";
+
+/// Named groups a pattern can use for the built-in columns; any other
+/// group becomes a field.
+pub const RESERVED_GROUPS: &[&str] = &["timestamp", "level", "message"];
+
+/// Field holding the bracketed token with `--bracket-as-category`.
+pub const CATEGORY_FIELD: &str = "category";
+
+/// Field naming the input file of each entry when several are analyzed.
+pub const SOURCE_FIELD: &str = "source_file";
+
+/// Compiles the line pattern once. Custom patterns must capture `message`;
+/// `timestamp` and `level` are optional.
+pub fn compile_pattern(pattern: Option<&str>) -> Result<Regex, String> {
+    let re = Regex::new(pattern.unwrap_or(DEFAULT_PATTERN)).map_err(|e| e.to_string())?;
+
+    if !re.capture_names().any(|name| name == Some("message")) {
+        return Err("pattern must contain a named group (?P<message>...)".to_string());
+    }
+
+    Ok(re)
+}
+
+/// Lines of the input `--profile-regex` times the pattern against.
+pub const PROFILE_SAMPLE_LINES: usize = 10_000;
+
+/// The sample is matched repeatedly until this much time has passed, so
+/// short files still give a stable rate.
+pub(crate) const PROFILE_MIN_SECONDS: f64 = 0.2;
+
+/// Per-line match time above which `--profile-regex` calls the pattern slow.
+pub const SLOW_MATCH_MICROS: f64 = 20.0;
+
+/// How fast a pattern matches, from `profile_regex`.
+pub struct RegexProfile {
+    /// Lines in the sample.
+    pub lines: usize,
+    /// Sample lines the pattern matches.
+    pub matched: usize,
+    /// Sample lines matched per second.
+    pub lines_per_second: f64,
+}
+
+/// Times `re.captures`, which is what parsing a line costs, over `sample`.
+pub fn profile_regex(re: &Regex, sample: &[String]) -> RegexProfile {
+    let matched = sample.iter().filter(|line| re.is_match(line)).count();
+    let started = std::time::Instant::now();
+    let mut passes = 0;
+    while passes == 0 || started.elapsed().as_secs_f64() < PROFILE_MIN_SECONDS {
+        for line in sample {
+            std::hint::black_box(re.captures(line));
+        }
+        passes += 1;
+    }
+    let seconds = started.elapsed().as_secs_f64();
+
+    RegexProfile {
+        lines: sample.len(),
+        matched,
+        lines_per_second: (sample.len() * passes) as f64 / seconds.max(f64::EPSILON),
+    }
+}
+
+/// Heuristic for a group that is repeated without bound and itself holds
+/// an unbounded repetition, as in `(a+)*` or `(\w+\s?)+`. The regex engine
+/// here matches in linear time, but the same pattern backtracks
+/// catastrophically in PCRE-style engines.
+pub fn has_nested_quantifiers(pattern: &str) -> bool {
+    // One flag per open group: does it contain an unbounded quantifier?
+    let mut groups: Vec<bool> = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    let mut closed_group: Option<bool> = None;
+
+    while let Some(c) = chars.next() {
+        let unbounded = match c {
+            '*' | '+' => true,
+            '{' => {
+                let mut repeat = String::new();
+                while let Some(c) = chars.next_if(|&c| c != '}') {
+                    repeat.push(c);
+                }
+                chars.next();
+                repeat.ends_with(',')
+            }
+            _ => false,
+        };
+        if unbounded {
+            if closed_group == Some(true) {
+                return true;
+            }
+            if let Some(inner) = groups.last_mut() {
+                *inner = true;
+            }
+        }
+        closed_group = None;
+
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                // Skip the class; `]` right after `[` or `[^` is a literal.
+                chars.next_if_eq(&'^');
+                chars.next_if_eq(&']');
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        ']' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '(' => groups.push(false),
+            ')' => {
+                let inner = groups.pop().unwrap_or(false);
+                if let Some(outer) = groups.last_mut() {
+                    *outer |= inner;
+                }
+                closed_group = Some(inner);
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// What a single input line turned out to be.
+#[derive(Debug)]
+pub enum Parsed {
+    /// A new entry.
+    Entry(LogEntry),
+    /// More text for the previous entry's message (detail lines, wrapped statements).
+    Continuation(String),
+    /// More fields for the previous entry (record headers of multi-line formats).
+    Fields(BTreeMap<String, String>),
+    /// Structural lines such as file headers that are neither entries nor failures.
+    Skip,
+    /// A record in the expected format that was rejected, with the reason.
+    Invalid(String),
+    /// A line no parser recognises.
+    Unparsed,
+}
+
+/// Dispatches each line to the parser for the selected input format.
+///
+/// Settings are given with the builder methods after `new`; the rest is
+/// state carried from line to line by multi-line formats.
+pub struct LineParser {
+    format: InputFormat,
+    pattern: Regex,
+    /// Last `# Time:` header of a MySQL slow log; records without one share it.
+    last_time: Option<String>,
+    /// Database selected by the last `use db;` of a MySQL slow log.
+    last_db: Option<String>,
+    /// Rails requests seen `Started` but not yet `Completed`, by request tag.
+    open_requests: HashMap<String, LogEntry>,
+    required_fields: Vec<FieldRequirement>,
+    json_keys: Vec<JsonKey>,
+    /// Column names from the last `#Fields:` directive of a W3C log.
+    w3c_fields: Vec<String>,
+    /// A running `--parser-cmd`, which replaces the format's parser.
+    decoder: Option<Decoder>,
+    unparsed_level: Option<String>,
+    bracket_as_category: bool,
+    multiline: bool,
+    time_format: Option<String>,
+    input_tz: Option<Tz>,
+    encoding: Encoding,
+}
+
+impl LineParser {
+    /// A parser with the same settings and none of the state, for reading
+    /// another part of the input.
+    pub fn fresh(&self) -> LineParser {
+        LineParser::new(self.format, self.pattern.clone())
+            .required_fields(self.required_fields.clone())
+            .json_keys(self.json_keys.clone())
+            .unparsed_level(self.unparsed_level.clone())
+            .bracket_as_category(self.bracket_as_category)
+            .multiline(self.multiline)
+            .time_format(self.time_format.clone())
+            .input_tz(self.input_tz)
+            .encoding(self.encoding)
+    }
+
+    /// A parser for `format`; `pattern` is the line regex of the default
+    /// format (see `compile_pattern`).
+    pub fn new(format: InputFormat, pattern: Regex) -> Self {
+        LineParser {
+            format,
+            pattern,
+            last_time: None,
+            last_db: None,
+            open_requests: HashMap::new(),
+            required_fields: Vec::new(),
+            json_keys: Vec::new(),
+            w3c_fields: Vec::new(),
+            decoder: None,
+            unparsed_level: None,
+            bracket_as_category: false,
+            multiline: false,
+            time_format: None,
+            input_tz: None,
+            encoding: Encoding::Auto,
+        }
+    }
+
+    /// Checks applied to JSON and GELF records; failures are invalid records.
+    pub fn required_fields(mut self, required: Vec<FieldRequirement>) -> Self {
+        self.required_fields = required;
+        self
+    }
+
+    /// Keys for the timestamp, level and message of JSON and logfmt records.
+    pub fn json_keys(mut self, keys: Vec<JsonKey>) -> Self {
+        self.json_keys = keys;
+        self
+    }
+
+    /// Keep lines no parser recognises as entries of this pseudo-level.
+    pub fn unparsed_level(mut self, level: Option<String>) -> Self {
+        self.unparsed_level = level;
+        self
+    }
+
+    /// Read the `level` group of the default pattern as a category.
+    pub fn bracket_as_category(mut self, as_category: bool) -> Self {
+        self.bracket_as_category = as_category;
+        self
+    }
+
+    /// Lines the default pattern does not match continue the previous entry.
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// strftime layout tried first on each timestamp.
+    pub fn time_format(mut self, format: Option<String>) -> Self {
+        self.time_format = format;
+        self
+    }
+
+    /// Zone of timestamps written without an offset.
+    pub fn input_tz(mut self, tz: Option<Tz>) -> Self {
+        self.input_tz = tz;
+        self
+    }
+
+    /// Text encoding of the files this parser reads; `Auto` detects it.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// The format this parser reads.
+    pub fn format(&self) -> InputFormat {
+        self.format
+    }
+
+    /// The line regex of the default format.
+    pub fn pattern(&self) -> &Regex {
+        &self.pattern
+    }
+
+    /// `number` is the 1-based line number, for formats that emit an entry
+    /// on a later line than the one it starts on.
+    pub fn parse(&mut self, line: &str, number: usize) -> Parsed {
+        let entry = match self.format {
+            InputFormat::Auto | InputFormat::Default => {
+                match parse_log_line(line, &self.pattern, self.bracket_as_category) {
+                    None if self.multiline => return Parsed::Continuation(line.trim_end().to_string()),
+                    entry => entry,
+                }
+            }
+            InputFormat::Postgres => return parse_postgres_line(line),
+            InputFormat::MysqlSlow => return self.parse_mysql_slow_line(line),
+            InputFormat::Python => return parse_python_line(line),
+            InputFormat::Rails => return self.parse_rails_line(line, number),
+            InputFormat::Cef => parse_cef_line(line),
+            InputFormat::Haproxy => parse_haproxy_line(line),
+            InputFormat::Syslog => parse_syslog_line(line),
+            InputFormat::Access => parse_access_line(line),
+            InputFormat::Json => return parse_json_line(line, &self.required_fields, &self.json_keys),
+            InputFormat::Logfmt => parse_logfmt_line(line, &self.json_keys),
+            InputFormat::Gelf => return parse_gelf_line(line, &self.required_fields),
+            InputFormat::W3c => return self.parse_w3c_line(line),
+        };
+        entry.map_or(Parsed::Unparsed, Parsed::Entry)
+    }
+
+    /// Entries still buffered at the end of the input.
+    pub fn flush(&mut self) -> Vec<LogEntry> {
+        let mut unfinished: Vec<LogEntry> =
+            self.open_requests.drain().map(|(_, e)| unfinished_request(e)).collect();
+        unfinished.sort_by_key(|e| e.line);
+        unfinished
+    }
+
+    /// Runs once per entry after the whole input is read.
+    pub fn finish(&self, entry: &mut LogEntry) {
+        if self.format == InputFormat::MysqlSlow {
+            entry.message = normalize_sql(&entry.message);
+        }
+        if let Some(format) = &self.time_format
+            && let Some(datetime) = parse_time_with(&entry.timestamp, format, self.input_tz)
+        {
+            entry.datetime = Some(datetime);
+        } else if let (Some(tz), Some(datetime)) = (self.input_tz, entry.datetime)
+            && !has_utc_offset(&entry.timestamp)
+        {
+            entry.datetime = Some(local_to_utc(datetime, tz));
+        }
+    }
+
+    /// Folds a request's lines into one entry, emitted at its `Completed`
+    /// line. Requests are told apart by their first tag (`config.log_tags`,
+    /// usually the request id), so interleaved workers are handled; untagged
+    /// logs are taken to be sequential. A `Started` for a tag whose previous
+    /// request never completed emits that one as unfinished.
+    ///
+    /// The entry's message is `Controller#action status text`, and the
+    /// level comes from the status class: 5xx Error, 4xx Warning, else Info.
+    pub fn parse_rails_line(&mut self, line: &str, number: usize) -> Parsed {
+        let Some(caps) = RAILS_PREFIX.captures(line) else {
+            return Parsed::Skip;
+        };
+        let key = caps.name("tag").map_or("", |m| m.as_str()).to_string();
+        let body = &caps["body"];
+
+        if let Some(started) = RAILS_STARTED.captures(body) {
+            let timestamp = started["time"].to_string();
+            let datetime = parse_timestamp(&timestamp)
+                .or_else(|| caps.name("time").and_then(|t| parse_timestamp(t.as_str())));
+            let mut fields = BTreeMap::from([
+                ("method".to_string(), started["method"].to_string()),
+                ("path".to_string(), started["path"].to_string()),
+                ("ip".to_string(), started["ip"].to_string()),
+            ]);
+            if !key.is_empty() {
+                fields.insert("request_id".to_string(), key.clone());
+            }
+            let request = LogEntry {
+                line: number,
+                timestamp,
+                datetime,
+                level: LogLevel::Info,
+                message: format!("{} {}", &started["method"], &started["path"]),
+                fields,
+                inherited: false,
+            };
+            return match self.open_requests.insert(key, request) {
+                Some(previous) => Parsed::Entry(unfinished_request(previous)),
+                None => Parsed::Skip,
+            };
+        }
+
+        let Some(request) = self.open_requests.get_mut(&key) else {
+            return Parsed::Skip;
+        };
+
+        if let Some(processing) = RAILS_PROCESSING.captures(body) {
+            request.fields.insert("action".to_string(), processing["action"].to_string());
+            request.fields.insert("format".to_string(), processing["format"].to_string());
+            return Parsed::Skip;
+        }
+
+        let Some(completed) = RAILS_COMPLETED.captures(body) else {
+            return Parsed::Skip;
+        };
+        let mut request = self.open_requests.remove(&key).unwrap();
+        let status = &completed["status"];
+        request.level = match status.as_bytes()[0] {
+            b'5' => LogLevel::Error,
+            b'4' => LogLevel::Warning,
+            _ => LogLevel::Info,
+        };
+        let endpoint = request.fields.get("action").unwrap_or(&request.message);
+        request.message = format!("{} {} {}", endpoint, status, completed["text"].trim());
+        request.fields.insert("status".to_string(), status.to_string());
+        request.fields.insert("duration_ms".to_string(), completed["ms"].to_string());
+        for part in completed.name("parts").map_or("", |m| m.as_str()).split('|') {
+            if let Some((name, value)) = part.split_once(':')
+                && let Some(ms) = value.trim().strip_suffix("ms")
+            {
+                let field = match name.trim() {
+                    "Views" => "view_ms",
+                    "ActiveRecord" => "db_ms",
+                    _ => continue,
+                };
+                request.fields.insert(field.to_string(), ms.to_string());
+            }
+        }
+        Parsed::Entry(request)
+    }
+
+    /// A record starts at `# User@Host:` and takes the most recent `# Time:`
+    /// header, as mysqld omits it for queries logged in the same second.
+    /// `# Key: value` header lines become lowercase fields (`query_time`,
+    /// `lock_time`, `rows_examined`, ...), `use db;` sets `db` for this and
+    /// later records, and the SQL text becomes the message. Entries are Info:
+    /// every record is a slow query.
+    pub fn parse_mysql_slow_line(&mut self, line: &str) -> Parsed {
+        if let Some(time) = line.strip_prefix("# Time:") {
+            self.last_time = Some(time.trim().to_string());
+            return Parsed::Skip;
+        }
+
+        if let Some(account) = line.strip_prefix("# User@Host:") {
+            let mut fields = mysql_header_fields(account);
+            let (user, rest) = account.split_once('@').unwrap_or((account, ""));
+            let user = user.split('[').next().unwrap_or_default().trim();
+            let host = rest.split(['[', ' ']).find(|s| !s.is_empty()).unwrap_or_default();
+            fields.insert("user".to_string(), user.to_string());
+            fields.insert("host".to_string(), host.to_string());
+            if let Some(db) = &self.last_db {
+                fields.insert("db".to_string(), db.clone());
+            }
+
+            let timestamp = self.last_time.clone().unwrap_or_default();
+            return Parsed::Entry(LogEntry {
+                line: 0,
+                datetime: parse_mysql_time(&timestamp),
+                timestamp,
+                level: LogLevel::Info,
+                message: String::new(),
+                fields,
+                inherited: false,
+            });
+        }
+
+        if let Some(header) = line.strip_prefix('#') {
+            return Parsed::Fields(mysql_header_fields(header));
+        }
+
+        let lower = line.to_ascii_lowercase();
+        if let Some(db) = lower.strip_prefix("use ") {
+            let db = line[4..4 + db.trim_end_matches(';').len()].trim().to_string();
+            self.last_db = Some(db.clone());
+            return Parsed::Fields(BTreeMap::from([("db".to_string(), db)]));
+        }
+        if lower.starts_with("set timestamp=") {
+            return Parsed::Skip;
+        }
+        if MYSQL_FILE_HEADER.iter().any(|h| line.starts_with(h)) || line.contains(", Version: ") {
+            return Parsed::Skip;
+        }
+
+        Parsed::Continuation(line.trim().to_string())
+    }
+
+    /// Columns are mapped by the latest `#Fields:` directive, so a server
+    /// restart that writes a new header mid-file re-maps from there; other
+    /// `#` directives are skipped. `date` and `time` form the timestamp,
+    /// `cs-method cs-uri-stem` the message, and the level follows the
+    /// `sc-status` class like Rails requests. The method, URI stem, status
+    /// and `time-taken` (milliseconds in IIS) are stored as `method`, `path`,
+    /// `status` and `duration_ms`; other columns keep their W3C names.
+    pub fn parse_w3c_line(&mut self, line: &str) -> Parsed {
+        if let Some(directive) = line.strip_prefix('#') {
+            if let Some(fields) = directive.strip_prefix("Fields:") {
+                self.w3c_fields = fields.split_whitespace().map(str::to_string).collect();
+            }
+            return Parsed::Skip;
+        }
+
+        let values: Vec<&str> = line.split_whitespace().collect();
+        if self.w3c_fields.is_empty() || values.len() != self.w3c_fields.len() {
+            return Parsed::Unparsed;
+        }
+
+        let mut fields = BTreeMap::new();
+        let (mut date, mut time) = ("", "");
+        for (name, &value) in self.w3c_fields.iter().zip(&values) {
+            let name = match name.as_str() {
+                "date" => {
+                    date = value;
+                    continue;
+                }
+                "time" => {
+                    time = value;
+                    continue;
+                }
+                "cs-method" => "method",
+                "cs-uri-stem" => "path",
+                "sc-status" => "status",
+                "time-taken" => "duration_ms",
+                name => name,
+            };
+            if value != "-" {
+                fields.insert(name.to_string(), value.to_string());
+            }
+        }
+
+        let Some(path) = fields.get("path") else {
+            return Parsed::Unparsed;
+        };
+        let message = match fields.get("method") {
+            Some(method) => format!("{} {}", method, path),
+            None => path.clone(),
+        };
+        fields.insert("action".to_string(), message.clone());
+        let level = match fields.get("status").map(|s| s.as_bytes()[0]) {
+            Some(b'5') => LogLevel::Error,
+            Some(b'4') => LogLevel::Warning,
+            _ => LogLevel::Info,
+        };
+
+        let timestamp = format!("{} {}", date, time).trim().to_string();
+        Parsed::Entry(LogEntry {
+            line: 0,
+            datetime: parse_timestamp(&timestamp),
+            timestamp,
+            level,
+            message,
+            fields,
+            inherited: false,
+        })
+    }
+}
+
+/// Lines sampled from the start of the file by `detect_format`.
+pub(crate) const DETECT_SAMPLE_LINES: usize = 50;
+
+/// Candidates for `--input-format auto`, in order of preference on ties.
+pub(crate) const DETECTABLE_FORMATS: &[InputFormat] = &[
+    InputFormat::Default,
+    InputFormat::Postgres,
+    InputFormat::MysqlSlow,
+    InputFormat::Python,
+    InputFormat::Rails,
+    InputFormat::Cef,
+    InputFormat::Haproxy,
+    InputFormat::Access,
+    InputFormat::Syslog,
+    InputFormat::Logfmt,
+    InputFormat::Gelf,
+    InputFormat::Json,
+    InputFormat::W3c,
+];
+
+/// Picks the format producing the most entries from the first non-blank
+/// lines. Falls back to the default format when nothing parses or the file
+/// cannot be read (reading it properly will report the error). Any JSON line
+/// carrying a GELF key rules out plain JSON, so that one invalid GELF record
+/// does not tip the file over to it.
+pub fn detect_format(path: &std::path::Path, pattern: &Regex, encoding: Encoding) -> InputFormat {
+    let Ok(reader) = open_sample(path, encoding) else {
+        return InputFormat::Default;
+    };
+    let sample: Vec<String> = reader
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .take(DETECT_SAMPLE_LINES)
+        .collect();
+
+    let entries = |format: InputFormat| {
+        let mut parser = LineParser::new(format, pattern.clone());
+        sample
+            .iter()
+            .enumerate()
+            .filter(|(i, line)| matches!(parser.parse(line, i + 1), Parsed::Entry(_)))
+            .count()
+    };
+
+    let gelf = sample.iter().any(|line| has_gelf_keys(line));
+    let mut best = (InputFormat::Default, 0);
+    for &format in DETECTABLE_FORMATS {
+        if gelf && format == InputFormat::Json {
+            continue;
+        }
+        let count = entries(format);
+        if count > best.1 {
+            best = (format, count);
+        }
+    }
+    best.0
+}
+
+/// Whether `line` is a JSON object with a `short_message` or `version` key.
+fn has_gelf_keys(line: &str) -> bool {
+    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line)
+        .is_ok_and(|record| record.contains_key("short_message") || record.contains_key("version"))
+}
+
+/// Without a `level` group every entry is Info; without a `timestamp` group
+/// entries have an empty timestamp and no parsed time. Named groups other
+/// than the reserved ones end up in `fields`. With `level_as_category`, the
+/// `level` group is stored as the `category` field instead, whatever it
+/// holds, and every entry is Info.
+pub fn parse_log_line(line: &str, pattern: &Regex, level_as_category: bool) -> Option<LogEntry> {
+    let caps = pattern.captures(line)?;
+
+    let timestamp = caps
+        .name("timestamp")
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_default();
+    let level = match caps.name("level") {
+        Some(m) if !level_as_category => LogLevel::from_name(m.as_str())?,
+        _ => LogLevel::Info,
+    };
+    let mut fields: BTreeMap<String, String> = pattern
+        .capture_names()
+        .flatten()
+        .filter(|name| !RESERVED_GROUPS.contains(name))
+        .filter_map(|name| Some((name.to_string(), caps.name(name)?.as_str().to_string())))
+        .collect();
+    if let Some(category) = caps.name("level").filter(|_| level_as_category) {
+        fields.insert(CATEGORY_FIELD.to_string(), category.as_str().to_string());
+    }
+
+    Some(LogEntry {
+        line: 0,
+        datetime: parse_timestamp(&timestamp),
+        timestamp,
+        level,
+        message: caps.name("message")?.as_str().to_string(),
+        fields,
+        inherited: false,
+    })
+}
+
+
+
+/// A PostgreSQL line with `log_line_prefix = '%m [%p] '`, optionally
+/// followed by `%e`: time, zone, pid, SQLSTATE, severity and message.
+pub(crate) static POSTGRES_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?P<time>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:\.\d+)?)(?: (?P<zone>[A-Za-z]{1,5}|[+-]\d{2}(?::?\d{2})?))?\s+\[(?P<pid>\d+)(?:-\d+)?\]:?(?:\s+(?P<sqlstate>[0-9A-Z]{5}))?:?\s+(?P<severity>[A-Z]+[1-5]?):\s+(?P<message>.*)$",
+    )
+    .unwrap()
+});
+
+/// Secondary severities that PostgreSQL writes as separate lines after the
+/// message they belong to.
+pub(crate) const POSTGRES_DETAIL: &[&str] = &["DETAIL", "HINT", "STATEMENT", "CONTEXT", "QUERY", "LOCATION"];
+
+/// Parses a PostgreSQL server log line. DETAIL/HINT/STATEMENT lines and the
+/// tab-indented lines of a wrapped statement are continuations of the entry
+/// above. The backend PID and, when `%e` is in the prefix, the SQLSTATE are
+/// stored as the `pid` and `sqlstate` fields.
+///
+/// Zone abbreviations other than UTC/GMT are ambiguous, so such timestamps are
+/// taken as UTC like naive ones; numeric offsets are converted.
+pub(crate) fn parse_postgres_line(line: &str) -> Parsed {
+    let Some(caps) = POSTGRES_LINE.captures(line) else {
+        return if line.starts_with([' ', '\t']) {
+            Parsed::Continuation(line.trim_end().to_string())
+        } else {
+            Parsed::Unparsed
+        };
+    };
+
+    let severity = &caps["severity"];
+    let message = caps["message"].to_string();
+    if POSTGRES_DETAIL.contains(&severity) {
+        return Parsed::Continuation(format!("{}:  {}", severity, message));
+    }
+    let level = match severity {
+        "LOG" | "NOTICE" | "INFO" => LogLevel::Info,
+        "WARNING" => LogLevel::Warning,
+        "ERROR" => LogLevel::Error,
+        "FATAL" | "PANIC" => LogLevel::Fatal,
+        s if s.starts_with("DEBUG") => LogLevel::Debug,
+        _ => return Parsed::Unparsed,
+    };
+
+    let time = &caps["time"];
+    let zone = caps.name("zone").map(|m| m.as_str());
+    let datetime = match zone {
+        Some(zone) if zone.starts_with(['+', '-']) => {
+            let offset = if zone.len() == 3 { format!("{}00", zone) } else { zone.to_string() };
+            parse_timestamp(&format!("{}{}", time, offset))
+        }
+        _ => parse_timestamp(time),
+    };
+    let timestamp = match zone {
+        Some(zone) => format!("{} {}", time, zone),
+        None => time.to_string(),
+    };
+
+    let mut fields = BTreeMap::new();
+    fields.insert("pid".to_string(), caps["pid"].to_string());
+    if let Some(sqlstate) = caps.name("sqlstate") {
+        fields.insert("sqlstate".to_string(), sqlstate.as_str().to_string());
+    }
+
+    Parsed::Entry(LogEntry {
+        line: 0,
+        timestamp,
+        datetime,
+        level,
+        message,
+        fields,
+        inherited: false,
+    })
+}
+
+/// A `name: value` pair of a MySQL slow log `# ...` header line.
+pub(crate) static MYSQL_HEADER_FIELD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\w+):\s+(\S+)").unwrap());
+
+/// Lines mysqld writes when it (re)opens the slow log.
+pub(crate) const MYSQL_FILE_HEADER: &[&str] = &["Tcp port:", "Time ", "Time\t"];
+
+/// Collects the `Key: value` pairs of a slow log header line, keys lowercased.
+pub(crate) fn mysql_header_fields(header: &str) -> BTreeMap<String, String> {
+    MYSQL_HEADER_FIELD
+        .captures_iter(header)
+        .map(|caps| (caps[1].to_ascii_lowercase(), caps[2].to_string()))
+        .collect()
+}
+
+/// Accepts the ISO-8601 header of MySQL 5.7+ and the older `YYMMDD H:MM:SS`.
+pub(crate) fn parse_mysql_time(s: &str) -> Option<NaiveDateTime> {
+    parse_timestamp(s).or_else(|| {
+        let s = s.split_whitespace().collect::<Vec<_>>().join(" ");
+        NaiveDateTime::parse_from_str(&s, "%y%m%d %H:%M:%S").ok()
+    })
+}
+
+/// Layouts of Python's `logging` module, most specific first: the
+/// `%(asctime)s - %(name)s - %(levelname)s - %(message)s` default of many
+/// projects, `basicConfig`'s `LEVEL:name:message`, and the bracketed-level
+/// variants with or without a timestamp.
+pub(crate) static PYTHON_LINES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    const TIME: &str = r"(?P<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:[,.]\d+)?)";
+    const LEVEL: &str = r"(?P<level>DEBUG|INFO|WARNING|WARN|ERROR|CRITICAL|FATAL)";
+    [
+        format!(r"^{TIME} - (?P<logger>\S+) - {LEVEL} - (?P<message>.*)$"),
+        format!(r"^{LEVEL}:(?P<logger>[^:\s]+):(?P<message>.*)$"),
+        format!(r"^\[{LEVEL}\]\s+(?:{TIME}\s+)?(?P<logger>[\w.]+):\s+(?P<message>.*)$"),
+        format!(r"^{TIME}\s+\[?{LEVEL}\]?\s+(?P<logger>[\w.]+):\s+(?P<message>.*)$"),
+    ]
+    .iter()
+    .map(|re| Regex::new(re).unwrap())
+    .collect()
+});
+
+/// Parses a Python `logging` line. The logger name goes into the `module`
+/// field and CRITICAL counts as Fatal. Lines matching no layout continue the
+/// entry above, which attaches `logging.exception` tracebacks to their error.
+pub(crate) fn parse_python_line(line: &str) -> Parsed {
+    let Some(caps) = PYTHON_LINES.iter().find_map(|re| re.captures(line)) else {
+        return Parsed::Continuation(line.to_string());
+    };
+
+    let Some(level) = LogLevel::from_name(&caps["level"]) else {
+        return Parsed::Unparsed;
+    };
+    let timestamp = caps.name("timestamp").map_or("", |m| m.as_str()).to_string();
+
+    Parsed::Entry(LogEntry {
+        line: 0,
+        datetime: parse_timestamp(&timestamp.replace(',', ".")),
+        timestamp,
+        level,
+        message: caps["message"].to_string(),
+        fields: BTreeMap::from([("module".to_string(), caps["logger"].to_string())]),
+        inherited: false,
+    })
+}
+
+/// The optional Ruby `Logger` prefix (`I, [2024-01-02T10:00:00.123 #42]  INFO -- : `)
+/// and `log_tags` brackets in front of every Rails line; the first tag keys the request.
+pub(crate) static RAILS_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:[DIWEFAU], \[(?P<time>[^\s\]]+)[^\]]*\]\s+\w+ -- [^:]*: )?(?:\[(?P<tag>[^\]]*)\] )?(?:\[[^\]]*\] )*(?P<body>.*)$",
+    )
+    .unwrap()
+});
+
+/// `Started GET "/path" for IP at TIME`, the first line of a Rails request.
+pub(crate) static RAILS_STARTED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^Started (?P<method>[A-Z]+) "(?P<path>[^"]*)" for (?P<ip>\S+) at (?P<time>.+)$"#).unwrap()
+});
+
+/// `Processing by Controller#action as FORMAT`.
+pub(crate) static RAILS_PROCESSING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Processing by (?P<action>\S+) as (?P<format>\S+)").unwrap());
+
+/// `Completed 200 OK in 12ms (...)`, the last line of a Rails request.
+pub(crate) static RAILS_COMPLETED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^Completed (?P<status>\d{3}) (?P<text>.*?) in (?P<ms>\d+(?:\.\d+)?)ms(?: \((?P<parts>.*)\))?",
+    )
+    .unwrap()
+});
+
+/// A Rails request that never logged `Completed`: a Warning, marked by the
+/// absence of a `status` field.
+pub(crate) fn unfinished_request(mut request: LogEntry) -> LogEntry {
+    let endpoint = request.fields.get("action").unwrap_or(&request.message);
+    request.message = format!("{} unfinished", endpoint);
+    request.level = LogLevel::Warning;
+    request
+}
+
+/// Keys tried, in order, for the timestamp of a JSON or logfmt record.
+pub(crate) const JSON_TIMESTAMP_KEYS: &[&str] = &["timestamp", "@timestamp", "time", "ts"];
+/// Keys tried, in order, for the level of a JSON or logfmt record.
+pub(crate) const JSON_LEVEL_KEYS: &[&str] = &["level", "severity", "lvl"];
+/// Keys tried, in order, for the message of a JSON or logfmt record.
+pub(crate) const JSON_MESSAGE_KEYS: &[&str] = &["message", "msg"];
+
+/// Parses one JSON object. The first key found among the usual names gives
+/// the timestamp (a string, or epoch seconds), level (Info when absent or
+/// unknown) and message, unless `keys` names the key for them; all other
+/// keys become fields, non-strings as JSON.
+///
+/// Records failing a `--require-fields` check, and object lines that are not
+/// valid JSON, are rejected as invalid; other lines are merely unparsed.
+pub(crate) fn parse_json_line(line: &str, required: &[FieldRequirement], keys: &[JsonKey]) -> Parsed {
+    match parse_json_record(line, required) {
+        Ok(record) => Parsed::Entry(json_entry(record, keys)),
+        Err(parsed) => parsed,
+    }
+}
+
+/// Builds an entry from a JSON object, as described for `parse_json_line`.
+pub(crate) fn json_entry(mut record: serde_json::Map<String, serde_json::Value>, keys: &[JsonKey]) -> LogEntry {
+    let mut take = |role: JsonRole, usual: &[&str]| match keys.iter().rfind(|k| k.role == role) {
+        Some(custom) => record.remove(&custom.key),
+        None => usual.iter().find_map(|key| record.remove(*key)),
+    };
+    let time = take(JsonRole::Timestamp, JSON_TIMESTAMP_KEYS);
+    let level = take(JsonRole::Level, JSON_LEVEL_KEYS);
+    let message = take(JsonRole::Message, JSON_MESSAGE_KEYS);
+
+    let (timestamp, datetime) = match time {
+        Some(serde_json::Value::String(s)) => {
+            let datetime = parse_timestamp(&s);
+            (s, datetime)
+        }
+        Some(serde_json::Value::Number(n)) => (n.to_string(), n.as_f64().and_then(epoch_seconds)),
+        _ => (String::new(), None),
+    };
+    let level = level.as_ref().and_then(|l| l.as_str()).and_then(LogLevel::from_name);
+
+    LogEntry {
+        line: 0,
+        timestamp,
+        datetime,
+        level: level.unwrap_or(LogLevel::Info),
+        message: message.map(json_text).unwrap_or_default(),
+        fields: record.into_iter().map(|(key, value)| (key, json_text(value))).collect(),
+        inherited: false,
+    }
+}
+
+/// Parses a logfmt line into an entry like a JSON record with string
+/// values: the usual keys (or those of `keys`) give the timestamp, level
+/// and message, and the other pairs become fields. A line is only taken
+/// for logfmt when all of it is pairs and one of them is such a key.
+pub(crate) fn parse_logfmt_line(line: &str, keys: &[JsonKey]) -> Option<LogEntry> {
+    let pairs = parse_logfmt_pairs(line)?;
+    let known = |key: &str| {
+        [JSON_TIMESTAMP_KEYS, JSON_LEVEL_KEYS, JSON_MESSAGE_KEYS].iter().any(|usual| usual.contains(&key))
+            || keys.iter().any(|k| k.key == key)
+    };
+    if !pairs.iter().any(|(key, _)| known(key)) {
+        return None;
+    }
+    let epoch = |key: &str, value: &str| {
+        let is_time = match keys.iter().rfind(|k| k.role == JsonRole::Timestamp) {
+            Some(custom) => custom.key == key,
+            None => JSON_TIMESTAMP_KEYS.contains(&key),
+        };
+        is_time.then(|| serde_json::Number::from_f64(value.parse().ok()?)).flatten()
+    };
+    let record = pairs
+        .into_iter()
+        .map(|(key, value)| match epoch(&key, &value) {
+            Some(seconds) => (key, serde_json::Value::Number(seconds)),
+            None => (key, serde_json::Value::String(value)),
+        })
+        .collect();
+    Some(json_entry(record, keys))
+}
+
+/// Splits a logfmt line into its pairs. Values are bare or quoted, with
+/// `\"`, `\\`, `\n` and `\t` escapes; a key without `=` is `true`. None
+/// when a token is not a pair.
+pub(crate) fn parse_logfmt_pairs(line: &str) -> Option<Vec<(String, String)>> {
+    let is_key = |key: &str| {
+        key.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_alphanumeric() || "_.-/@:".contains(c))
+    };
+    let mut pairs = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        if !is_key(key) {
+            return None;
+        }
+        rest = &rest[key_end..];
+        let value = match rest.strip_prefix('=') {
+            None => "true".to_string(),
+            Some(after) => match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let mut value = String::new();
+                    let mut chars = quoted.char_indices();
+                    let end = loop {
+                        match chars.next()? {
+                            (_, '\\') => match chars.next()? {
+                                (_, 'n') => value.push('\n'),
+                                (_, 't') => value.push('\t'),
+                                (_, c) => value.push(c),
+                            },
+                            (i, '"') => break i + 1,
+                            (_, c) => value.push(c),
+                        }
+                    };
+                    rest = &quoted[end..];
+                    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                        return None;
+                    }
+                    value
+                }
+                None => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    rest = &after[end..];
+                    after[..end].to_string()
+                }
+            },
+        };
+        pairs.push((key.to_string(), value));
+        rest = rest.trim_start();
+    }
+    Some(pairs)
+}
+
+/// Deserializes a JSON object line and applies `--require-fields`; the
+/// error is what the line should count as.
+pub(crate) fn parse_json_record(
+    line: &str,
+    required: &[FieldRequirement],
+) -> Result<serde_json::Map<String, serde_json::Value>, Parsed> {
+    let record: serde_json::Map<String, serde_json::Value> = match serde_json::from_str(line) {
+        Ok(record) => record,
+        Err(e) if line.trim_start().starts_with('{') => {
+            return Err(Parsed::Invalid(format!("invalid JSON: {}", e)));
+        }
+        Err(_) => return Err(Parsed::Unparsed),
+    };
+
+    for requirement in required {
+        let Some(value) = record.get(&requirement.name) else {
+            return Err(Parsed::Invalid(format!("missing field '{}'", requirement.name)));
+        };
+        if let Some(kind) = requirement.kind
+            && JsonType::of(value) != kind
+        {
+            return Err(Parsed::Invalid(format!(
+                "field '{}' is {}, expected {}",
+                requirement.name,
+                JsonType::of(value).name(),
+                kind.name()
+            )));
+        }
+    }
+
+    Ok(record)
+}
+
+/// Parses a Graylog GELF message. `short_message` is the message and is
+/// required; `full_message`, `host` and the `_`-prefixed additional fields
+/// (without their underscore) become fields. `level` is a syslog severity:
+/// 0-2 Fatal, 3 Error, 4 Warning, 5-6 Info, 7 Debug, Info when absent.
+pub(crate) fn parse_gelf_line(line: &str, required: &[FieldRequirement]) -> Parsed {
+    let mut record = match parse_json_record(line, required) {
+        Ok(record) => record,
+        Err(parsed) => return parsed,
+    };
+
+    let Some(message) = record.remove("short_message") else {
+        return Parsed::Invalid("missing short_message".to_string());
+    };
+    let level = match record.remove("level").map(|l| l.as_u64()) {
+        None => LogLevel::Info,
+        Some(Some(0..=2)) => LogLevel::Fatal,
+        Some(Some(3)) => LogLevel::Error,
+        Some(Some(4)) => LogLevel::Warning,
+        Some(Some(5 | 6)) => LogLevel::Info,
+        Some(Some(7)) => LogLevel::Debug,
+        Some(_) => return Parsed::Invalid("level is not a syslog severity (0-7)".to_string()),
+    };
+    let (timestamp, datetime) = match record.remove("timestamp") {
+        Some(serde_json::Value::Number(n)) => (n.to_string(), n.as_f64().and_then(epoch_seconds)),
+        Some(_) => return Parsed::Invalid("timestamp is not a number".to_string()),
+        None => (String::new(), None),
+    };
+    record.remove("version");
+
+    let fields = record
+        .into_iter()
+        .map(|(key, value)| {
+            let key = key.strip_prefix('_').map_or(key.clone(), str::to_string);
+            (key, json_text(value))
+        })
+        .collect();
+
+    Parsed::Entry(LogEntry {
+        line: 0,
+        timestamp,
+        datetime,
+        level,
+        message: json_text(message),
+        fields,
+        inherited: false,
+    })
+}
+
+/// Epoch seconds with sub-second precision kept to the microsecond.
+pub(crate) fn epoch_seconds(secs: f64) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp_micros((secs * 1_000_000.0).round() as i64).map(|dt| dt.naive_utc())
+}
+
+/// Strings without their quotes; anything else as compact JSON.
+pub(crate) fn json_text(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// Header fields of a CEF record after `CEF:Version`, stored under these names.
+pub(crate) const CEF_HEADER: &[&str] = &["vendor", "product", "device_version", "signature_id", "name", "severity"];
+
+/// Parses `[syslog header] CEF:0|Vendor|Product|Version|SignatureID|Name|Severity|ext`.
+/// The Name becomes the message; the other header fields and every extension
+/// pair become fields (header names win over extension keys). Severity 0-3
+/// or Low is Info, 4-6 or Medium Warning, 7-10, High or Very-High Error.
+///
+/// The time comes from the `rt`, `end` or `start` extension, falling back to
+/// the syslog header; a BSD syslog time has no year and gets the current one.
+pub(crate) fn parse_cef_line(line: &str) -> Option<LogEntry> {
+    let start = line.find("CEF:")?;
+    let (prefix, record) = (line[..start].trim(), &line[start + 4..]);
+
+    let mut parts = split_cef_header(record);
+    if parts.len() != 8 {
+        return None;
+    }
+    let extension = parts.pop()?;
+    let mut header = parts.into_iter().skip(1);
+
+    let mut fields = parse_cef_extension(&extension);
+    for name in CEF_HEADER {
+        fields.insert(name.to_string(), header.next()?);
+    }
+    let message = fields.remove("name")?;
+    let level = match fields["severity"].as_str() {
+        "Low" | "Unknown" => LogLevel::Info,
+        "Medium" => LogLevel::Warning,
+        "High" | "Very-High" => LogLevel::Error,
+        severity => match severity.parse::<u8>().ok()? {
+            0..=3 => LogLevel::Info,
+            4..=6 => LogLevel::Warning,
+            _ => LogLevel::Error,
+        },
+    };
+
+    let syslog = SYSLOG_PREFIX.captures(prefix);
+    if let Some(host) = syslog.as_ref().and_then(|c| c.name("host")) {
+        fields.entry("host".to_string()).or_insert_with(|| host.as_str().to_string());
+    }
+    let (timestamp, datetime) = ["rt", "end", "start"]
+        .iter()
+        .filter_map(|key| fields.get(*key))
+        .find_map(|value| Some((value.clone(), parse_cef_time(value)?)))
+        .map(|(timestamp, datetime)| (timestamp, Some(datetime)))
+        .or_else(|| {
+            let time = syslog.as_ref()?.name("time")?.as_str();
+            Some((time.to_string(), parse_syslog_time(time)))
+        })
+        .unwrap_or_default();
+
+    Some(LogEntry {
+        line: 0,
+        timestamp,
+        datetime,
+        level,
+        message,
+        fields,
+        inherited: false,
+    })
+}
+
+/// Everything of an HAProxy HTTP log line up to the captured headers and the
+/// request, which may be cut short when HAProxy truncates a long URI.
+pub(crate) static HAPROXY_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?P<client>\S+):\d+ \[(?P<accept>[^\]]+)\] (?P<frontend>\S+) (?P<backend>[^/\s]+)/(?P<server>\S+) (?P<timers>[-+\d]+(?:/[-+\d]+){4}) (?P<status>-?\d+) \+?\d+ \S+ \S+ (?P<state>\S{4}) \S+ \S+(?P<rest>.*)$",
+    )
+    .unwrap()
+});
+
+/// HAProxy's five timers in log order, stored as fields under these names.
+pub(crate) const HAPROXY_TIMERS: &[&str] = &["tq", "tw", "tc", "tr", "tt"];
+
+/// Parses an HAProxy `option httplog` line. The accept date is the
+/// timestamp and `METHOD path` (without query string) the message; the
+/// level follows the status class like Rails requests, and a status of -1
+/// (no response at all) is an Error.
+///
+/// `frontend`, `backend`, `server`, `client_ip`, `status`, `method` and
+/// `uri` become fields, as do the timers (`tq`, `tw`, `tc`, `tr`, `tt`, in
+/// ms, -1 when the step was never reached) and the first two characters of
+/// the termination state as `termination` (`--` for a normal session). The
+/// backend doubles as the request `action`, and `tr` as its `duration_ms`.
+pub(crate) fn parse_haproxy_line(line: &str) -> Option<LogEntry> {
+    let caps = HAPROXY_LINE.captures(line)?;
+    let status = &caps["status"];
+    let level = match status.as_bytes()[0] {
+        b'5' | b'-' => LogLevel::Error,
+        b'4' => LogLevel::Warning,
+        _ => LogLevel::Info,
+    };
+
+    let mut fields = BTreeMap::from([
+        ("client_ip".to_string(), caps["client"].to_string()),
+        ("frontend".to_string(), caps["frontend"].to_string()),
+        ("backend".to_string(), caps["backend"].to_string()),
+        ("action".to_string(), caps["backend"].to_string()),
+        ("server".to_string(), caps["server"].to_string()),
+        ("status".to_string(), status.to_string()),
+        ("termination".to_string(), caps["state"].chars().take(2).collect()),
+    ]);
+    for (name, value) in HAPROXY_TIMERS.iter().zip(caps["timers"].split('/')) {
+        fields.insert(name.to_string(), value.trim_start_matches('+').to_string());
+    }
+    if let Some(tr) = fields.get("tr").filter(|tr| !tr.starts_with('-')) {
+        fields.insert("duration_ms".to_string(), tr.clone());
+    }
+
+    let request = caps["rest"].split_once('"').map_or("", |(_, r)| r.trim_end_matches('"'));
+    let mut request = request.split_whitespace();
+    let message = match (request.next(), request.next()) {
+        (Some(method), Some(uri)) => {
+            fields.insert("method".to_string(), method.to_string());
+            fields.insert("uri".to_string(), uri.to_string());
+            format!("{} {}", method, uri.split('?').next().unwrap_or(uri))
+        }
+        (Some(request), None) => request.to_string(),
+        _ => "(no request)".to_string(),
+    };
+
+    let timestamp = caps["accept"].to_string();
+    Some(LogEntry {
+        line: 0,
+        datetime: NaiveDateTime::parse_from_str(&timestamp, "%d/%b/%Y:%H:%M:%S%.f").ok(),
+        timestamp,
+        level,
+        message,
+        fields,
+        inherited: false,
+    })
+}
+
+/// Syslog facility names by code (RFC 5424, section 6.2.1).
+pub(crate) const SYSLOG_FACILITIES: &[&str] = &[
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp",
+    "security", "console", "solaris-cron", "local0", "local1", "local2", "local3", "local4", "local5", "local6",
+    "local7",
+];
+
+/// Syslog severity names by code.
+pub(crate) const SYSLOG_SEVERITIES: &[&str] = &["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD [MSG]`, RFC 5424.
+pub(crate) static SYSLOG_5424: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^<(?P<pri>\d{1,3})>1 (?P<time>\S+) (?P<host>\S+) (?P<app>\S+) (?P<pid>\S+) (?P<msgid>\S+) (?P<rest>.*)$")
+        .unwrap()
+});
+
+/// `[<PRI>]Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`, RFC 3164, also with the
+/// ISO-8601 time rsyslog writes by default; the tag is optional.
+pub(crate) static SYSLOG_3164: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:<(?P<pri>\d{1,3})>)?(?P<time>[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}|\d{4}-\d{2}-\d{2}T\S+) (?P<host>\S+) (?:(?P<app>[^\s:\[\]]+)(?:\[(?P<pid>[^\]]*)\])?: )?(?P<message>.*)$",
+    )
+    .unwrap()
+});
+
+/// Parses a syslog line, RFC 5424 or RFC 3164. The level follows the
+/// severity of `<PRI>`: 0-2 Fatal, 3 Error, 4 Warning, 5-6 Info, 7 Debug; without a
+/// `<PRI>`, as in files written by syslog daemons, it is Info.
+///
+/// `facility` and `severity` (by name, e.g. `auth` and `err`), `host`,
+/// `app`, `pid` and, for RFC 5424, `msgid` become fields, leaving out nil
+/// (`-`) values. Each structured data parameter becomes a field named
+/// `SD-ID.NAME`, e.g. `origin.ip`. A BSD time has no year and gets the
+/// current one.
+pub(crate) fn parse_syslog_line(line: &str) -> Option<LogEntry> {
+    let (caps, sd, message) = match SYSLOG_5424.captures(line) {
+        Some(caps) => {
+            let (sd, message) = parse_structured_data(caps.name("rest")?.as_str())?;
+            let message = message.strip_prefix('\u{feff}').unwrap_or(message).to_string();
+            (caps, sd, message)
+        }
+        None => {
+            let caps = SYSLOG_3164.captures(line)?;
+            let message = caps["message"].to_string();
+            (caps, BTreeMap::new(), message)
+        }
+    };
+
+    let mut fields = sd;
+    let level = match caps.name("pri") {
+        Some(pri) => {
+            let pri: usize = pri.as_str().parse().ok()?;
+            let (facility, severity) = (pri / 8, pri % 8);
+            fields.insert("facility".to_string(), SYSLOG_FACILITIES.get(facility)?.to_string());
+            fields.insert("severity".to_string(), SYSLOG_SEVERITIES[severity].to_string());
+            match severity {
+                0..=2 => LogLevel::Fatal,
+                3 => LogLevel::Error,
+                4 => LogLevel::Warning,
+                5 | 6 => LogLevel::Info,
+                _ => LogLevel::Debug,
+            }
+        }
+        None => LogLevel::Info,
+    };
+    for name in ["host", "app", "pid", "msgid"] {
+        if let Some(value) = caps.name(name).filter(|value| value.as_str() != "-") {
+            fields.insert(name.to_string(), value.as_str().to_string());
+        }
+    }
+
+    let time = &caps["time"];
+    let (timestamp, datetime) = match time {
+        "-" => (String::new(), None),
+        time => (time.to_string(), parse_syslog_time(time)),
+    };
+    Some(LogEntry {
+        line: 0,
+        timestamp,
+        datetime,
+        level,
+        message,
+        fields,
+        inherited: false,
+    })
+}
+
+/// Splits RFC 5424 structured data, `-` or `[id name="value" ...]...`, off
+/// the front of `rest`, returning its parameters as `id.name` fields (with
+/// `\"`, `\\` and `\]` unescaped) and the message after it. None when it
+/// is malformed.
+pub(crate) fn parse_structured_data(rest: &str) -> Option<(BTreeMap<String, String>, &str)> {
+    let mut fields = BTreeMap::new();
+    let mut rest = match rest.strip_prefix('-') {
+        Some(after) if after.is_empty() || after.starts_with(' ') => after,
+        _ if rest.starts_with('[') => rest,
+        _ => return None,
+    };
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = element.find([' ', ']'])?;
+        let id = &element[..id_end];
+        rest = &element[id_end..];
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if let Some(after) = rest.strip_prefix(']') {
+                rest = after;
+                break;
+            }
+            let (name, after) = rest.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = after.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => match chars.next()? {
+                        (_, next @ ('"' | '\\' | ']')) => value.push(next),
+                        (_, next) => {
+                            value.push('\\');
+                            value.push(next);
+                        }
+                    },
+                    (i, '"') => break i + 1,
+                    (_, c) => value.push(c),
+                }
+            };
+            fields.insert(format!("{}.{}", id, name), value);
+            rest = &after[end..];
+        }
+    }
+    Some((fields, rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+/// `client ident user [time] "request" status bytes`, the Common Log Format,
+/// optionally followed by the Combined format's `"referer" "user agent"`
+/// and whatever else the server is configured to add.
+pub(crate) static ACCESS_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^(?P<client>\S+) \S+ (?P<user>\S+) \[(?P<time>[^\]]+)\] "(?P<request>(?:[^"\\]|\\.)*)" (?P<status>\d{3}|-) (?P<bytes>\d+|-)(?: "(?P<referer>(?:[^"\\]|\\.)*)" "(?P<user_agent>(?:[^"\\]|\\.)*)")?"#,
+    )
+    .unwrap()
+});
+
+/// Parses an Apache or nginx access log line in the Common or Combined Log
+/// Format. `METHOD path` (query string dropped) is the message and the
+/// request `action`; the level follows the status class like W3C logs.
+///
+/// `client_ip`, `user`, `method`, `uri`, `protocol`, `status`, `bytes`,
+/// `referer` and `user_agent` become fields, leaving out `-` values. A
+/// decimal number ending the line, as nginx's `$request_time` in seconds,
+/// becomes `duration_ms`.
+pub(crate) fn parse_access_line(line: &str) -> Option<LogEntry> {
+    let caps = ACCESS_LINE.captures(line)?;
+    let mut fields = BTreeMap::new();
+    let seconds = line[caps.get(0)?.end()..].split_whitespace().last().filter(|last| last.contains('.'));
+    if let Some(seconds) = seconds.and_then(|seconds| seconds.parse::<f64>().ok()) {
+        fields.insert("duration_ms".to_string(), round1(seconds * 1000.0).to_string());
+    }
+    for name in ["client", "user", "status", "bytes", "referer", "user_agent"] {
+        if let Some(value) = caps.name(name).filter(|value| !matches!(value.as_str(), "-" | "")) {
+            let name = if name == "client" { "client_ip" } else { name };
+            fields.insert(name.to_string(), value.as_str().to_string());
+        }
+    }
+
+    let request = &caps["request"];
+    let message = match request.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, uri, ref protocol @ ..] => {
+            fields.insert("method".to_string(), method.to_string());
+            fields.insert("uri".to_string(), uri.to_string());
+            if let [protocol] = protocol {
+                fields.insert("protocol".to_string(), protocol.to_string());
+            }
+            format!("{} {}", method, uri.split('?').next().unwrap_or(uri))
+        }
+        _ if request.is_empty() || request == "-" => "(no request)".to_string(),
+        _ => request.to_string(),
+    };
+    fields.insert("action".to_string(), message.clone());
+    let level = match caps["status"].as_bytes()[0] {
+        b'5' => LogLevel::Error,
+        b'4' => LogLevel::Warning,
+        _ => LogLevel::Info,
+    };
+
+    let timestamp = caps["time"].to_string();
+    Some(LogEntry {
+        line: 0,
+        datetime: DateTime::parse_from_str(&timestamp, "%d/%b/%Y:%H:%M:%S %z").ok().map(|dt| dt.naive_utc()),
+        timestamp,
+        level,
+        message,
+        fields,
+        inherited: false,
+    })
+}
+
+/// Splits on unescaped `|` into at most 8 parts (version through extension),
+/// resolving `\|` and `\\` in the header. The extension keeps its escapes.
+pub(crate) fn split_cef_header(record: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = record.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if parts.len() == 8 {
+            parts[7] = record[i..].to_string();
+            break;
+        }
+        let current = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some((_, next @ ('|' | '\\'))) => current.push(next),
+                Some((_, next)) => {
+                    current.push('\\');
+                    current.push(next);
+                }
+                None => current.push('\\'),
+            },
+            '|' => parts.push(String::new()),
+            c => current.push(c),
+        }
+    }
+
+    parts
+}
+
+/// Splits `key=value key2=value with spaces` on unescaped `=`: each key is the
+/// word right before its `=`, and values run up to the next key. Values are
+/// unescaped (`\=`, `\\`, `\n`, `\r`).
+pub(crate) fn parse_cef_extension(extension: &str) -> BTreeMap<String, String> {
+    let bytes = extension.as_bytes();
+    let separators: Vec<usize> = (0..bytes.len())
+        .filter(|&i| bytes[i] == b'=' && is_unescaped(bytes, i))
+        .collect();
+
+    let key_start = |eq: usize| extension[..eq].rfind(' ').map_or(0, |space| space + 1);
+    let mut fields = BTreeMap::new();
+    for (n, &eq) in separators.iter().enumerate() {
+        let key = &extension[key_start(eq)..eq];
+        let value_end = separators.get(n + 1).map_or(extension.len(), |&next| key_start(next));
+        if key.is_empty() || value_end <= eq {
+            continue;
+        }
+        fields.insert(key.to_string(), unescape_cef_value(extension[eq + 1..value_end].trim()));
+    }
+
+    fields
+}
+
+/// Whether the character at `i` is preceded by an even number of backslashes.
+pub(crate) fn is_unescaped(bytes: &[u8], i: usize) -> bool {
+    bytes[..i].iter().rev().take_while(|&&b| b == b'\\').count() % 2 == 0
+}
+
+/// Undoes the escapes of a CEF extension value: `\n` and `\r` are line
+/// breaks, and a backslash before any other character (`\=`, `\\`)
+/// stands for that character.
+pub(crate) fn unescape_cef_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(next) => out.push(next),
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+/// CEF times are epoch milliseconds or `MMM dd yyyy HH:mm:ss[.SSS]`.
+pub(crate) fn parse_cef_time(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(millis) = value.parse::<i64>() {
+        return DateTime::from_timestamp_millis(millis).map(|dt| dt.naive_utc());
+    }
+    NaiveDateTime::parse_from_str(value, "%b %d %Y %H:%M:%S%.f").ok()
+}
+
+/// `<PRI>` followed by an RFC 5424 (`1 2024-01-02T10:00:00Z host`) or BSD
+/// (`Jan  2 10:00:00 host`) header.
+pub(crate) static SYSLOG_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:<\d{1,3}>)?(?:\d\s+)?(?P<time>\d{4}-\d{2}-\d{2}T\S+|[A-Z][a-z]{2}\s+\d{1,2} \d{2}:\d{2}:\d{2})(?:\s+(?P<host>\S+))?",
+    )
+    .unwrap()
+});
+
+/// ISO-8601 syslog times parse as usual; BSD ones lack a year, see
+/// `bsd_syslog_time`.
+pub(crate) fn parse_syslog_time(time: &str) -> Option<NaiveDateTime> {
+    parse_timestamp(time).or_else(|| bsd_syslog_time(time, Utc::now().naive_utc()))
+}
+
+/// A BSD time such as `Feb 29 10:00:00` in the latest year that puts it at
+/// most a day after `now`: December lines read in January belong to the
+/// previous year, and a leap day to the last leap year.
+fn bsd_syslog_time(time: &str, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let time = time.split_whitespace().collect::<Vec<_>>().join(" ");
+    let latest = now + TimeDelta::days(1);
+    // Leap years can be eight years apart (2096 and 2104).
+    (latest.year() - 8..=latest.year())
+        .rev()
+        .filter_map(|year| NaiveDateTime::parse_from_str(&format!("{} {}", year, time), "%Y %b %d %H:%M:%S").ok())
+        .find(|datetime| *datetime <= latest)
+}
+
+/// Parses `timestamp` with a `--time-format` layout. A time with an offset
+/// (`%z`) is converted to UTC; one without is taken as local time in `tz`,
+/// or as UTC.
+pub(crate) fn parse_time_with(timestamp: &str, format: &str, tz: Option<Tz>) -> Option<NaiveDateTime> {
+    if let Ok(datetime) = DateTime::parse_from_str(timestamp, format) {
+        return Some(datetime.naive_utc());
+    }
+    let datetime = NaiveDateTime::parse_from_str(timestamp, format).ok()?;
+    Some(tz.map_or(datetime, |tz| local_to_utc(datetime, tz)))
+}
+
+/// Whether a timestamp says which UTC time it is: it ends with `Z` or an
+/// offset such as `+02:00` or `-0700`, or is an epoch number.
+pub(crate) fn has_utc_offset(timestamp: &str) -> bool {
+    static OFFSET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:Z|[+-]\d{2}:?\d{2})$").unwrap());
+    let timestamp = timestamp.trim();
+    OFFSET.is_match(timestamp) || timestamp.parse::<f64>().is_ok()
+}
+
+/// The UTC time of `local`, a wall-clock time in `tz`. A time repeated when
+/// the clocks go back is taken as the first one; one skipped when they go
+/// forward is read with the offset from before the change.
+pub(crate) fn local_to_utc(local: NaiveDateTime, tz: Tz) -> NaiveDateTime {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(datetime) | LocalResult::Ambiguous(datetime, _) => datetime.naive_utc(),
+        LocalResult::None => {
+            let before = tz.offset_from_utc_datetime(&(local - TimeDelta::days(1))).fix();
+            local - TimeDelta::seconds(before.local_minus_utc().into())
+        }
+    }
+}
+
+/// Parses `YYYY-MM-DD HH:MM:SS` as well as the ISO-8601 / RFC3339 forms
+/// (`T` separator, fractional seconds, `Z` or `+HH:MM` offset). Timestamps
+/// carrying an offset are converted to UTC; naive ones are taken as UTC.
+pub fn parse_timestamp(s: &str) -> Option<NaiveDateTime> {
+    let (date, time) = s.split_once(|c: char| c == 'T' || c.is_whitespace())?;
+    let time = time.trim_start();
+    let normalized = format!("{}T{}", date, time.strip_suffix('Z').unwrap_or(time));
+
+    if let Ok(dt) = DateTime::parse_from_str(&normalized, "%Y-%m-%dT%H:%M:%S%.f%z") {
+        return Some(dt.naive_utc());
+    }
+
+    NaiveDateTime::parse_from_str(&normalized, "%Y-%m-%dT%H:%M:%S%.f").ok()
+}
+
+
+
+/* =========================
+   Parse cache
+   ========================= */
+
+/// Layout version of `--cache` files. Bump it whenever `CacheFile`,
+/// `CachedEntry` or a parser's output changes, so stale caches are re-parsed
+/// instead of misread.
+pub(crate) const CACHE_VERSION: u32 = 3;
+
+/// A `--cache` file: gzipped JSON holding the parsed entries of one input,
+/// valid while the input keeps its size and modification time and is read
+/// with the same parse settings. JSON rather than bincode because the crate
+/// already depends on `serde_json` and `flate2`; compressed, it stays small,
+/// and loading it is still far cheaper than running the parsers again.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CacheFile {
+    /// `CACHE_VERSION` of the program that wrote the file.
+    pub version: u32,
+    /// Size of the input in bytes.
+    pub source_size: u64,
+    /// Modification time of the input as (seconds, nanoseconds) since the epoch.
+    pub source_mtime: (u64, u32),
+    /// Options that shape the entries (input format, pattern, ...), see `parse_settings`.
+    pub settings: String,
+    /// The parsed entries, in input order.
+    pub entries: Vec<CachedEntry>,
+    /// As `ParsedLog::unparsed_lines`.
+    pub unparsed_lines: usize,
+    /// As `ParsedLog::invalid_records`.
+    pub invalid_records: usize,
+    /// Renamed from `invalid_samples` (a list of strings) in version 3, so
+    /// older caches still load and are then found stale.
+    #[serde(default, rename = "invalid_sample_lines")]
+    pub invalid_samples: Vec<(usize, String)>,
+    /// As `ParsedLog::truncated_lines`.
+    pub truncated_lines: usize,
+    /// Missing from version 1 caches, which are stale anyway.
+    #[serde(default)]
+    pub lines: usize,
+}
+
+/// `LogEntry` with its parsed timestamp, which the output form skips.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedEntry {
+    /// As `LogEntry::line`.
+    pub line: usize,
+    /// As `LogEntry::timestamp`.
+    pub timestamp: String,
+    /// Nanoseconds since the epoch.
+    pub datetime: Option<i64>,
+    /// As `LogEntry::level`.
+    pub level: LogLevel,
+    /// As `LogEntry::message`.
+    pub message: String,
+    /// As `LogEntry::fields`.
+    pub fields: BTreeMap<String, String>,
+    /// As `LogEntry::inherited`.
+    pub inherited: bool,
+}
+
+impl From<&LogEntry> for CachedEntry {
+    fn from(entry: &LogEntry) -> Self {
+        CachedEntry {
+            line: entry.line,
+            timestamp: entry.timestamp.clone(),
+            datetime: entry.datetime.and_then(|dt| dt.and_utc().timestamp_nanos_opt()),
+            level: entry.level.clone(),
+            message: entry.message.clone(),
+            fields: entry.fields.clone(),
+            inherited: entry.inherited,
+        }
+    }
+}
+
+impl From<CachedEntry> for LogEntry {
+    fn from(entry: CachedEntry) -> Self {
+        LogEntry {
+            line: entry.line,
+            timestamp: entry.timestamp,
+            datetime: entry.datetime.map(|nanos| DateTime::from_timestamp_nanos(nanos).naive_utc()),
+            level: entry.level,
+            message: entry.message,
+            fields: entry.fields,
+            inherited: entry.inherited,
+        }
+    }
+}
+
+/// Size and modification time of `path`, the cache's validity key.
+pub(crate) fn source_stamp(path: &std::path::Path) -> std::io::Result<(u64, (u64, u32))> {
+    let meta = std::fs::metadata(path)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok((meta.len(), (mtime.as_secs(), mtime.subsec_nanos())))
+}
+
+/// Why a cache could not be used.
+pub enum CacheMiss {
+    /// No cache file yet.
+    Missing,
+    /// Written for another version, input or parse settings.
+    Stale,
+    /// The file exists but cannot be read, with the reason.
+    Unreadable(String),
+}
+
+/// The parsed log stored at `cache` for `source`, if it was written for the
+/// input's current size and mtime with the same `settings`.
+pub fn load_cache(cache: &std::path::Path, source: &std::path::Path, settings: &str) -> Result<ParsedLog, CacheMiss> {
+    let file = match File::open(cache) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(CacheMiss::Missing),
+        Err(e) => return Err(CacheMiss::Unreadable(e.to_string())),
+    };
+    let cached: CacheFile = serde_json::from_reader(BufReader::new(MultiGzDecoder::new(file)))
+        .map_err(|e| CacheMiss::Unreadable(e.to_string()))?;
+    let (size, mtime) = source_stamp(source).map_err(|e| CacheMiss::Unreadable(e.to_string()))?;
+    if cached.version != CACHE_VERSION
+        || cached.source_size != size
+        || cached.source_mtime != mtime
+        || cached.settings != settings
+    {
+        return Err(CacheMiss::Stale);
+    }
+    Ok(ParsedLog {
+        entries: cached.entries.into_iter().map(LogEntry::from).collect(),
+        unparsed_lines: cached.unparsed_lines,
+        invalid_records: cached.invalid_records,
+        invalid_samples: cached.invalid_samples,
+        truncated_lines: cached.truncated_lines,
+        lines: cached.lines,
+    })
+}
+
+/// Writes `log` to `cache` through a temporary file, so an interrupted run
+/// never leaves a half-written cache behind.
+pub fn save_cache(
+    cache: &std::path::Path,
+    source: &std::path::Path,
+    settings: &str,
+    log: &ParsedLog,
+) -> std::io::Result<()> {
+    let (source_size, source_mtime) = source_stamp(source)?;
+    let cached = CacheFile {
+        version: CACHE_VERSION,
+        source_size,
+        source_mtime,
+        settings: settings.to_string(),
+        entries: log.entries.iter().map(CachedEntry::from).collect(),
+        unparsed_lines: log.unparsed_lines,
+        invalid_records: log.invalid_records,
+        invalid_samples: log.invalid_samples.clone(),
+        truncated_lines: log.truncated_lines,
+        lines: log.lines,
+    };
+    let mut tmp = cache.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut out = GzEncoder::new(BufWriter::new(File::create(&tmp)?), Compression::fast());
+    serde_json::to_writer(&mut out, &cached)?;
+    out.finish()?.flush()?;
+    std::fs::rename(&tmp, cache)
+}
+
+
+
+/* =========================
+   Ordering
+   ========================= */
+
+/// Counts entries whose timestamp is earlier than one already seen.
+pub fn count_out_of_order(entries: &[LogEntry]) -> usize {
+    let mut latest: Option<NaiveDateTime> = None;
+    let mut count = 0;
+
+    for dt in entries.iter().filter_map(|e| e.datetime) {
+        match latest {
+            Some(max) if dt < max => count += 1,
+            _ => latest = Some(dt),
+        }
+    }
+
+    count
+}
+
+/// Stable sort by timestamp. Entries without a parsed timestamp keep their
+/// slot; only the timestamped entries are permuted among themselves.
+pub fn sort_by_time(entries: &mut [LogEntry]) {
+    let slots: Vec<usize> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.datetime.is_some())
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut dated: Vec<LogEntry> = slots.iter().map(|&i| entries[i].clone()).collect();
+    dated.sort_by_key(|e| e.datetime);
+
+    for (slot, entry) in slots.into_iter().zip(dated) {
+        entries[slot] = entry;
+    }
+}
+
+/// Bounded reordering for nearly-sorted input: buffers at most `window`
+/// entries and always releases the earliest one. Entries without a parsed
+/// timestamp inherit the key of the previous timestamped entry, so they stay
+/// right behind it.
+pub struct ReorderWindow<I: Iterator<Item = LogEntry>> {
+    inner: I,
+    buffer: ReorderBuffer,
+}
+
+/// The buffer of `ReorderWindow`, for entries that are pushed to it one at
+/// a time, as `--stream` and `--follow` read them.
+#[derive(Clone)]
+pub struct ReorderBuffer {
+    window: usize,
+    heap: BinaryHeap<Reverse<Pending>>,
+    seq: usize,
+    last_key: Option<NaiveDateTime>,
+}
+
+/// A buffered entry, ordered by timestamp key then arrival order.
+#[derive(Clone)]
+struct Pending {
+    key: Option<NaiveDateTime>,
+    seq: usize,
+    entry: LogEntry,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.key, self.seq) == (other.key, other.seq)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.key, self.seq).cmp(&(other.key, other.seq))
+    }
+}
+
+impl ReorderBuffer {
+    /// Holds at most `window` entries (at least one).
+    pub fn new(window: usize) -> Self {
+        ReorderBuffer {
+            window: window.max(1),
+            heap: BinaryHeap::new(),
+            seq: 0,
+            last_key: None,
+        }
+    }
+
+    /// Buffers `entry` and, once the buffer is full, releases the earliest
+    /// entry it holds.
+    pub fn push(&mut self, entry: LogEntry) -> Option<LogEntry> {
+        if entry.datetime.is_some() {
+            self.last_key = entry.datetime;
+        }
+        self.heap.push(Reverse(Pending {
+            key: self.last_key,
+            seq: self.seq,
+            entry,
+        }));
+        self.seq += 1;
+
+        if self.heap.len() >= self.window { self.pop() } else { None }
+    }
+
+    /// Releases the earliest entry left, for draining the buffer at the end.
+    pub fn pop(&mut self) -> Option<LogEntry> {
+        self.heap.pop().map(|Reverse(pending)| pending.entry)
+    }
+}
+
+impl<I: Iterator<Item = LogEntry>> ReorderWindow<I> {
+    /// Reorders `inner` with a buffer of `window` entries (at least one).
+    pub fn new(inner: I, window: usize) -> Self {
+        ReorderWindow { inner, buffer: ReorderBuffer::new(window) }
+    }
+}
+
+impl<I: Iterator<Item = LogEntry>> Iterator for ReorderWindow<I> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        for entry in self.inner.by_ref() {
+            if let Some(earliest) = self.buffer.push(entry) {
+                return Some(earliest);
+            }
+        }
+        self.buffer.pop()
+    }
+}
+
+
+
+/* =========================
+   Deduplication
+   ========================= */
+
+/// Compact 64-bit digest of the fields that make two entries identical.
+pub(crate) fn entry_digest(entry: &LogEntry) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    entry.timestamp.hash(&mut hasher);
+    format!("{:?}", entry.level).hash(&mut hasher);
+    entry.message.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Entries left by `dedupe` and what it dropped.
+pub struct DedupeResult {
+    /// The entries kept, in input order.
+    pub entries: Vec<LogEntry>,
+    /// Duplicates dropped.
+    pub removed: usize,
+    /// Whether the digest set filled up, so later duplicates may remain.
+    pub truncated: bool,
+}
+
+/// Drops exact duplicates, keeping the first occurrence. Only digests are
+/// stored, and at most `max_digests` of them: once full, new entries are no
+/// longer remembered and `truncated` is set. With a `window`, digests older
+/// than the window are forgotten, which keeps the set small for the common
+/// case of a collector shipping the same line twice.
+pub fn dedupe(entries: Vec<LogEntry>, window: Option<TimeDelta>, max_digests: usize) -> DedupeResult {
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut recent: VecDeque<(NaiveDateTime, u64)> = VecDeque::new();
+    let mut kept = Vec::with_capacity(entries.len());
+    let mut removed = 0;
+    let mut truncated = false;
+
+    for entry in entries {
+        let digest = entry_digest(&entry);
+
+        if let (Some(window), Some(dt)) = (window, entry.datetime) {
+            while let Some(&(oldest, old_digest)) = recent.front() {
+                if dt - oldest <= window {
+                    break;
+                }
+                seen.remove(&old_digest);
+                recent.pop_front();
+            }
+        }
+
+        if seen.contains(&digest) {
+            removed += 1;
+            continue;
+        }
+
+        if seen.len() < max_digests {
+            seen.insert(digest);
+            if let (Some(_), Some(dt)) = (window, entry.datetime) {
+                recent.push_back((dt, digest));
+            }
+        } else {
+            truncated = true;
+        }
+        kept.push(entry);
+    }
+
+    DedupeResult {
+        entries: kept,
+        removed,
+        truncated,
+    }
+}
+
+
+
+/* =========================
+   Timestamp quality
+   ========================= */
+
+/// A step between the timestamps of consecutive entries.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeJump {
+    /// Line of the earlier entry.
+    pub from_line: usize,
+    /// Timestamp of the earlier entry, as written.
+    pub from: String,
+    /// Line of the later entry.
+    pub to_line: usize,
+    /// Timestamp of the later entry, as written.
+    pub to: String,
+    /// Length of the step; negative when time goes backwards.
+    pub seconds: i64,
+}
+
+/// How trustworthy the input was: lines that did not parse, entries with
+/// borrowed or duplicate timestamps, and steps back or far forward in time.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QualityReport {
+    /// Lines that produced no entry, invalid records included.
+    pub unparsed_lines: usize,
+    /// Unparsed lines kept as `--unparsed-level` entries and counted in the total.
+    pub unparsed_entries: usize,
+    /// Records in the expected format that were rejected.
+    pub invalid_records: usize,
+    /// Lines cut at `--max-line-bytes`.
+    pub truncated_lines: usize,
+    /// Entries whose timestamp came from the previous entry.
+    pub inherited_entries: usize,
+    /// Entries dropped by `--dedupe`.
+    pub duplicates_removed: usize,
+    /// Times an entry is earlier than the one before it.
+    pub backward_steps: usize,
+    /// The biggest of those steps.
+    pub largest_backward_jump: Option<TimeJump>,
+    /// Steps forward longer than the skew threshold.
+    pub forward_jumps: Vec<TimeJump>,
+}
+
+impl QualityReport {
+    /// Adds the time checks of entries checked on their own, such as
+    /// another input file. No step is counted between the two.
+    pub fn add_time_checks(&mut self, other: QualityReport) {
+        self.backward_steps += other.backward_steps;
+        if let Some(jump) = other.largest_backward_jump
+            && self.largest_backward_jump.as_ref().is_none_or(|j| jump.seconds < j.seconds)
+        {
+            self.largest_backward_jump = Some(jump);
+        }
+        self.forward_jumps.extend(other.forward_jumps);
+    }
+}
+
+/// Single pass over consecutive timestamped entries, in file order.
+pub fn check_time_quality(entries: &[LogEntry], skew_threshold: TimeDelta) -> QualityReport {
+    let mut quality = TimeQuality::new(skew_threshold);
+    entries.iter().for_each(|entry| quality.add(entry));
+    quality.finish()
+}
+
+/// `check_time_quality` one entry at a time; only the first and the
+/// previous timestamp are kept.
+#[derive(Clone)]
+pub struct TimeQuality {
+    report: QualityReport,
+    skew_threshold: TimeDelta,
+    /// Line, timestamp text and time of the first timestamped entry.
+    first: Option<(usize, String, NaiveDateTime)>,
+    /// The same for the last one so far.
+    previous: Option<(usize, String, NaiveDateTime)>,
+}
+
+impl TimeQuality {
+    /// Counts forward steps longer than `skew_threshold` as jumps.
+    pub fn new(skew_threshold: TimeDelta) -> Self {
+        TimeQuality { report: QualityReport::default(), skew_threshold, first: None, previous: None }
+    }
+
+    /// Checks `entry` against the previous timestamped entry; entries without
+    /// a timestamp are skipped.
+    pub fn add(&mut self, entry: &LogEntry) {
+        if let Some(dt) = entry.datetime {
+            self.add_time(entry.line, &entry.timestamp, dt);
+        }
+    }
+
+    /// `add` for a timestamp read without building an entry.
+    pub fn add_time(&mut self, line: usize, timestamp: &str, dt: NaiveDateTime) {
+        if let Some((prev_line, prev, prev_dt)) = &self.previous {
+            let step = dt - *prev_dt;
+            let jump = || TimeJump {
+                from_line: *prev_line,
+                from: prev.clone(),
+                to_line: line,
+                to: timestamp.to_string(),
+                seconds: step.num_seconds(),
+            };
+
+            if step < TimeDelta::zero() {
+                self.report.backward_steps += 1;
+                let largest = self
+                    .report
+                    .largest_backward_jump
+                    .as_ref()
+                    .is_none_or(|j| step.num_seconds() < j.seconds);
+                if largest {
+                    self.report.largest_backward_jump = Some(jump());
+                }
+            } else if step > self.skew_threshold {
+                self.report.forward_jumps.push(jump());
+            }
+        }
+
+        if self.first.is_none() {
+            self.first = Some((line, timestamp.to_string(), dt));
+        }
+        self.previous = Some((line, timestamp.to_string(), dt));
+    }
+
+    /// Continues with the report of the input that follows this one, whose
+    /// line numbers restart after `line_offset` lines. The result is the
+    /// same as adding all the entries to one `TimeQuality`.
+    pub fn merge(&mut self, next: TimeQuality, line_offset: usize) {
+        let Some((line, timestamp, dt)) = next.first else { return };
+        self.add_time(line + line_offset, &timestamp, dt);
+
+        let mut report = next.report;
+        for jump in report.forward_jumps.iter_mut().chain(report.largest_backward_jump.as_mut()) {
+            jump.from_line += line_offset;
+            jump.to_line += line_offset;
+        }
+        self.report.add_time_checks(report);
+        self.previous = next.previous.map(|(line, timestamp, dt)| (line + line_offset, timestamp, dt));
+    }
+
+    /// The report on the entries added so far.
+    pub fn finish(self) -> QualityReport {
+        self.report
+    }
+}
+
+
+
+/// How often one message (or template) occurred.
+#[derive(Debug, Serialize)]
+pub struct ErrorFrequency {
+    /// The message, or its template when messages are normalized.
+    pub message: String,
+    /// Occurrences counted.
+    pub count: usize,
+    /// Timestamp of the earliest occurrence, as written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    /// Timestamp of the latest occurrence, as written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    /// In approximate mode, how much `count` may overestimate the true count.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_overcount: Option<usize>,
+    /// Percentage of all error entries, for top errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+}
+
+/// The span between the earliest and the latest timestamp.
+#[derive(Debug, Serialize)]
+pub struct TimeRange {
+    /// Earliest timestamp, as written.
+    pub start: String,
+    /// Latest timestamp, as written.
+    pub end: String,
+    /// Length of the span.
+    pub seconds: i64,
+    /// Earliest timestamp, parsed.
+    #[serde(skip)]
+    pub first: NaiveDateTime,
+    /// Latest timestamp, parsed.
+    #[serde(skip)]
+    pub last: NaiveDateTime,
+}
+
+/// One value of a `--count-group` capture.
+#[derive(Debug, Serialize)]
+pub struct ValueCount {
+    /// The captured value.
+    pub value: String,
+    /// Entries with that value.
+    pub count: usize,
+    /// With `--count-distinct`, different messages among those entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distinct_messages: Option<usize>,
+}
+
+/// The values of the `--count-group` capture, most frequent first.
+#[derive(Debug, Serialize)]
+pub struct GroupCounts {
+    /// Name of the capture.
+    pub group: String,
+    /// Counts per value.
+    pub counts: Vec<ValueCount>,
+}
+
+/// Errors attributed to one team of an owners file.
+#[derive(Debug, Serialize)]
+pub struct OwnerSummary {
+    /// The team, or `UNASSIGNED_OWNER`.
+    pub owner: String,
+    /// Error entries the team owns.
+    pub errors: usize,
+    /// The team's most frequent template.
+    pub top_message: Option<String>,
+    /// For the unassigned bucket, its biggest templates.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ErrorFrequency>,
+}
+
+/// What `analyze_logs` reports, plus the optional sections the CLI fills in
+/// from its own options.
+#[derive(Debug, Serialize)]
+pub struct LogStats {
+    /// Entries analyzed.
+    pub total_entries: usize,
+    /// Entries per level name (`{:?}` of `LogLevel`).
+    pub by_level: HashMap<String, usize>,
+    /// Most frequent error messages, most frequent first.
+    pub top_errors: Vec<ErrorFrequency>,
+    /// Whether `top_errors` was counted with Space-Saving.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub top_errors_approximate: bool,
+    /// Span of the timestamped entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_range: Option<TimeRange>,
+    /// Parse failures and timestamp problems.
+    pub quality: QualityReport,
+    /// `--health` score.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthScore>,
+    /// `--count-group` distribution.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_counts: Option<GroupCounts>,
+    /// `--owners` attribution.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owners: Option<Vec<OwnerSummary>>,
+    /// `--error-context` groups.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_contexts: Option<Vec<ErrorContext>>,
+    /// Slow query templates of a MySQL slow log or PostgreSQL log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_queries: Option<Vec<QueryPattern>>,
+    /// Request latencies of a Rails, access, HAProxy or W3C log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests: Option<RequestReport>,
+    /// Status and client breakdown of an access, HAProxy or W3C log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpReport>,
+    /// `--alert-pattern` results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alerts: Option<Vec<Alert>>,
+    /// `--correlated-within` pairs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlated_pairs: Option<Vec<CorrelatedPair>>,
+    /// `--top-by-level-sections` tables.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_by_level: Option<Vec<LevelTop>>,
+    /// `--sessionize` report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<SessionReport>,
+    /// `--detect-retry-storms` findings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_storms: Option<Vec<RetryStorm>>,
+    /// `--peak-report` busiest windows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peaks: Option<PeakReport>,
+    /// `--per-file` level counts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileSummary>>,
+}
+
+/// Earliest and latest timestamped entry seen so far.
+#[derive(Default)]
+pub(crate) struct Span<'a> {
+    first: Option<&'a LogEntry>,
+    last: Option<&'a LogEntry>,
+}
+
+impl<'a> Span<'a> {
+    /// Takes `entry` into account if it has a timestamp.
+    pub fn add(&mut self, entry: &'a LogEntry) {
+        let Some(dt) = entry.datetime else { return };
+        if self.first.is_none_or(|f| f.datetime.is_some_and(|f| dt < f)) {
+            self.first = Some(entry);
+        }
+        if self.last.is_none_or(|l| l.datetime.is_some_and(|l| dt >= l)) {
+            self.last = Some(entry);
+        }
+    }
+}
+
+/// Space-Saving heavy hitters: at most `capacity` counters. A new message
+/// arriving when full replaces the smallest counter and inherits its count,
+/// which is recorded as the possible overcount. Any message whose true
+/// count exceeds total/capacity is guaranteed to be tracked.
+#[derive(Clone)]
+pub(crate) struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<String, (usize, usize)>,
+    by_count: BTreeSet<(usize, String)>,
+}
+
+impl SpaceSaving {
+    /// Tracks at most `capacity` messages (at least one).
+    pub fn new(capacity: usize) -> Self {
+        SpaceSaving {
+            capacity: capacity.max(1),
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    /// Seeds the summary from exact counts, keeping the largest ones.
+    pub fn from_exact<'a>(capacity: usize, counts: impl IntoIterator<Item = (&'a str, usize)>) -> Self {
+        let mut summary = SpaceSaving::new(capacity);
+        let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        for (message, count) in counts.into_iter().take(summary.capacity) {
+            summary.counters.insert(message.to_string(), (count, 0));
+            summary.by_count.insert((count, message.to_string()));
+        }
+
+        summary
+    }
+
+    /// Combines the summaries of two parts of the input. A message missing
+    /// from a full summary may have occurred there up to that summary's
+    /// smallest count, which is added to both its count and its overcount.
+    /// The `capacity` largest counters are kept.
+    pub fn merge(self, other: SpaceSaving) -> SpaceSaving {
+        let floor = |summary: &SpaceSaving| match summary.by_count.first() {
+            Some((min, _)) if summary.counters.len() >= summary.capacity => *min,
+            _ => 0,
+        };
+        let (floor_self, floor_other) = (floor(&self), floor(&other));
+        let mut merged: Vec<(String, (usize, usize))> = Vec::new();
+        for (message, &(count, over)) in &self.counters {
+            let (other_count, other_over) = other.counters.get(message).copied().unwrap_or((floor_other, floor_other));
+            merged.push((message.clone(), (count + other_count, over + other_over)));
+        }
+        for (message, &(count, over)) in &other.counters {
+            if !self.counters.contains_key(message) {
+                merged.push((message.clone(), (count + floor_self, over + floor_self)));
+            }
+        }
+        merged.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| a.0.cmp(&b.0)));
+
+        let mut summary = SpaceSaving::new(self.capacity);
+        for (message, (count, over)) in merged.into_iter().take(summary.capacity) {
+            summary.by_count.insert((count, message.clone()));
+            summary.counters.insert(message, (count, over));
+        }
+        summary
+    }
+
+    /// Counts one occurrence of `message`.
+    pub fn add(&mut self, message: &str) {
+        if let Some((count, _)) = self.counters.get_mut(message) {
+            self.by_count.remove(&(*count, message.to_string()));
+            *count += 1;
+            self.by_count.insert((*count, message.to_string()));
+            return;
+        }
+
+        let floor = if self.counters.len() < self.capacity {
+            0
+        } else {
+            let (min, evicted) = self.by_count.pop_first().unwrap_or_default();
+            self.counters.remove(&evicted);
+            min
+        };
+
+        self.counters.insert(message.to_string(), (floor + 1, floor));
+        self.by_count.insert((floor + 1, message.to_string()));
+    }
+
+    /// The `n` highest counters, each with its possible overcount.
+    pub fn top(self, n: usize) -> Vec<ErrorFrequency> {
+        let mut top: Vec<ErrorFrequency> = self
+            .counters
+            .into_iter()
+            .map(|(message, (count, overcount))| ErrorFrequency {
+                message,
+                count,
+                first_seen: None,
+                last_seen: None,
+                max_overcount: Some(overcount),
+                percent: None,
+            })
+            .collect();
+
+        top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.message.cmp(&b.message)));
+        top.truncate(n);
+        top
+    }
+}
+
+/// When and how `StatsBuilder` switches to Space-Saving for top errors.
+#[derive(Debug, Clone, Copy)]
+pub struct ApproxOptions {
+    /// Use Space-Saving from the start.
+    pub forced: bool,
+    /// Counters Space-Saving keeps.
+    pub capacity: usize,
+    /// Distinct-message count at which exact counting gives way to Space-Saving.
+    pub threshold: usize,
+}
+
+/// With `normalize`, top errors are grouped by normalized template; level
+/// counts and everything else still see the raw entries.
+pub fn analyze_logs(
+    entries: &[LogEntry],
+    top_n: usize,
+    approx: ApproxOptions,
+    normalize: Option<&Normalizer>,
+) -> LogStats {
+    let mut builder = StatsBuilder::new(top_n, approx, normalize.cloned());
+    entries.iter().for_each(|entry| builder.add(entry));
+    builder.finish()
+}
+
+/// Earliest and latest timestamp seen so far, kept as copies so the
+/// entries themselves can be dropped.
+#[derive(Clone, Default)]
+pub(crate) struct TimeBounds {
+    first: Option<(String, NaiveDateTime)>,
+    last: Option<(String, NaiveDateTime)>,
+}
+
+impl TimeBounds {
+    /// Takes `entry` into account if it has a timestamp.
+    pub fn add(&mut self, entry: &LogEntry) {
+        let Some(dt) = entry.datetime else { return };
+        if self.first.as_ref().is_none_or(|(_, first)| dt < *first) {
+            self.first = Some((entry.timestamp.clone(), dt));
+        }
+        if self.last.as_ref().is_none_or(|(_, last)| dt >= *last) {
+            self.last = Some((entry.timestamp.clone(), dt));
+        }
+    }
+
+    /// Takes in the bounds of entries that follow this one's.
+    pub fn merge(&mut self, next: TimeBounds) {
+        if let Some((timestamp, dt)) = next.first
+            && self.first.as_ref().is_none_or(|(_, first)| dt < *first)
+        {
+            self.first = Some((timestamp, dt));
+        }
+        if let Some((timestamp, dt)) = next.last
+            && self.last.as_ref().is_none_or(|(_, last)| dt >= *last)
+        {
+            self.last = Some((timestamp, dt));
+        }
+    }
+
+    /// The span, once a timestamped entry was added.
+    pub fn range(&self) -> Option<TimeRange> {
+        let ((start, first), (end, last)) = (self.first.as_ref()?, self.last.as_ref()?);
+        Some(TimeRange {
+            start: start.clone(),
+            end: end.clone(),
+            seconds: (*last - *first).num_seconds(),
+            first: *first,
+            last: *last,
+        })
+    }
+}
+
+/// `analyze_logs` fed one entry at a time, so the entries need not all be
+/// in memory: `--stream` adds each as it is parsed. Memory grows with the
+/// number of distinct error messages, up to `approx.threshold`.
+#[derive(Clone)]
+pub struct StatsBuilder {
+    top_n: usize,
+    approx: ApproxOptions,
+    normalize: Option<Normalizer>,
+    total_entries: usize,
+    by_level: HashMap<String, usize>,
+    error_messages: HashMap<String, (usize, TimeBounds)>,
+    summary: Option<SpaceSaving>,
+    bounds: TimeBounds,
+}
+
+impl StatsBuilder {
+    /// Counts top errors by exact message, or by template with `normalize`.
+    pub fn new(top_n: usize, approx: ApproxOptions, normalize: Option<Normalizer>) -> Self {
+        StatsBuilder {
+            top_n,
+            approx,
+            normalize,
+            total_entries: 0,
+            by_level: HashMap::new(),
+            error_messages: HashMap::new(),
+            summary: approx.forced.then(|| SpaceSaving::new(approx.capacity)),
+            bounds: TimeBounds::default(),
+        }
+    }
+
+    /// Counts one entry.
+    pub fn add(&mut self, entry: &LogEntry) {
+        self.total_entries += 1;
+        *self.by_level.entry(format!("{:?}", entry.level)).or_insert(0) += 1;
+        self.bounds.add(entry);
+
+        if !entry.level.is_error() {
+            return;
+        }
+
+        let key = match &self.normalize {
+            Some(normalizer) => Cow::Owned(normalizer.normalize(&entry.message)),
+            None => Cow::Borrowed(entry.message.as_str()),
+        };
+
+        if let Some(summary) = &mut self.summary {
+            summary.add(&key);
+            return;
+        }
+
+        // Look up before inserting so a repeated message is not copied.
+        if !self.error_messages.contains_key(key.as_ref()) {
+            self.error_messages.insert(key.to_string(), Default::default());
+        }
+        let (count, seen) = self.error_messages.get_mut(key.as_ref()).expect("inserted above");
+        *count += 1;
+        seen.add(entry);
+
+        if self.error_messages.len() > self.approx.threshold {
+            let exact = self.error_messages.iter().map(|(message, (count, _))| (message.as_str(), *count));
+            self.summary = Some(SpaceSaving::from_exact(self.approx.capacity, exact));
+            self.error_messages = HashMap::new();
+        }
+    }
+
+    /// Adds the counts of a builder fed the entries that follow this one's,
+    /// as for parts of a file read in parallel. Merging the parts in file
+    /// order gives the same result whatever order they finished in. Once
+    /// either side is approximate, so is the result.
+    pub fn merge(&mut self, mut next: StatsBuilder) {
+        self.total_entries += next.total_entries;
+        for (level, count) in next.by_level.drain() {
+            *self.by_level.entry(level).or_insert(0) += count;
+        }
+        self.bounds.merge(std::mem::take(&mut next.bounds));
+
+        if self.summary.is_none() && next.summary.is_none() {
+            for (message, (count, seen)) in next.error_messages.drain() {
+                let (total, bounds) = self.error_messages.entry(message).or_default();
+                *total += count;
+                bounds.merge(seen);
+            }
+            if self.error_messages.len() <= self.approx.threshold {
+                return;
+            }
+        }
+
+        let summary = self.take_summary().merge(next.take_summary());
+        self.summary = Some(summary);
+    }
+
+    /// The Space-Saving summary, seeded from the exact counts if there is
+    /// none yet.
+    fn take_summary(&mut self) -> SpaceSaving {
+        self.summary.take().unwrap_or_else(|| {
+            let exact: Vec<(String, usize)> =
+                self.error_messages.drain().map(|(message, (count, _))| (message, count)).collect();
+            SpaceSaving::from_exact(self.approx.capacity, exact.iter().map(|(message, count)| (message.as_str(), *count)))
+        })
+    }
+
+    /// The statistics of the entries added.
+    pub fn finish(self) -> LogStats {
+        let top_n = self.top_n;
+        let top_errors_approximate = self.summary.is_some();
+        let mut top_errors = match self.summary {
+            Some(summary) => summary.top(top_n),
+            None => {
+                let mut top_errors: Vec<ErrorFrequency> = self
+                    .error_messages
+                    .into_iter()
+                    .map(|(message, (count, seen))| ErrorFrequency {
+                        message,
+                        count,
+                        first_seen: seen.first.map(|(timestamp, _)| timestamp),
+                        last_seen: seen.last.map(|(timestamp, _)| timestamp),
+                        max_overcount: None,
+                        percent: None,
+                    })
+                    .collect();
+
+                top_errors.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.message.cmp(&b.message)));
+                top_errors.truncate(top_n);
+                top_errors
+            }
+        };
+
+        let total_errors = error_count(&self.by_level);
+        if total_errors > 0 {
+            for err in &mut top_errors {
+                err.percent = Some(round1(100.0 * err.count as f64 / total_errors as f64));
+            }
+        }
+
+        LogStats {
+            total_entries: self.total_entries,
+            by_level: self.by_level,
+            top_errors,
+            top_errors_approximate,
+            time_range: self.bounds.range(),
+            quality: QualityReport::default(),
+            health: None,
+            group_counts: None,
+            owners: None,
+            error_contexts: None,
+            slow_queries: None,
+            requests: None,
+            http: None,
+            alerts: None,
+            correlated_pairs: None,
+            top_by_level: None,
+            sessions: None,
+            retry_storms: None,
+            peaks: None,
+            files: None,
+        }
+    }
+}
+
+/// The most frequent messages of one level, for `--top-by-level-sections`.
+#[derive(Debug, Serialize)]
+pub struct LevelTop {
+    /// Level name.
+    pub level: String,
+    /// Its most frequent messages; percentages are of the level's entries.
+    pub top: Vec<ErrorFrequency>,
+}
+
+/// The `top_n` most frequent messages of every level present, counted
+/// exactly, most severe level first. Shares are of the level's entries.
+pub fn top_by_level(entries: &[LogEntry], top_n: usize, normalize: Option<&Normalizer>) -> Vec<LevelTop> {
+    // Level name → (entries, count and span per message).
+    type Messages<'a> = HashMap<Cow<'a, str>, (usize, Span<'a>)>;
+    let mut by_level: HashMap<String, (usize, Messages)> = HashMap::new();
+    for entry in entries {
+        let key = match normalize {
+            Some(normalizer) => Cow::Owned(normalizer.normalize(&entry.message)),
+            None => Cow::Borrowed(entry.message.as_str()),
+        };
+        let (total, messages) = by_level.entry(format!("{:?}", entry.level)).or_default();
+        *total += 1;
+        let (count, seen) = messages.entry(key).or_default();
+        *count += 1;
+        seen.add(entry);
+    }
+
+    let rank = |level: &str| Reverse(LogLevel::from_name(level).and_then(|l| l.severity()));
+    let mut levels: Vec<_> = by_level.into_iter().collect();
+    levels.sort_by(|a, b| rank(&a.0).cmp(&rank(&b.0)).then_with(|| a.0.cmp(&b.0)));
+    levels
+        .into_iter()
+        .map(|(level, (total, messages))| {
+            let mut top: Vec<ErrorFrequency> = messages
+                .into_iter()
+                .map(|(message, (count, seen))| ErrorFrequency {
+                    message: message.to_string(),
+                    count,
+                    first_seen: seen.first.map(|e| e.timestamp.clone()),
+                    last_seen: seen.last.map(|e| e.timestamp.clone()),
+                    max_overcount: None,
+                    percent: Some(round1(100.0 * count as f64 / total as f64)),
+                })
+                .collect();
+            top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.message.cmp(&b.message)));
+            top.truncate(top_n);
+            LevelTop { level, top }
+        })
+        .collect()
+}
+
+/// Keeps the most frequent errors until together they account for at least
+/// `fraction` of `total_errors`.
+pub fn truncate_to_coverage(top_errors: &mut Vec<ErrorFrequency>, total_errors: usize, fraction: f64) {
+    let target = fraction * total_errors as f64;
+    let mut covered = 0;
+    let keep = top_errors
+        .iter()
+        .position(|err| {
+            covered += err.count;
+            covered as f64 >= target
+        })
+        .map_or(top_errors.len(), |i| i + 1);
+    top_errors.truncate(keep);
+}
+
+/// Selects the error entries behind a top error. A rank refers to the
+/// `top_errors` table, which groups by exact message unless `normalized`;
+/// message text also matches entries with the same normalized template, so
+/// a message copied from another run still finds its siblings.
+pub fn error_entries(
+    entries: &[LogEntry],
+    top_errors: &[ErrorFrequency],
+    selector: &ErrorSelector,
+    normalized: bool,
+    normalizer: &Normalizer,
+) -> Result<Vec<LogEntry>, String> {
+    let (message, template) = match selector {
+        ErrorSelector::Rank(rank) => match top_errors.get(rank - 1) {
+            Some(err) => (err.message.as_str(), normalized.then(|| err.message.clone())),
+            None => return Err(format!("there are only {} top error(s)", top_errors.len())),
+        },
+        ErrorSelector::Message(message) => (message.as_str(), Some(normalizer.normalize(message))),
+    };
+
+    Ok(entries
+        .iter()
+        .filter(|e| e.level.is_error())
+        .filter(|e| {
+            e.message == message
+                || template.as_ref().is_some_and(|t| normalizer.normalize(&e.message) == *t)
+        })
+        .cloned()
+        .collect())
+}
+
+/// Runs every `--extract` regex over each message and stores its named
+/// captures as fields, overriding parser fields of the same name. Returns
+/// how many messages each regex matched.
+pub fn extract_fields(entries: &mut [LogEntry], patterns: &[Regex]) -> Vec<usize> {
+    let mut hits = vec![0; patterns.len()];
+    for entry in entries {
+        for (re, hits) in patterns.iter().zip(&mut hits) {
+            let Some(caps) = re.captures(&entry.message) else { continue };
+            *hits += 1;
+            for name in re.capture_names().flatten() {
+                if let Some(m) = caps.name(name) {
+                    entry.fields.insert(name.to_string(), m.as_str().to_string());
+                }
+            }
+        }
+    }
+    hits
+}
+
+/// Built-in columns, which `--add-field` cannot replace.
+pub const BUILTIN_COLUMNS: &[&str] = &["line", "timestamp", "level", "message"];
+
+/// `--add-field NAME=TEMPLATE`: a field computed from the other columns.
+#[derive(Debug, Clone)]
+pub struct AddField {
+    /// Column name of the new field.
+    pub name: String,
+    /// Text and column references, expanded in order.
+    pub template: Vec<TemplatePart>,
+    /// `TEMPLATE==TEXT` (true) or `TEMPLATE!=TEXT` (false): the field is
+    /// `true` or `false` instead of the expanded template.
+    pub compare: Option<(bool, String)>,
+}
+
+/// A piece of an `AddField` template.
+#[derive(Debug, Clone)]
+pub enum TemplatePart {
+    /// Literal text.
+    Text(String),
+    /// `{column}` or `{column:modifier}`.
+    Ref(String, Option<Modifier>),
+}
+
+/// How a `{column:modifier}` reference transforms the column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Modifier {
+    /// `YYYY-MM-DD` of the timestamp.
+    Date,
+    /// The timestamp's hour, `00` to `23`.
+    Hour,
+    /// The timestamp's hour and minute, `HH:MM`.
+    Minute,
+    /// The timestamp's weekday, `Mon` to `Sun`.
+    Weekday,
+    /// Lowercase text.
+    Lower,
+    /// Uppercase text.
+    Upper,
+}
+
+impl AddField {
+    /// The field's value for `entry`.
+    pub fn eval(&self, entry: &LogEntry) -> String {
+        let mut value = String::new();
+        for part in &self.template {
+            match part {
+                TemplatePart::Text(text) => value.push_str(text),
+                TemplatePart::Ref(column, modifier) => {
+                    let time = |format: &str| entry.datetime.map(|dt| dt.format(format).to_string());
+                    let text = match modifier {
+                        Some(Modifier::Date) => time("%Y-%m-%d").unwrap_or_default(),
+                        Some(Modifier::Hour) => time("%H").unwrap_or_default(),
+                        Some(Modifier::Minute) => time("%H:%M").unwrap_or_default(),
+                        Some(Modifier::Weekday) => time("%a").unwrap_or_default(),
+                        Some(Modifier::Lower) => entry_column(entry, column).to_lowercase(),
+                        Some(Modifier::Upper) => entry_column(entry, column).to_uppercase(),
+                        None => entry_column(entry, column),
+                    };
+                    value.push_str(&text);
+                }
+            }
+        }
+        match &self.compare {
+            Some((equal, text)) => ((value == *text) == *equal).to_string(),
+            None => value,
+        }
+    }
+}
+
+/// Adds each `--add-field` to every entry, in order, so a field can use
+/// the ones before it. A field reference that no entry has is an error
+/// rather than a column of empty values.
+pub fn add_fields(entries: &mut [LogEntry], fields: &[AddField]) -> Result<(), String> {
+    for field in fields {
+        let mut unseen: Vec<&str> = field
+            .template
+            .iter()
+            .filter_map(|part| match part {
+                TemplatePart::Ref(column, _) if !BUILTIN_COLUMNS.contains(&column.as_str()) => Some(column.as_str()),
+                _ => None,
+            })
+            .collect();
+        for entry in entries.iter_mut() {
+            unseen.retain(|column| !entry.fields.contains_key(*column));
+            let value = field.eval(entry);
+            entry.fields.insert(field.name.clone(), value);
+        }
+        if let (Some(column), false) = (unseen.first(), entries.is_empty()) {
+            return Err(format!("'{}' refers to '{}', which no entry has", field.name, column));
+        }
+    }
+    Ok(())
+}
+
+/// `--level-remap FIELD~=REGEX=LEVEL`: entries whose column matches get
+/// the level.
+#[derive(Debug, Clone)]
+pub struct LevelRemap {
+    /// The rule as written, for reports.
+    pub spec: String,
+    /// Column matched.
+    pub column: String,
+    /// Pattern the column must match.
+    pub regex: Regex,
+    /// Level given to matching entries.
+    pub level: LogLevel,
+}
+
+/// Gives each entry the level of the first rule that matches it and returns
+/// how many entries each rule changed; an entry already at the rule's level
+/// stops there but is not counted.
+pub fn remap_levels(entries: &mut [LogEntry], rules: &[LevelRemap]) -> Vec<usize> {
+    let mut remapped = vec![0; rules.len()];
+    for entry in entries {
+        let Some(i) = rules.iter().position(|rule| rule.regex.is_match(&entry_column(entry, &rule.column))) else {
+            continue;
+        };
+        if entry.level != rules[i].level {
+            entry.level = rules[i].level.clone();
+            remapped[i] += 1;
+        }
+    }
+    remapped
+}
+
+/// Entries of one input file, for `--per-file`.
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    /// The file as given on the command line.
+    pub file: String,
+    /// Entries of the file.
+    pub total_entries: usize,
+    /// Entries per level name.
+    pub by_level: HashMap<String, usize>,
+}
+
+/// Level counts per `SOURCE_FIELD` value, in the order of `files`; files
+/// left without entries are listed with zero counts.
+pub fn per_file(entries: &[LogEntry], files: &[String]) -> Vec<FileSummary> {
+    let mut summaries: Vec<FileSummary> = files
+        .iter()
+        .map(|file| FileSummary { file: file.clone(), total_entries: 0, by_level: HashMap::new() })
+        .collect();
+    let index: HashMap<&str, usize> = files.iter().enumerate().map(|(i, file)| (file.as_str(), i)).collect();
+    for entry in entries {
+        let Some(&i) = entry.fields.get(SOURCE_FIELD).and_then(|file| index.get(file.as_str())) else { continue };
+        summaries[i].total_entries += 1;
+        *summaries[i].by_level.entry(format!("{:?}", entry.level)).or_insert(0) += 1;
+    }
+    summaries
+}
+
+/// Distribution of a named capture's values, most frequent first. Entries
+/// where the group did not participate in the match are not counted.
+///
+/// With `distinct`, each value also gets the number of different messages
+/// among its entries (templates when `normalize`): one message repeated a
+/// thousand times counts 1, a thousand different ones count 1000. Messages
+/// are held as 64-bit digests.
+pub fn count_group(entries: &[LogEntry], group: &str, distinct: bool, normalize: Option<&Normalizer>) -> GroupCounts {
+    let mut counts: HashMap<&str, (usize, HashSet<u64>)> = HashMap::new();
+    for entry in entries {
+        let Some(value) = entry.fields.get(group) else { continue };
+        let (count, messages) = counts.entry(value).or_default();
+        *count += 1;
+        if distinct {
+            let mut hasher = DefaultHasher::new();
+            match normalize {
+                Some(normalizer) => normalizer.normalize(&entry.message).hash(&mut hasher),
+                None => entry.message.hash(&mut hasher),
+            }
+            messages.insert(hasher.finish());
+        }
+    }
+
+    let mut counts: Vec<ValueCount> = counts
+        .into_iter()
+        .map(|(value, (count, messages))| ValueCount {
+            value: value.to_string(),
+            count,
+            distinct_messages: distinct.then_some(messages.len()),
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+
+    GroupCounts {
+        group: group.to_string(),
+        counts,
+    }
+}
+
+
+
+/* =========================
+   Error context
+   ========================= */
+
+/// A run of entries around one or more errors, for `--error-context`.
+#[derive(Debug, Serialize)]
+pub struct ErrorContext {
+    /// The errors and the entries leading up to them, in file order.
+    pub entries: Vec<LogEntry>,
+}
+
+/// For each entry selected by `is_error`, the `before` entries preceding it.
+/// Windows that overlap or touch are merged into one group, so no entry is
+/// shown twice.
+pub fn error_contexts(
+    entries: &[LogEntry],
+    before: usize,
+    is_error: impl Fn(&LogEntry) -> bool,
+) -> Vec<ErrorContext> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if !is_error(entry) {
+            continue;
+        }
+        let start = i.saturating_sub(before);
+        match ranges.last_mut() {
+            Some((_, end)) if start <= *end + 1 => *end = i,
+            _ => ranges.push((start, i)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| ErrorContext {
+            entries: entries[start..=end].to_vec(),
+        })
+        .collect()
+}
+
+
+
+/* =========================
+   Correlation
+   ========================= */
+
+/// Two error templates where the second often follows the first.
+#[derive(Debug, Serialize)]
+pub struct CorrelatedPair {
+    /// The template seen earlier.
+    pub first: String,
+    /// The template that followed.
+    pub then: String,
+    /// Errors of `then` preceded by `first` within the window.
+    pub count: usize,
+}
+
+/// Counts, for every timestamped error, the other templates seen among the
+/// errors of the preceding `window`, and returns the `top_n` most frequent
+/// `(first, then)` pairs. Each error counts a preceding template once, however
+/// often it occurred. Errors are grouped by normalized template; entries
+/// must be time-ordered (see `--sort-by-time`).
+pub fn correlated_pairs(
+    entries: &[LogEntry],
+    window: TimeDelta,
+    top_n: usize,
+    normalizer: &Normalizer,
+) -> Vec<CorrelatedPair> {
+    let mut recent: VecDeque<(NaiveDateTime, Rc<str>)> = VecDeque::new();
+    let mut counts: HashMap<(Rc<str>, Rc<str>), usize> = HashMap::new();
+
+    for entry in entries.iter().filter(|e| e.level.is_error()) {
+        let Some(dt) = entry.datetime else { continue };
+        while recent.front().is_some_and(|(t, _)| dt - *t > window) {
+            recent.pop_front();
+        }
+
+        let template: Rc<str> = normalizer.normalize(&entry.message).into();
+        let firsts: HashSet<&Rc<str>> = recent.iter().map(|(_, t)| t).filter(|t| **t != template).collect();
+        for first in firsts {
+            *counts.entry((first.clone(), template.clone())).or_insert(0) += 1;
+        }
+        recent.push_back((dt, template));
+    }
+
+    let mut pairs: Vec<CorrelatedPair> = counts
+        .into_iter()
+        .map(|((first, then), count)| CorrelatedPair {
+            first: first.to_string(),
+            then: then.to_string(),
+            count,
+        })
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.count.cmp(&a.count).then_with(|| (&a.first, &a.then).cmp(&(&b.first, &b.then)))
+    });
+    pairs.truncate(top_n);
+    pairs
+}
+
+
+/// A message repeated in a burst, as from a client retrying.
+#[derive(Debug, Serialize)]
+pub struct RetryStorm {
+    /// The message, or its template.
+    pub message: String,
+    /// Timestamp of the first error of the densest window.
+    pub start: String,
+    /// Timestamp of the last error of that window.
+    pub end: String,
+    /// Errors of the message within that window.
+    pub count: usize,
+    /// Errors per second in that window.
+    pub per_second: f64,
+}
+
+/// Error messages (templates when `normalize`) seen more than `threshold`
+/// times within `window`, each with its densest window, most intense first.
+/// Like `correlated_pairs` it expects time-ordered entries.
+pub fn retry_storms(
+    entries: &[LogEntry],
+    threshold: usize,
+    window: TimeDelta,
+    normalize: Option<&Normalizer>,
+) -> Vec<RetryStorm> {
+    let mut recent: HashMap<String, VecDeque<&LogEntry>> = HashMap::new();
+    let mut densest: HashMap<String, (&LogEntry, &LogEntry, usize)> = HashMap::new();
+
+    for entry in entries.iter().filter(|e| e.level.is_error()) {
+        let Some(dt) = entry.datetime else { continue };
+        let message = match normalize {
+            Some(normalizer) => normalizer.normalize(&entry.message),
+            None => entry.message.clone(),
+        };
+        let burst = recent.entry(message.clone()).or_default();
+        while burst.front().and_then(|e| e.datetime).is_some_and(|t| dt - t > window) {
+            burst.pop_front();
+        }
+        burst.push_back(entry);
+
+        let count = burst.len();
+        if count > threshold && densest.get(&message).is_none_or(|d| count > d.2) {
+            densest.insert(message, (burst[0], entry, count));
+        }
+    }
+
+    let seconds = window.num_seconds().max(1) as f64;
+    let mut storms: Vec<RetryStorm> = densest
+        .into_iter()
+        .map(|(message, (first, last, count))| RetryStorm {
+            message,
+            start: first.timestamp.clone(),
+            end: last.timestamp.clone(),
+            count,
+            per_second: count as f64 / seconds,
+        })
+        .collect();
+    storms.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.message.cmp(&b.message)));
+    storms
+}
+
+
+
+/* =========================
+   Traces
+   ========================= */
+
+/// One entry of a `--show-id` trace.
+#[derive(Debug, Serialize)]
+pub struct TraceStep {
+    /// Seconds since the first timestamped entry of the trace.
+    pub offset_seconds: Option<f64>,
+    /// 1-based line the entry starts on.
+    pub line: usize,
+    /// Timestamp as written in the log.
+    pub timestamp: String,
+    /// Level of the entry.
+    pub level: LogLevel,
+    /// Message of the entry.
+    pub message: String,
+}
+
+/// The entries sharing one request or trace ID, in file order.
+#[derive(Debug, Serialize)]
+pub struct Trace {
+    /// The ID that was traced.
+    pub id: String,
+    /// Seconds from the first to the last timestamped entry.
+    pub duration_seconds: Option<f64>,
+    /// The matching entries.
+    pub steps: Vec<TraceStep>,
+}
+
+/// The entries whose `field` (any field without one) equals `id`, in file
+/// order, timed from the first of them that has a timestamp.
+pub fn trace(entries: &[LogEntry], id: &str, field: Option<&str>) -> Trace {
+    let matching: Vec<&LogEntry> = entries
+        .iter()
+        .filter(|e| match field {
+            Some(field) => e.fields.get(field).is_some_and(|v| v == id),
+            None => e.fields.values().any(|v| v == id),
+        })
+        .collect();
+
+    let first = matching.iter().find_map(|e| e.datetime);
+    let offset = |dt: Option<NaiveDateTime>| {
+        let (dt, first) = (dt?, first?);
+        Some((dt - first).num_milliseconds() as f64 / 1000.0)
+    };
+    let last = matching.iter().filter_map(|e| e.datetime).max();
+
+    Trace {
+        id: id.to_string(),
+        duration_seconds: offset(last),
+        steps: matching
+            .into_iter()
+            .map(|e| TraceStep {
+                offset_seconds: offset(e.datetime),
+                line: e.line,
+                timestamp: e.timestamp.clone(),
+                level: e.level.clone(),
+                message: e.message.clone(),
+            })
+            .collect(),
+    }
+}
+
+
+
+/* =========================
+   Sessions
+   ========================= */
+
+/// A run of entries no further apart than the session gap.
+#[derive(Debug, Serialize)]
+pub struct Session {
+    /// Timestamp of the first entry, as written.
+    pub start: String,
+    /// Timestamp of the last entry, as written.
+    pub end: String,
+    /// Value of the key field, with `--session-key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Number of entries in the session.
+    pub entries: usize,
+    /// Error entries among them.
+    pub errors: usize,
+}
+
+/// `--sessionize`: how the log splits into sessions of activity.
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    /// Number of sessions.
+    pub sessions: usize,
+    /// Sessions with at least one error.
+    pub error_sessions: usize,
+    /// Average number of entries per session.
+    pub mean_entries: f64,
+    /// The `top_n` sessions with the most entries.
+    pub largest: Vec<Session>,
+}
+
+/// Splits the timestamped entries into sessions: runs of consecutive
+/// entries at most `gap` apart, counted in whole seconds so that `0s` means
+/// the same second, with the same `key` field value when one is given.
+/// Entries without a timestamp belong to no session.
+pub fn sessionize(entries: &[LogEntry], gap: TimeDelta, key: Option<&str>, top_n: usize) -> SessionReport {
+    fn key_of<'a>(entry: &'a LogEntry, key: Option<&str>) -> Option<&'a String> {
+        key.and_then(|k| entry.fields.get(k))
+    }
+    // (first, last, entries, errors) per session, as entry references.
+    let mut sessions: Vec<(&LogEntry, &LogEntry, usize, usize)> = Vec::new();
+
+    for entry in entries {
+        let Some(dt) = entry.datetime else { continue };
+        let error = usize::from(entry.level.is_error());
+        match sessions.last_mut() {
+            Some((first, last, count, errors))
+                if last.datetime.is_some_and(|l| {
+                    dt.and_utc().timestamp() - l.and_utc().timestamp() <= gap.num_seconds()
+                }) && key_of(first, key) == key_of(entry, key) =>
+            {
+                *last = entry;
+                *count += 1;
+                *errors += error;
+            }
+            _ => sessions.push((entry, entry, 1, error)),
+        }
+    }
+
+    let error_sessions = sessions.iter().filter(|s| s.3 > 0).count();
+    let mean_entries = if sessions.is_empty() {
+        0.0
+    } else {
+        sessions.iter().map(|s| s.2).sum::<usize>() as f64 / sessions.len() as f64
+    };
+    let total = sessions.len();
+    // Stable, so equally large sessions stay in file order.
+    sessions.sort_by_key(|s| Reverse(s.2));
+    sessions.truncate(top_n);
+
+    SessionReport {
+        sessions: total,
+        error_sessions,
+        mean_entries,
+        largest: sessions
+            .into_iter()
+            .map(|(first, last, entries, errors)| Session {
+                start: first.timestamp.clone(),
+                end: last.timestamp.clone(),
+                key: key_of(first, key).cloned(),
+                entries,
+                errors,
+            })
+            .collect(),
+    }
+}
+
+
+
+/* =========================
+   Normalization
+   ========================= */
+
+/// Replacements applied in order to turn a message into a template, so that
+/// messages differing only by IDs, addresses or counters group together.
+#[derive(Debug, Clone)]
+pub struct NormalizeRule {
+    /// What to replace.
+    pub regex: Regex,
+    /// What to replace it with, taken literally.
+    pub placeholder: String,
+}
+
+/// Describes rules whose placeholder a later rule (custom or built-in)
+/// would rewrite, and rules repeating an earlier regex, which never match.
+pub fn normalize_conflicts(rules: &[NormalizeRule]) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let later = rules[i + 1..].iter().map(|r| &r.regex).chain(NORMALIZE_RULES.iter().map(|(re, _)| re));
+        if let Some(re) = later.clone().find(|re| re.is_match(&rule.placeholder)) {
+            conflicts.push(format!(
+                "placeholder '{}' of rule '{}' is rewritten by later rule '{}'",
+                rule.placeholder, rule.regex, re
+            ));
+        }
+        if rules[..i].iter().any(|r| r.regex.as_str() == rule.regex.as_str()) {
+            conflicts.push(format!("rule '{}' repeats an earlier regex and never applies", rule.regex));
+        }
+    }
+    conflicts
+}
+
+/// The built-in rules, applied after any custom ones: UUIDs, IP addresses,
+/// hex literals, long hex IDs and then any remaining number.
+pub(crate) static NORMALIZE_RULES: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        (r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}", "<UUID>"),
+        (r"\b\d{1,3}(?:\.\d{1,3}){3}(?::\d+)?\b", "<IP>"),
+        (r"\b0x[0-9a-fA-F]+\b", "<HEX>"),
+        (r"\b[0-9a-fA-F]{8,}\b", "<ID>"),
+        (r"\d+", "<N>"),
+    ]
+    .into_iter()
+    .map(|(re, placeholder)| (Regex::new(re).unwrap(), placeholder))
+    .collect()
+});
+
+/// Turns messages into templates with the user's rules, in order, and
+/// then `NORMALIZE_RULES`. The default has no user rules.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    rules: Vec<NormalizeRule>,
+}
+
+impl Normalizer {
+    /// Applies `rules` before the built-in ones; see `normalize_conflicts`
+    /// for rules that can never take effect.
+    pub fn new(rules: Vec<NormalizeRule>) -> Self {
+        Normalizer { rules }
+    }
+
+    /// The template of `message`.
+    pub fn normalize(&self, message: &str) -> String {
+        self.rules
+            .iter()
+            .map(|rule| (&rule.regex, rule.placeholder.as_str()))
+            .chain(NORMALIZE_RULES.iter().map(|(re, placeholder)| (re, *placeholder)))
+            .fold(message.to_string(), |msg, (re, placeholder)| {
+                re.replace_all(&msg, regex::NoExpand(placeholder)).into_owned()
+            })
+    }
+}
+
+
+
+/* =========================
+   Slow queries
+   ========================= */
+
+/// Rewrites of `normalize_sql`, applied in order.
+pub(crate) static SQL_LITERALS: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        (r"'(?:[^'\\]|\\.|'')*'", "?"),
+        (r#""(?:[^"\\]|\\.)*""#, "?"),
+        (r"\b0x[0-9a-fA-F]+\b", "?"),
+        (r"\b\d+(?:\.\d+)?\b", "?"),
+        (r"(?i)\bIN\s*\(\s*\?(?:\s*,\s*\?)*\s*\)", "IN (?+)"),
+        (r"\s+", " "),
+    ]
+    .into_iter()
+    .map(|(re, placeholder)| (Regex::new(re).unwrap(), placeholder))
+    .collect()
+});
+
+/// Replaces string and numeric literals with `?` and folds `IN` lists, so
+/// queries differing only in their arguments share one template.
+pub(crate) fn normalize_sql(sql: &str) -> String {
+    SQL_LITERALS
+        .iter()
+        .fold(sql.to_string(), |sql, (re, placeholder)| {
+            re.replace_all(&sql, *placeholder).into_owned()
+        })
+        .trim()
+        .trim_end_matches(';')
+        .to_string()
+}
+
+/// Timing of one SQL template across the slow log, in seconds.
+#[derive(Debug, Serialize)]
+pub struct QueryPattern {
+    /// Query with its literals replaced by `?`.
+    pub template: String,
+    /// Number of queries with this template.
+    pub count: usize,
+    /// Sum of their query times.
+    pub total_query_time: f64,
+    /// 95th percentile query time.
+    pub p95_query_time: f64,
+    /// Slowest query time.
+    pub max_query_time: f64,
+    /// Average lock time.
+    pub avg_lock_time: f64,
+    /// Sum of the rows examined.
+    pub rows_examined: u64,
+}
+
+/// Groups slow query entries by template and ranks them by total query time,
+/// the cost they put on the server.
+pub fn summarize_queries(entries: &[LogEntry], top_n: usize) -> Vec<QueryPattern> {
+    let metric = |e: &LogEntry, name: &str| -> f64 {
+        e.fields.get(name).and_then(|v| v.parse().ok()).unwrap_or(0.0)
+    };
+
+    let mut by_template: HashMap<&str, Vec<&LogEntry>> = HashMap::new();
+    for entry in entries {
+        by_template.entry(&entry.message).or_default().push(entry);
+    }
+
+    let mut patterns: Vec<QueryPattern> = by_template
+        .into_iter()
+        .map(|(template, records)| {
+            let mut times: Vec<f64> = records.iter().map(|e| metric(e, "query_time")).collect();
+            times.sort_by(f64::total_cmp);
+            let p95 = percentile(&times, 95);
+            let lock: f64 = records.iter().map(|e| metric(e, "lock_time")).sum();
+
+            QueryPattern {
+                template: template.to_string(),
+                count: records.len(),
+                total_query_time: times.iter().sum(),
+                p95_query_time: p95,
+                max_query_time: times[times.len() - 1],
+                avg_lock_time: lock / records.len() as f64,
+                rows_examined: records.iter().map(|e| metric(e, "rows_examined") as u64).sum(),
+            }
+        })
+        .collect();
+
+    patterns.sort_by(|a, b| {
+        b.total_query_time
+            .total_cmp(&a.total_query_time)
+            .then_with(|| a.template.cmp(&b.template))
+    });
+    patterns.truncate(top_n);
+    patterns
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+pub(crate) fn percentile(sorted: &[f64], p: usize) -> f64 {
+    sorted[(sorted.len() * p).div_ceil(100).max(1) - 1]
+}
+
+
+
+/* =========================
+   Requests
+   ========================= */
+
+/// Latency and errors of one Rails `Controller#action`, W3C
+/// `METHOD /path` or HAProxy backend, durations in ms. Each duration is
+/// taken over the requests that record it, and is `None` when none do.
+#[derive(Debug, Serialize)]
+pub struct EndpointSummary {
+    /// `Controller#action`, `METHOD /path` or backend name.
+    pub endpoint: String,
+    /// Number of completed requests.
+    pub requests: usize,
+    /// Requests answered with a 5xx status.
+    pub server_errors: usize,
+    /// Requests answered with a 4xx status.
+    pub client_errors: usize,
+    /// 95th percentile duration.
+    pub p95_ms: Option<f64>,
+    /// Longest duration.
+    pub max_ms: Option<f64>,
+    /// Average time spent in the database.
+    pub avg_db_ms: Option<f64>,
+    /// Average time spent rendering views.
+    pub avg_view_ms: Option<f64>,
+}
+
+/// The busiest endpoints of a request log.
+#[derive(Debug, Serialize)]
+pub struct RequestReport {
+    /// Busiest first, at most `top_n` of them.
+    pub endpoints: Vec<EndpointSummary>,
+    /// Requests that were `Started` but never `Completed`.
+    pub unfinished: usize,
+}
+
+/// Groups completed requests by endpoint, busiest first. Requests that
+/// never reached a controller (routing errors) are grouped as `(unrouted)`.
+/// Requests without a duration, such as HAProxy's aborted ones (`tr` of
+/// -1), are counted but left out of the timings.
+pub fn summarize_requests(entries: &[LogEntry], top_n: usize) -> RequestReport {
+
+    let mut unfinished = 0;
+    let mut by_endpoint: HashMap<&str, Vec<&LogEntry>> = HashMap::new();
+    for entry in entries.iter().filter(|e| e.fields.contains_key("method")) {
+        if !entry.fields.contains_key("status") {
+            unfinished += 1;
+            continue;
+        }
+        let endpoint = entry.fields.get("action").map_or("(unrouted)", String::as_str);
+        by_endpoint.entry(endpoint).or_default().push(entry);
+    }
+
+    let mut endpoints: Vec<EndpointSummary> = by_endpoint
+        .into_iter()
+        .map(|(endpoint, requests)| {
+            let metric = |name: &str| -> Vec<f64> {
+                requests.iter().filter_map(|e| e.fields.get(name)?.parse().ok()).collect()
+            };
+            let mut times = metric("duration_ms");
+            times.sort_by(f64::total_cmp);
+            let status_class = |class: char| {
+                requests.iter().filter(|e| e.fields["status"].starts_with(class)).count()
+            };
+            let average = |name: &str| {
+                let values = metric(name);
+                (!values.is_empty()).then(|| round1(values.iter().sum::<f64>() / values.len() as f64))
+            };
+
+            EndpointSummary {
+                endpoint: endpoint.to_string(),
+                requests: requests.len(),
+                server_errors: status_class('5'),
+                client_errors: status_class('4'),
+                p95_ms: (!times.is_empty()).then(|| percentile(&times, 95)),
+                max_ms: times.last().copied(),
+                avg_db_ms: average("db_ms"),
+                avg_view_ms: average("view_ms"),
+            }
+        })
+        .collect();
+
+    endpoints.sort_by(|a, b| b.requests.cmp(&a.requests).then_with(|| a.endpoint.cmp(&b.endpoint)));
+    endpoints.truncate(top_n);
+    RequestReport { endpoints, unfinished }
+}
+
+/// Fields holding a request's path (Rails, W3C) or URI (access, HAProxy).
+pub(crate) const HTTP_PATH_FIELDS: &[&str] = &["path", "uri"];
+
+/// Fields holding the client address in the request log formats.
+pub(crate) const HTTP_CLIENT_FIELDS: &[&str] = &["client_ip", "ip", "c-ip"];
+
+/// A path or client and its share of the requests, in percent.
+#[derive(Debug, Serialize)]
+pub struct HttpCount {
+    /// The path or client address.
+    pub value: String,
+    /// Number of requests with it.
+    pub requests: usize,
+    /// Share of all requests.
+    pub percent: f64,
+}
+
+/// Latency of one endpoint, for the slowest endpoints of `--http`.
+#[derive(Debug, Serialize)]
+pub struct EndpointLatency {
+    /// `METHOD /path`, without query string.
+    pub endpoint: String,
+    /// Number of requests to it.
+    pub requests: usize,
+    /// 95th percentile duration.
+    pub p95_ms: f64,
+    /// Longest duration.
+    pub max_ms: f64,
+    /// Average duration.
+    pub avg_ms: f64,
+}
+
+/// `--http`: the traffic summary of a request log, as goaccess gives it.
+#[derive(Debug, Serialize)]
+pub struct HttpReport {
+    /// Number of requests.
+    pub requests: usize,
+    /// Requests by status class (`2xx`, `4xx`, ...); `-` when none was sent.
+    pub status_classes: BTreeMap<String, usize>,
+    /// Sum of the response sizes, when the log records them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_sent: Option<u64>,
+    /// Most requested paths, without query string.
+    pub top_paths: Vec<HttpCount>,
+    /// Endpoints by p95 latency; empty when the log has no durations.
+    pub slowest_endpoints: Vec<EndpointLatency>,
+    /// Clients with the most requests.
+    pub top_clients: Vec<HttpCount>,
+}
+
+/// Summarizes the requests among `entries`, those with a `method` and a
+/// `status` field, keeping the `top_n` paths (without query string),
+/// endpoints and clients.
+pub fn summarize_http(entries: &[LogEntry], top_n: usize) -> HttpReport {
+    let requests: Vec<&LogEntry> = entries
+        .iter()
+        .filter(|e| e.fields.contains_key("method") && e.fields.contains_key("status"))
+        .collect();
+    let field = |e: &LogEntry, names: &[&str]| names.iter().find_map(|name| e.fields.get(*name)).cloned();
+    let top = |values: Vec<String>| {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for value in values {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+        let mut counts: Vec<HttpCount> = counts
+            .into_iter()
+            .map(|(value, count)| HttpCount {
+                value,
+                requests: count,
+                percent: round1(100.0 * count as f64 / requests.len() as f64),
+            })
+            .collect();
+        counts.sort_by(|a, b| b.requests.cmp(&a.requests).then_with(|| a.value.cmp(&b.value)));
+        counts.truncate(top_n);
+        counts
+    };
+
+    let mut status_classes = BTreeMap::new();
+    for request in &requests {
+        let class = match request.fields["status"].as_bytes() {
+            [digit, ..] if digit.is_ascii_digit() => format!("{}xx", *digit as char),
+            _ => "-".to_string(),
+        };
+        *status_classes.entry(class).or_insert(0) += 1;
+    }
+    let bytes: Vec<u64> = requests.iter().filter_map(|e| e.fields.get("bytes")?.parse().ok()).collect();
+
+    let mut durations: HashMap<&str, Vec<f64>> = HashMap::new();
+    for request in &requests {
+        if let Some(ms) = request.fields.get("duration_ms").and_then(|ms| ms.parse().ok()) {
+            let endpoint = request.fields.get("action").unwrap_or(&request.message);
+            durations.entry(endpoint).or_default().push(ms);
+        }
+    }
+    let mut slowest_endpoints: Vec<EndpointLatency> = durations
+        .into_iter()
+        .map(|(endpoint, mut times)| {
+            times.sort_by(f64::total_cmp);
+            EndpointLatency {
+                endpoint: endpoint.to_string(),
+                requests: times.len(),
+                p95_ms: percentile(&times, 95),
+                max_ms: times[times.len() - 1],
+                avg_ms: round1(times.iter().sum::<f64>() / times.len() as f64),
+            }
+        })
+        .collect();
+    slowest_endpoints.sort_by(|a, b| {
+        b.p95_ms.total_cmp(&a.p95_ms).then(b.max_ms.total_cmp(&a.max_ms)).then_with(|| a.endpoint.cmp(&b.endpoint))
+    });
+    slowest_endpoints.truncate(top_n);
+
+    HttpReport {
+        requests: requests.len(),
+        status_classes,
+        bytes_sent: (!bytes.is_empty()).then(|| bytes.iter().sum()),
+        top_paths: top(requests
+            .iter()
+            .filter_map(|e| field(e, HTTP_PATH_FIELDS))
+            .map(|path| path.split('?').next().unwrap_or_default().to_string())
+            .collect()),
+        slowest_endpoints,
+        top_clients: top(requests.iter().filter_map(|e| field(e, HTTP_CLIENT_FIELDS)).collect()),
+    }
+}
+
+
+
+/* =========================
+   Owners
+   ========================= */
+
+#[derive(Debug, Deserialize)]
+struct OwnersFile {
+    #[serde(default)]
+    owner: Vec<OwnerRule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnerRule {
+    team: String,
+    patterns: Vec<String>,
+}
+
+/// Teams and the message patterns they own, from `load_owners`.
+pub struct Owners {
+    rules: Vec<(String, RegexSet)>,
+}
+
+/// Owner of the errors no rule matches.
+pub(crate) const UNASSIGNED_OWNER: &str = "(unassigned)";
+
+/// Loads an owners file of the form:
+///
+/// ```toml
+/// [[owner]]
+/// team = "payments"
+/// patterns = ["^Payment", "stripe"]
+/// ```
+///
+/// Rules are tried in file order against normalized messages; the first
+/// matching team owns the error.
+pub fn load_owners(path: &std::path::Path) -> Result<Owners, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: OwnersFile = toml::from_str(&content).map_err(|e| e.to_string())?;
+
+    let rules = file
+        .owner
+        .into_iter()
+        .map(|rule| {
+            RegexSet::new(&rule.patterns)
+                .map(|set| (rule.team.clone(), set))
+                .map_err(|e| format!("team '{}': {}", rule.team, e))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Owners { rules })
+}
+
+/// Errors per owner with each owner's most frequent template. The
+/// unassigned bucket, if any, comes last and lists its `top_n` biggest
+/// groups so the mapping can be extended.
+pub fn summarize_owners(
+    entries: &[LogEntry],
+    owners: &Owners,
+    top_n: usize,
+    normalizer: &Normalizer,
+) -> Vec<OwnerSummary> {
+    let mut templates: HashMap<String, usize> = HashMap::new();
+    for entry in entries.iter().filter(|e| e.level.is_error()) {
+        *templates.entry(normalizer.normalize(&entry.message)).or_insert(0) += 1;
+    }
+
+    let mut by_owner: HashMap<&str, Vec<ErrorFrequency>> = HashMap::new();
+    for (message, count) in templates {
+        let owner = owners
+            .rules
+            .iter()
+            .find(|(_, set)| set.is_match(&message))
+            .map_or(UNASSIGNED_OWNER, |(team, _)| team.as_str());
+        by_owner
+            .entry(owner)
+            .or_default()
+            .push(ErrorFrequency {
+                message,
+                count,
+                first_seen: None,
+                last_seen: None,
+                max_overcount: None,
+                percent: None,
+            });
+    }
+
+    let mut summaries: Vec<OwnerSummary> = by_owner
+        .into_iter()
+        .map(|(owner, mut groups)| {
+            groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.message.cmp(&b.message)));
+            let unassigned = owner == UNASSIGNED_OWNER;
+            if unassigned {
+                groups.truncate(top_n);
+            }
+            OwnerSummary {
+                owner: owner.to_string(),
+                errors: groups.iter().map(|g| g.count).sum(),
+                top_message: groups.first().map(|g| g.message.clone()),
+                groups: if unassigned { groups } else { Vec::new() },
+            }
+        })
+        .collect();
+
+    summaries.sort_by(|a, b| {
+        (a.owner == UNASSIGNED_OWNER)
+            .cmp(&(b.owner == UNASSIGNED_OWNER))
+            .then_with(|| b.errors.cmp(&a.errors))
+            .then_with(|| a.owner.cmp(&b.owner))
+    });
+
+    summaries
+}
+
+
+
+/* =========================
+   Health score
+   ========================= */
+
+/// Relative weights of the health components; only their ratios matter.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthWeights {
+    /// Weight of the error rate.
+    pub error_rate: f64,
+    /// Weight of the fatal entries.
+    pub fatal: f64,
+    /// Weight of the errors missing from the baseline.
+    pub new_errors: f64,
+    /// Weight of the lines that failed to parse.
+    pub parse_failures: f64,
+    /// Weight of the gaps in the timeline.
+    pub gaps: f64,
+}
+
+impl Default for HealthWeights {
+    fn default() -> Self {
+        HealthWeights {
+            error_rate: 40.0,
+            fatal: 20.0,
+            new_errors: 15.0,
+            parse_failures: 10.0,
+            gaps: 15.0,
+        }
+    }
+}
+
+/// One term of a `HealthScore`.
+#[derive(Debug, Serialize)]
+pub struct HealthComponent {
+    /// Component name, as in `HealthWeights`.
+    pub name: &'static str,
+    /// Its weight as configured; only its ratio to the others matters.
+    pub weight: f64,
+    /// Raw measurement, `None` when the component cannot be computed.
+    pub value: Option<f64>,
+    /// Points this component contributes to the score.
+    pub points: Option<f64>,
+}
+
+/// How healthy the log looks, from 0 (worst) to 100.
+#[derive(Debug, Serialize)]
+pub struct HealthScore {
+    /// The sum of the components' points; `None` when no component could
+    /// be computed, as for an empty or fully filtered log.
+    pub score: Option<f64>,
+    /// Every component, including those without a value.
+    pub components: Vec<HealthComponent>,
+}
+
+/// 0–100 score where each component scores between 0 (at or beyond its
+/// worst-case level) and its share of the weights. Components that cannot
+/// be computed are left out and the remaining weights are rescaled, so a
+/// log without timestamps is judged on the other components only, and a
+/// log without entries or lines has no score at all.
+pub fn compute_health(stats: &LogStats, has_timestamps: bool, weights: &HealthWeights) -> HealthScore {
+    let errors = error_count(&stats.by_level);
+    let total = stats.total_entries;
+    let lines = total + stats.quality.unparsed_lines - stats.quality.unparsed_entries;
+
+    // (name, weight, raw value, penalty in 0..=1)
+    let measured = [
+        (
+            "error_rate",
+            weights.error_rate,
+            (total > 0).then(|| {
+                let rate = errors as f64 / total as f64;
+                (rate, rate * 10.0)
+            }),
+        ),
+        (
+            "fatal",
+            weights.fatal,
+            (total > 0).then(|| {
+                let fatal = stats.by_level.get("Fatal").copied().unwrap_or(0) as f64;
+                (fatal, fatal / 5.0)
+            }),
+        ),
+        // Needs a baseline to compare fingerprints against.
+        ("new_errors", weights.new_errors, None),
+        (
+            "parse_failures",
+            weights.parse_failures,
+            (lines > 0).then(|| {
+                let ratio = stats.quality.unparsed_lines as f64 / lines as f64;
+                (ratio, ratio * 10.0)
+            }),
+        ),
+        (
+            "gaps",
+            weights.gaps,
+            (has_timestamps && total > 0).then(|| {
+                let gaps = stats.quality.forward_jumps.len() as f64;
+                (gaps, gaps / 5.0)
+            }),
+        ),
+    ];
+
+    let available: f64 = measured
+        .iter()
+        .filter(|(_, _, m)| m.is_some())
+        .map(|(_, w, _)| w.max(0.0))
+        .sum();
+
+    let components: Vec<HealthComponent> = measured
+        .into_iter()
+        .map(|(name, weight, m)| HealthComponent {
+            name,
+            weight,
+            value: m.map(|(value, _)| value),
+            points: m.filter(|_| available > 0.0).map(|(_, penalty)| {
+                round1(100.0 * weight.max(0.0) / available * (1.0 - penalty.clamp(0.0, 1.0)))
+            }),
+        })
+        .collect();
+
+    let score = (available > 0.0)
+        .then(|| round1(components.iter().filter_map(|c| c.points).sum::<f64>().clamp(0.0, 100.0)));
+
+    HealthScore { score, components }
+}
+
+/// Rounds `x` to one decimal place.
+pub fn round1(x: f64) -> f64 {
+    (x * 10.0).round() / 10.0
+}
+
+
+
+/* =========================
+   Redaction
+   ========================= */
+
+/// What a redacted substring is replaced with.
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// The patterns of `--redact`: emails, bearer tokens, IPv6 and IPv4
+/// addresses, and card-like digit runs.
+pub(crate) static BUILTIN_REDACTIONS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        // email
+        r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+        // bearer token
+        r"(?i)\bbearer\s+[A-Za-z0-9\-._~+/]+=*",
+        // IPv6, full and compressed
+        r"\b(?:[0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}\b",
+        r"\b(?:[0-9a-fA-F]{1,4}:){1,6}(?::[0-9a-fA-F]{1,4}){1,6}\b",
+        // IPv4
+        r"\b(?:\d{1,3}\.){3}\d{1,3}\b",
+        // credit-card-like: 13 to 16 digits, optionally grouped
+        r"\b(?:\d[ -]?){12,15}\d\b",
+    ]
+    .into_iter()
+    .map(|re| Regex::new(re).unwrap())
+    .collect()
+});
+
+/// Masks sensitive substrings in output. Applied after filtering and
+/// grouping, so counts are computed on the original messages.
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// `None` when there is nothing to redact: neither the built-in patterns
+    /// nor any `custom` one.
+    pub fn new(builtin: bool, custom: &[Regex]) -> Option<Self> {
+        let mut patterns = if builtin { BUILTIN_REDACTIONS.clone() } else { Vec::new() };
+        patterns.extend(custom.iter().cloned());
+        (!patterns.is_empty()).then_some(Redactor { patterns })
+    }
+
+    /// Masks every match of the patterns in `s`.
+    pub fn redact(&self, s: &mut String) {
+        for re in &self.patterns {
+            if re.is_match(s) {
+                *s = re.replace_all(s, REDACTED).into_owned();
+            }
+        }
+    }
+
+    /// Masks the messages, keys and samples in `stats`.
+    pub fn redact_stats(&self, stats: &mut LogStats) {
+        for err in &mut stats.top_errors {
+            self.redact(&mut err.message);
+        }
+        for err in stats.top_by_level.iter_mut().flatten().flat_map(|l| &mut l.top) {
+            self.redact(&mut err.message);
+        }
+        for owner in stats.owners.iter_mut().flatten() {
+            if let Some(message) = &mut owner.top_message {
+                self.redact(message);
+            }
+            for group in &mut owner.groups {
+                self.redact(&mut group.message);
+            }
+        }
+        for value in stats.group_counts.iter_mut().flat_map(|g| &mut g.counts) {
+            self.redact(&mut value.value);
+        }
+        for entry in stats.error_contexts.iter_mut().flatten().flat_map(|c| &mut c.entries) {
+            self.redact(&mut entry.message);
+        }
+        for query in stats.slow_queries.iter_mut().flatten() {
+            self.redact(&mut query.template);
+        }
+        for pair in stats.correlated_pairs.iter_mut().flatten() {
+            self.redact(&mut pair.first);
+            self.redact(&mut pair.then);
+        }
+        for sample in stats.alerts.iter_mut().flatten().filter_map(|a| a.sample.as_mut()) {
+            self.redact(sample);
+        }
+        for storm in stats.retry_storms.iter_mut().flatten() {
+            self.redact(&mut storm.message);
+        }
+        for key in stats.sessions.iter_mut().flat_map(|s| &mut s.largest).filter_map(|s| s.key.as_mut()) {
+            self.redact(key);
+        }
+    }
+}
+
+
+
+/* =========================
+   Gates
+   ========================= */
+
+/// Exit code when a `--fail-on` gate trips.
+pub const GATE_FAILURE_EXIT: i32 = 3;
+
+/// Metrics a `--fail-on` gate can test.
+pub const GATE_METRICS: &[&str] = &[
+    "total",
+    "errors",
+    "warnings",
+    "parse_failures",
+    "error_rate",
+    "unassigned_errors",
+];
+
+/// `--fail-on`: fails the run when a metric crosses a threshold.
+#[derive(Debug, Clone)]
+pub struct Gate {
+    /// The gate as written, without whitespace.
+    pub expr: String,
+    /// One of `GATE_METRICS`.
+    pub metric: String,
+    /// `>=`, `<=`, `==`, `>` or `<`.
+    pub op: String,
+    /// The value the metric is compared with.
+    pub threshold: f64,
+}
+
+/// The value of one of `GATE_METRICS` in `stats`; `0` for an unknown name.
+pub fn metric_value(stats: &LogStats, metric: &str) -> f64 {
+    let count = |level: &str| stats.by_level.get(level).copied().unwrap_or(0) as f64;
+
+    match metric {
+        "total" => stats.total_entries as f64,
+        "errors" => error_count(&stats.by_level) as f64,
+        "warnings" => count("Warning"),
+        "parse_failures" => stats.quality.unparsed_lines as f64,
+        "error_rate" if stats.total_entries > 0 => error_count(&stats.by_level) as f64 / stats.total_entries as f64,
+        "unassigned_errors" => stats
+            .owners
+            .iter()
+            .flatten()
+            .filter(|o| o.owner == UNASSIGNED_OWNER)
+            .map(|o| o.errors as f64)
+            .sum(),
+        _ => 0.0,
+    }
+}
+
+/// `--alert-pattern`: a message pattern and how often it may appear.
+#[derive(Debug, Clone)]
+pub struct AlertPattern {
+    /// The pattern as written.
+    pub spec: String,
+    /// Matches the messages; escaped unless written as `re:REGEX`.
+    pub regex: Regex,
+    /// Highest count that does not trigger the alert.
+    pub max: usize,
+}
+
+/// How often an `AlertPattern` matched.
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    /// The pattern as written.
+    pub pattern: String,
+    /// Highest count that does not trigger the alert.
+    pub max: usize,
+    /// Number of matching entries.
+    pub count: usize,
+    /// `count` is above `max`.
+    pub triggered: bool,
+    /// Timestamp of the first match.
+    pub first_seen: Option<String>,
+    /// Timestamp of the last match.
+    pub last_seen: Option<String>,
+    /// Line number of the first matching entry.
+    pub sample_line: Option<usize>,
+    /// Message of the first matching entry.
+    pub sample: Option<String>,
+}
+
+/// Counts the messages matching each `--alert-pattern` in one pass. Every
+/// pattern gets a row, including those that never matched.
+pub fn check_alerts(entries: &[LogEntry], patterns: &[AlertPattern]) -> Vec<Alert> {
+    let set = RegexSet::new(patterns.iter().map(|p| p.regex.as_str())).unwrap();
+    let mut matches: Vec<(usize, Span, Option<&LogEntry>)> = patterns.iter().map(|_| Default::default()).collect();
+
+    for entry in entries {
+        for i in set.matches(&entry.message).iter() {
+            let (count, span, sample) = &mut matches[i];
+            *count += 1;
+            span.add(entry);
+            sample.get_or_insert(entry);
+        }
+    }
+
+    patterns
+        .iter()
+        .zip(matches)
+        .map(|(pattern, (count, span, sample))| Alert {
+            pattern: pattern.spec.clone(),
+            max: pattern.max,
+            count,
+            triggered: count > pattern.max,
+            first_seen: span.first.map(|e| e.timestamp.clone()),
+            last_seen: span.last.map(|e| e.timestamp.clone()),
+            sample_line: sample.map(|e| e.line),
+            sample: sample.map(|e| e.message.clone()),
+        })
+        .collect()
+}
+
+/// Describes each violated `--require-level` / `--forbid-level` assertion.
+pub fn failed_level_assertions(
+    stats: &LogStats,
+    required: &[LogLevel],
+    forbidden: &[LogLevel],
+) -> Vec<String> {
+    let count = |level: &LogLevel| stats.by_level.get(&format!("{:?}", level)).copied().unwrap_or(0);
+
+    let missing = required
+        .iter()
+        .filter(|l| count(l) == 0)
+        .map(|l| format!("required level {:?} is absent", l));
+    let present = forbidden
+        .iter()
+        .filter(|l| count(l) > 0)
+        .map(|l| format!("forbidden level {:?} is present ({} entries)", l, count(l)));
+
+    missing.chain(present).collect()
+}
+
+/// Returns the gates whose condition holds, with the actual metric value.
+pub fn failed_gates<'a>(stats: &LogStats, gates: &'a [Gate]) -> Vec<(&'a Gate, f64)> {
+    gates
+        .iter()
+        .map(|gate| (gate, metric_value(stats, &gate.metric)))
+        .filter(|(gate, value)| match gate.op.as_str() {
+            ">=" => *value >= gate.threshold,
+            "<=" => *value <= gate.threshold,
+            "==" => *value == gate.threshold,
+            ">" => *value > gate.threshold,
+            _ => *value < gate.threshold,
+        })
+        .collect()
+}
+
+
+
+/* =========================
+   Time series
+   ========================= */
+
+/// Where bucket boundaries fall for `--rollup` and `--window`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BucketAlign {
+    /// Multiples of the width since the Unix epoch: `5m` gives :00, :05, :10...
+    Clock,
+    /// Offsets from the first timestamped entry.
+    FirstEntry,
+}
+
+/// The instant buckets are counted from.
+pub fn bucket_origin(entries: &[LogEntry], align: BucketAlign) -> NaiveDateTime {
+    let epoch = DateTime::UNIX_EPOCH.naive_utc();
+    match align {
+        BucketAlign::Clock => epoch,
+        BucketAlign::FirstEntry => entries.iter().find_map(|e| e.datetime).unwrap_or(epoch),
+    }
+}
+
+/// Start of the bucket containing `dt`, with buckets laid out every `width`
+/// from `origin`.
+pub fn bucket_start(dt: NaiveDateTime, width: TimeDelta, origin: NaiveDateTime) -> NaiveDateTime {
+    let width = width.num_seconds().max(1);
+    let offset = (dt - origin).num_seconds();
+
+    origin + TimeDelta::seconds(offset - offset.rem_euclid(width))
+}
+
+/// Start of the bucket containing `dt` on the wall clock of `tz`, so that
+/// `1d` buckets are local days of 23 or 25 hours around DST changes. An
+/// hour repeated when clocks go back yields two buckets, told apart by
+/// their offset; a bucket starting inside the hour skipped when clocks go
+/// forward starts at the change instead.
+pub(crate) fn local_bucket_start(dt: NaiveDateTime, width: TimeDelta, tz: Tz) -> DateTime<FixedOffset> {
+    let local = tz.from_utc_datetime(&dt);
+    let floor = bucket_start(local.naive_local(), width, DateTime::UNIX_EPOCH.naive_utc());
+
+    let start = match tz.from_local_datetime(&floor) {
+        LocalResult::Single(start) => start,
+        LocalResult::Ambiguous(earlier, later) => if later <= local { later } else { earlier },
+        LocalResult::None => {
+            let after = TimeDelta::seconds(local.offset().fix().local_minus_utc().into());
+            let before = tz.offset_from_utc_datetime(&(floor - after)).fix();
+            tz.from_utc_datetime(&(floor - TimeDelta::seconds(before.local_minus_utc().into())))
+        }
+    };
+    start.fixed_offset()
+}
+
+/// One bucket and level of a `--rollup`.
+#[derive(Debug, Serialize)]
+pub struct RollupRow {
+    /// Bucket start, formatted for its width.
+    pub bucket: String,
+    /// Level name.
+    pub level: String,
+    /// Entries of the level in the bucket.
+    pub count: usize,
+}
+
+/// `local_bucket_start` with `tz`, `bucket_start` in UTC otherwise.
+pub fn zoned_bucket_start(
+    dt: NaiveDateTime,
+    width: TimeDelta,
+    origin: NaiveDateTime,
+    tz: Option<Tz>,
+) -> DateTime<FixedOffset> {
+    match tz {
+        Some(tz) => local_bucket_start(dt, width, tz),
+        None => bucket_start(dt, width, origin).and_utc().fixed_offset(),
+    }
+}
+
+/// One row per (bucket, level) pair, in chronological order. Entries
+/// without a parsed timestamp cannot be placed and are skipped. With `tz`,
+/// buckets follow that zone's wall clock instead of being laid out from
+/// `origin`, and are labelled in local time with their offset.
+pub fn rollup(entries: &[LogEntry], width: TimeDelta, origin: NaiveDateTime, tz: Option<Tz>) -> Vec<RollupRow> {
+    let mut counts: BTreeMap<(DateTime<FixedOffset>, String), usize> = BTreeMap::new();
+
+    for entry in entries {
+        if let Some(dt) = entry.datetime {
+            let start = zoned_bucket_start(dt, width, origin, tz);
+            *counts.entry((start, format!("{:?}", entry.level))).or_insert(0) += 1;
+        }
+    }
+
+    let label = if tz.is_some() { "%Y-%m-%d %H:%M:%S%:z" } else { "%Y-%m-%d %H:%M:%S" };
+    counts
+        .into_iter()
+        .map(|((bucket, level), count)| RollupRow {
+            bucket: bucket.format(label).to_string(),
+            level,
+            count,
+        })
+        .collect()
+}
+
+/// One point of a `--timeseries`.
+#[derive(Debug, Serialize)]
+pub struct SeriesPoint {
+    /// RFC 3339, with the `--tz` offset if any.
+    pub bucket_start: String,
+    /// Level name.
+    pub level: String,
+    /// Entries of the level in the bucket.
+    pub count: usize,
+}
+
+/// Like `rollup`, but dense for plotting: every bucket from the first
+/// timestamped entry's to the last one's, each with a point for every level
+/// of `LogLevel::ALL` and any pseudo-level seen, zero counts included.
+pub fn timeseries(entries: &[LogEntry], width: TimeDelta, origin: NaiveDateTime, tz: Option<Tz>) -> Vec<SeriesPoint> {
+    let mut counts: HashMap<(DateTime<FixedOffset>, String), usize> = HashMap::new();
+    let mut levels: BTreeSet<String> = LogLevel::ALL.iter().map(|level| format!("{:?}", level)).collect();
+    let mut range: Option<(NaiveDateTime, NaiveDateTime)> = None;
+    for entry in entries {
+        let Some(dt) = entry.datetime else { continue };
+        let level = format!("{:?}", entry.level);
+        *counts.entry((zoned_bucket_start(dt, width, origin, tz), level.clone())).or_insert(0) += 1;
+        levels.insert(level);
+        range = Some(range.map_or((dt, dt), |(first, last)| (first.min(dt), last.max(dt))));
+    }
+    let Some((first, last)) = range else {
+        return Vec::new();
+    };
+
+    let end = zoned_bucket_start(last, width, origin, tz);
+    let mut bucket = zoned_bucket_start(first, width, origin, tz);
+    let mut points = Vec::new();
+    loop {
+        for level in &levels {
+            points.push(SeriesPoint {
+                bucket_start: bucket.to_rfc3339(),
+                level: level.clone(),
+                count: counts.get(&(bucket, level.clone())).copied().unwrap_or(0),
+            });
+        }
+        if bucket >= end {
+            return points;
+        }
+        bucket = zoned_bucket_start(bucket.naive_utc() + width, width, origin, tz);
+    }
+}
+
+
+/// One bucket of a `--bucket` histogram.
+#[derive(Debug, Serialize)]
+pub struct VolumeBucket {
+    /// RFC 3339, with the `--tz` offset if any.
+    pub bucket_start: String,
+    /// Entries in the bucket.
+    pub total: usize,
+    /// Every level of `LogLevel::ALL` and any pseudo-level seen in the log,
+    /// zero counts included.
+    pub by_level: BTreeMap<String, usize>,
+}
+
+/// `timeseries` grouped by bucket: how many entries each bucket holds, in
+/// total and per level, empty buckets included.
+pub fn volume_histogram(
+    entries: &[LogEntry],
+    width: TimeDelta,
+    origin: NaiveDateTime,
+    tz: Option<Tz>,
+) -> Vec<VolumeBucket> {
+    let mut buckets: Vec<VolumeBucket> = Vec::new();
+    for point in timeseries(entries, width, origin, tz) {
+        match buckets.last_mut() {
+            Some(bucket) if bucket.bucket_start == point.bucket_start => {
+                bucket.total += point.count;
+                bucket.by_level.insert(point.level, point.count);
+            }
+            _ => buckets.push(VolumeBucket {
+                bucket_start: point.bucket_start,
+                total: point.count,
+                by_level: BTreeMap::from([(point.level, point.count)]),
+            }),
+        }
+    }
+    buckets
+}
+
+/// The busiest bucket of a `PeakReport`.
+#[derive(Debug, Serialize)]
+pub struct Peak {
+    /// Bucket start, `%Y-%m-%d %H:%M:%S`.
+    pub start: String,
+    /// Entries in the bucket.
+    pub count: usize,
+    /// Average entries per second over the bucket.
+    pub per_second: f64,
+}
+
+/// `--peak-report`: the busiest moments of the log.
+#[derive(Debug, Serialize)]
+pub struct PeakReport {
+    /// Bucket width in seconds.
+    pub resolution_seconds: i64,
+    /// Busiest bucket over all entries; `None` without timestamps.
+    pub overall: Option<Peak>,
+    /// Busiest bucket of each level.
+    pub by_level: BTreeMap<String, Peak>,
+}
+
+/// The clock-aligned bucket of `width` holding the most entries, overall
+/// and for each level. Ties go to the earliest bucket; entries without a
+/// parsed timestamp are not counted.
+pub fn peak_report(entries: &[LogEntry], width: TimeDelta) -> PeakReport {
+    let origin = DateTime::UNIX_EPOCH.naive_utc();
+    let mut overall: HashMap<NaiveDateTime, usize> = HashMap::new();
+    let mut by_level: HashMap<String, HashMap<NaiveDateTime, usize>> = HashMap::new();
+
+    for entry in entries {
+        let Some(dt) = entry.datetime else { continue };
+        let start = bucket_start(dt, width, origin);
+        *overall.entry(start).or_insert(0) += 1;
+        *by_level.entry(format!("{:?}", entry.level)).or_default().entry(start).or_insert(0) += 1;
+    }
+
+    let seconds = width.num_seconds().max(1);
+    let peak = |counts: HashMap<NaiveDateTime, usize>| {
+        counts
+            .into_iter()
+            .max_by_key(|&(start, count)| (count, Reverse(start)))
+            .map(|(start, count)| Peak {
+                start: start.format("%Y-%m-%d %H:%M:%S").to_string(),
+                count,
+                per_second: count as f64 / seconds as f64,
+            })
+    };
+
+    PeakReport {
+        resolution_seconds: seconds,
+        overall: peak(overall),
+        by_level: by_level
+            .into_iter()
+            .filter_map(|(level, counts)| Some((level, peak(counts)?)))
+            .collect(),
+    }
+}
+
+/// Files `SplitWriter` keeps open at once. Past that the least recently
+/// written one is closed, and reopened for appending if its bucket comes up
+/// again, so any number of buckets fits in a few file descriptors.
+pub(crate) const SPLIT_OPEN_FILES: usize = 64;
+
+/// One file written by `split_by_time`.
+#[derive(Debug, Serialize)]
+pub struct SplitFile {
+    /// Path of the file.
+    pub file: String,
+    /// Entries copied into it.
+    pub entries: usize,
+    /// Lines copied into it, continuation lines included.
+    pub lines: usize,
+}
+
+/// One file per bucket under `dir`, named after the bucket and created on
+/// its first line.
+pub(crate) struct SplitWriter {
+    dir: PathBuf,
+    /// Least recently written first.
+    open: Vec<(String, BufWriter<File>)>,
+    files: BTreeMap<String, SplitFile>,
+}
+
+impl SplitWriter {
+    /// Writes into `dir`, which must exist.
+    pub fn new(dir: &std::path::Path) -> Self {
+        SplitWriter { dir: dir.to_path_buf(), open: Vec::new(), files: BTreeMap::new() }
+    }
+
+    /// `starts_entry` marks the first line of an entry, for the counts.
+    pub fn write_line(&mut self, bucket: &str, line: &str, starts_entry: bool) -> std::io::Result<()> {
+        if self.open.last().is_none_or(|(b, _)| b != bucket) {
+            let file = match self.open.iter().position(|(b, _)| b == bucket) {
+                Some(index) => self.open.remove(index),
+                None => {
+                    if self.open.len() >= SPLIT_OPEN_FILES {
+                        self.open.remove(0).1.flush()?;
+                    }
+                    let path = self.dir.join(format!("{}.log", bucket));
+                    let file = if self.files.contains_key(bucket) {
+                        std::fs::OpenOptions::new().append(true).open(&path)?
+                    } else {
+                        File::create(&path)?
+                    };
+                    self.files.entry(bucket.to_string()).or_insert(SplitFile {
+                        file: path.display().to_string(),
+                        entries: 0,
+                        lines: 0,
+                    });
+                    (bucket.to_string(), BufWriter::new(file))
+                }
+            };
+            self.open.push(file);
+        }
+
+        if let (Some((_, out)), Some(counts)) = (self.open.last_mut(), self.files.get_mut(bucket)) {
+            writeln!(out, "{}", line)?;
+            counts.lines += 1;
+            counts.entries += usize::from(starts_entry);
+        }
+        Ok(())
+    }
+
+    /// Flushes the open files and lists every file written, by bucket name.
+    pub fn finish(self) -> std::io::Result<Vec<SplitFile>> {
+        for (_, mut out) in self.open {
+            out.flush()?;
+        }
+        Ok(self.files.into_values().collect())
+    }
+}
+
+/// Copies the raw lines of the entries in `buckets` (first line number →
+/// bucket name) from `path`, read as `encoding`, into one file per bucket.
+/// An entry runs up to the next line in `starts`, the first lines of every
+/// parsed entry, so continuation lines go with their entry; lines of
+/// entries not in `buckets` are left out.
+pub fn split_by_time(
+    path: &std::path::Path,
+    encoding: Encoding,
+    starts: &[usize],
+    buckets: &HashMap<usize, String>,
+    dir: &std::path::Path,
+    redactor: Option<&Redactor>,
+) -> std::io::Result<Vec<SplitFile>> {
+    std::fs::create_dir_all(dir)?;
+    let mut writer = SplitWriter::new(dir);
+    let mut starts = starts.iter().peekable();
+    let mut current = None;
+
+    for (index, line) in open_input(path, encoding)?.lines().enumerate() {
+        let mut line = line?;
+        let number = index + 1;
+        let mut starts_entry = false;
+        while let Some(start) = starts.next_if(|&&start| start <= number) {
+            current = buckets.get(start);
+            starts_entry = current.is_some();
+        }
+        let Some(bucket) = current else { continue };
+        if let Some(redactor) = redactor {
+            redactor.redact(&mut line);
+        }
+        writer.write_line(bucket, &line, starts_entry)?;
+    }
+
+    writer.finish()
+}
+
+
+
+/* =========================
+   Windowed analysis
+   ========================= */
+
+/// The most frequent error message of a window.
+#[derive(Debug, Serialize)]
+pub struct WindowTopError {
+    /// The message.
+    pub message: String,
+    /// How often it occurred in the window.
+    pub count: usize,
+}
+
+/// `--window`: the counts of one fixed-width window.
+#[derive(Debug, Serialize)]
+pub struct WindowStats {
+    /// Window start.
+    pub start: String,
+    /// Window end.
+    pub end: String,
+    /// The log starts after or ends before the window boundary.
+    pub partial: bool,
+    /// Entries in the window.
+    pub total: usize,
+    /// Entries per level.
+    pub by_level: BTreeMap<String, usize>,
+    /// Share of error entries.
+    pub error_rate: f64,
+    /// Most frequent error message, if any.
+    pub top_error: Option<WindowTopError>,
+    /// Longest time between consecutive entries inside the window.
+    pub biggest_gap_seconds: Option<i64>,
+}
+
+/// Accumulates one window at a time, so only the current window's error
+/// frequencies are held in memory.
+pub(crate) struct WindowBuilder {
+    start: NaiveDateTime,
+    total: usize,
+    by_level: BTreeMap<String, usize>,
+    errors: HashMap<String, usize>,
+    last_seen: Option<NaiveDateTime>,
+    biggest_gap: Option<TimeDelta>,
+}
+
+impl WindowBuilder {
+    /// An empty window starting at `start`.
+    pub fn new(start: NaiveDateTime) -> Self {
+        WindowBuilder {
+            start,
+            total: 0,
+            by_level: BTreeMap::new(),
+            errors: HashMap::new(),
+            last_seen: None,
+            biggest_gap: None,
+        }
+    }
+
+    /// Counts `entry` into the window.
+    pub fn add(&mut self, entry: &LogEntry) {
+        self.total += 1;
+        *self.by_level.entry(format!("{:?}", entry.level)).or_insert(0) += 1;
+        if entry.level.is_error() {
+            *self.errors.entry(entry.message.clone()).or_insert(0) += 1;
+        }
+        if let Some(dt) = entry.datetime {
+            if let Some(last) = self.last_seen {
+                let gap = dt - last;
+                if self.biggest_gap.is_none_or(|g| gap > g) {
+                    self.biggest_gap = Some(gap);
+                }
+            }
+            self.last_seen = Some(self.last_seen.map_or(dt, |l| l.max(dt)));
+        }
+    }
+
+    /// The stats of the window ending at `end`; `partial` when the log does
+    /// not cover all of it.
+    pub fn finish(self, end: NaiveDateTime, partial: bool) -> WindowStats {
+        let errors = error_count(&self.by_level);
+        let top_error = self
+            .errors
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+            .map(|(message, count)| WindowTopError { message, count });
+
+        WindowStats {
+            start: self.start.format("%Y-%m-%d %H:%M:%S").to_string(),
+            end: end.format("%Y-%m-%d %H:%M:%S").to_string(),
+            partial,
+            total: self.total,
+            error_rate: if self.total == 0 { 0.0 } else { errors as f64 / self.total as f64 },
+            by_level: self.by_level,
+            top_error,
+            biggest_gap_seconds: self.biggest_gap.map(|g| g.num_seconds()),
+        }
+    }
+}
+
+/// Consecutive windows of `width` laid out from `origin`, including empty
+/// ones between the first and last entry. Expects time-ordered entries (see
+/// `--sort-by-time`); an entry earlier than the current window is counted
+/// in the current window rather than reopening an old one.
+pub fn analyze_windows(entries: &[LogEntry], width: TimeDelta, origin: NaiveDateTime) -> Vec<WindowStats> {
+    let mut windows = Vec::new();
+    let mut current: Option<WindowBuilder> = None;
+    let (mut first, mut last): (Option<NaiveDateTime>, Option<NaiveDateTime>) = (None, None);
+
+    for entry in entries {
+        let Some(dt) = entry.datetime else { continue };
+        first = first.or(Some(dt));
+        last = Some(last.map_or(dt, |l| l.max(dt)));
+        let start = bucket_start(dt, width, origin);
+
+        match &mut current {
+            Some(window) if start <= window.start => window.add(entry),
+            _ => {
+                if let Some(window) = current.take() {
+                    let mut next = window.start + width;
+                    let partial = windows.is_empty() && first.is_some_and(|f| f > window.start);
+                    windows.push(window.finish(next, partial));
+                    while next < start {
+                        windows.push(WindowBuilder::new(next).finish(next + width, false));
+                        next += width;
+                    }
+                }
+                let mut window = WindowBuilder::new(start);
+                window.add(entry);
+                current = Some(window);
+            }
+        }
+    }
+
+    if let Some(window) = current {
+        let is_first = windows.is_empty();
+        let end = window.start + width;
+        let partial = (is_first && first.is_some_and(|f| f > window.start))
+            || last.is_some_and(|l| l < end - TimeDelta::seconds(1));
+        windows.push(window.finish(end, partial));
+    }
+
+    windows
+}
+
+/// Calendar date of a (UTC) timestamp, in `tz` when given.
+pub(crate) fn local_date(dt: NaiveDateTime, tz: Option<Tz>) -> NaiveDate {
+    match tz {
+        Some(tz) => dt.and_utc().with_timezone(&tz).date_naive(),
+        None => dt.date(),
+    }
+}
+
+/// One window per calendar day from the first to the last entry, days
+/// without entries included. Days are local dates, so with `--tz` a day
+/// spans 23 or 25 hours around DST changes. Like `analyze_windows` it
+/// expects time-ordered entries.
+pub fn analyze_days(entries: &[LogEntry], tz: Option<Tz>) -> Vec<WindowStats> {
+    let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let mut days = Vec::new();
+    let mut current: Option<(NaiveDate, WindowBuilder)> = None;
+
+    for entry in entries {
+        let Some(dt) = entry.datetime else { continue };
+        let date = local_date(dt, tz);
+
+        match &mut current {
+            Some((day, window)) if date <= *day => window.add(entry),
+            _ => {
+                if let Some((mut day, window)) = current.take() {
+                    let mut next = day.succ_opt().unwrap_or(day);
+                    days.push(window.finish(midnight(next), false));
+                    while next < date {
+                        day = next;
+                        next = day.succ_opt().unwrap_or(day);
+                        days.push(WindowBuilder::new(midnight(day)).finish(midnight(next), false));
+                    }
+                }
+                let mut window = WindowBuilder::new(midnight(date));
+                window.add(entry);
+                current = Some((date, window));
+            }
+        }
+    }
+
+    if let Some((day, window)) = current {
+        days.push(window.finish(midnight(day.succ_opt().unwrap_or(day)), false));
+    }
+
+    days
+}
+
+/// Value of a `--columns` entry: a built-in column or an extracted field
+/// (empty when the entry does not have it).
+pub fn entry_column(entry: &LogEntry, column: &str) -> String {
+    match column {
+        "line" => entry.line.to_string(),
+        "timestamp" => entry.timestamp.clone(),
+        "level" => format!("{:?}", entry.level),
+        "message" => entry.message.clone(),
+        field => entry.fields.get(field).cloned().unwrap_or_default(),
+    }
+}
+
+
+
+/* =========================
+   Query
+   ========================= */
+
+/// `--query`: a boolean expression of column tests, e.g.
+/// `level=error AND (message~"timeout" OR message~"refused") AND ts>2024-05-01`.
+#[derive(Debug, Clone)]
+pub enum Query {
+    /// Both sides match.
+    And(Box<Query>, Box<Query>),
+    /// Either side matches.
+    Or(Box<Query>, Box<Query>),
+    /// The inner query does not match.
+    Not(Box<Query>),
+    /// A single column test.
+    Test(QueryTest),
+}
+
+/// `COLUMN OP VALUE`, where the column is a built-in one (`ts` for
+/// `timestamp`) or a field.
+#[derive(Debug, Clone)]
+pub struct QueryTest {
+    /// Built-in column or field name.
+    pub column: String,
+    /// How the column is compared.
+    pub op: QueryOp,
+    /// What it is compared with.
+    pub value: QueryValue,
+}
+
+/// The operator of a `QueryTest`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryOp {
+    /// `=`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `~`, a regex match.
+    Match,
+    /// `!~`
+    NotMatch,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+/// The value side of a test, typed by its column and operator when parsed.
+#[derive(Debug, Clone)]
+pub enum QueryValue {
+    /// Compared as text, exactly.
+    Text(String),
+    /// For `~` and `!~`.
+    Regex(Regex),
+    /// For the `level` column, ordered by severity.
+    Level(LogLevel),
+    /// For the `ts` column.
+    Time(TimeBound),
+    /// Compared as numbers, for the ordering operators.
+    Number(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum QueryToken {
+    Open,
+    Close,
+    Op(QueryOp),
+    Word(String),
+    Quoted(String),
+}
+
+fn query_tokens(s: &str) -> Result<Vec<QueryToken>, String> {
+    const OPS: [(&str, QueryOp); 9] = [
+        ("==", QueryOp::Eq),
+        ("!=", QueryOp::Ne),
+        ("!~", QueryOp::NotMatch),
+        (">=", QueryOp::Ge),
+        ("<=", QueryOp::Le),
+        ("=", QueryOp::Eq),
+        ("~", QueryOp::Match),
+        (">", QueryOp::Gt),
+        ("<", QueryOp::Lt),
+    ];
+    let is_special = |c: char| c.is_whitespace() || "()\"'=!~<>".contains(c);
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { QueryToken::Open } else { QueryToken::Close });
+            rest = &rest[1..];
+        } else if c == '"' || c == '\'' {
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, q)) if q == c => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) if escaped == c || escaped == '\\' => text.push(escaped),
+                        Some((_, other)) => text.extend(['\\', other]),
+                        None => return Err(format!("unclosed {} in '{}'", c, s)),
+                    },
+                    Some((_, other)) => text.push(other),
+                    None => return Err(format!("unclosed {} in '{}'", c, s)),
+                }
+            };
+            tokens.push(QueryToken::Quoted(text));
+            rest = &rest[end..];
+        } else if let Some((op, kind)) = OPS.iter().find(|(op, _)| rest.starts_with(op)) {
+            tokens.push(QueryToken::Op(*kind));
+            rest = &rest[op.len()..];
+        } else if c == '!' {
+            return Err(format!("expected != or !~ in '{}'", s));
+        } else {
+            let end = rest.find(is_special).unwrap_or(rest.len());
+            tokens.push(QueryToken::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens: OR binds loosest, then AND, then NOT.
+struct QueryParser<'a> {
+    tokens: std::iter::Peekable<std::slice::Iter<'a, QueryToken>>,
+}
+
+impl QueryParser<'_> {
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.tokens.peek(), Some(QueryToken::Word(w)) if w.eq_ignore_ascii_case(keyword));
+        if found {
+            self.tokens.next();
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+        while self.keyword("OR") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.not()?;
+        while self.keyword("AND") {
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.keyword("NOT") {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        match self.tokens.next() {
+            Some(QueryToken::Open) => {
+                let query = self.or()?;
+                match self.tokens.next() {
+                    Some(QueryToken::Close) => Ok(query),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(QueryToken::Word(column)) => {
+                let Some(QueryToken::Op(op)) = self.tokens.next() else {
+                    return Err(format!("expected an operator after '{}'", column));
+                };
+                let value = match self.tokens.next() {
+                    Some(QueryToken::Word(value) | QueryToken::Quoted(value)) => value,
+                    _ => return Err(format!("expected a value after '{}'", column)),
+                };
+                query_test(column, *op, value).map(Query::Test)
+            }
+            Some(QueryToken::Close) => Err("unexpected ')'".to_string()),
+            Some(QueryToken::Quoted(text)) => Err(format!("expected a column before \"{}\"", text)),
+            Some(QueryToken::Op(_)) => Err("expected a column before the operator".to_string()),
+            None => Err("expected a test such as level=error".to_string()),
+        }
+    }
+}
+
+fn query_test(column: &str, op: QueryOp, value: &str) -> Result<QueryTest, String> {
+    let column = if column == "ts" { "timestamp" } else { column };
+    let ordered = matches!(op, QueryOp::Lt | QueryOp::Le | QueryOp::Gt | QueryOp::Ge);
+    let value = match op {
+        QueryOp::Match | QueryOp::NotMatch => QueryValue::Regex(parse_regex(value)?),
+        _ if column == "level" => QueryValue::Level(parse_level(value)?),
+        _ if column == "timestamp" && ordered => QueryValue::Time(value.parse()?),
+        _ if ordered => QueryValue::Number(
+            value
+                .parse()
+                .map_err(|_| format!("'{}' needs a number to compare {} with", value, column))?,
+        ),
+        _ => QueryValue::Text(value.to_string()),
+    };
+    Ok(QueryTest { column: column.to_string(), op, value })
+}
+
+/// Parses a query expression. Tests are `COLUMN OP VALUE` with `=`,
+/// `!=`, `~` / `!~` (regex), `<`, `<=`, `>`, `>=`; AND, OR and NOT (any case)
+/// and parentheses combine them. Values with spaces or operators are quoted.
+impl std::str::FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = query_tokens(s)?;
+        let mut parser = QueryParser { tokens: tokens.iter().peekable() };
+        let query = parser.or().map_err(|e| format!("{} in '{}'", e, s))?;
+        match parser.tokens.next() {
+            None => Ok(query),
+            Some(QueryToken::Close) => Err(format!("unexpected ')' in '{}'", s)),
+            Some(_) => Err(format!("expected AND or OR between tests in '{}'", s)),
+        }
+    }
+}
+
+impl Query {
+    /// Fixes relative times (`ts>2h`, `ts>yesterday`) to `now`, reading
+    /// times without an offset in `tz`.
+    pub fn resolve(mut self, now: DateTime<Utc>, tz: Option<Tz>) -> Self {
+        self.resolve_times(now, tz);
+        self
+    }
+
+    fn resolve_times(&mut self, now: DateTime<Utc>, tz: Option<Tz>) {
+        match self {
+            Query::And(a, b) | Query::Or(a, b) => {
+                a.resolve_times(now, tz);
+                b.resolve_times(now, tz);
+            }
+            Query::Not(query) => query.resolve_times(now, tz),
+            Query::Test(QueryTest { value: QueryValue::Time(bound), .. }) => {
+                *bound = TimeBound::Utc(bound.resolve(now, tz));
+            }
+            Query::Test(_) => {}
+        }
+    }
+
+    /// Whether `entry` satisfies the query.
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(entry) && b.matches(entry),
+            Query::Or(a, b) => a.matches(entry) || b.matches(entry),
+            Query::Not(query) => !query.matches(entry),
+            Query::Test(test) => test.matches(entry),
+        }
+    }
+}
+
+impl QueryTest {
+    /// Ordering tests fail on entries without a value to order: no
+    /// timestamp, a pseudo-level or a field that is not a number.
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        let ordering = match &self.value {
+            QueryValue::Regex(re) => {
+                return re.is_match(&entry_column(entry, &self.column)) == (self.op == QueryOp::Match);
+            }
+            QueryValue::Text(text) => {
+                return (entry_column(entry, &self.column) == *text) == (self.op == QueryOp::Eq);
+            }
+            QueryValue::Level(level) if matches!(self.op, QueryOp::Eq | QueryOp::Ne) => {
+                return (entry.level == *level) == (self.op == QueryOp::Eq);
+            }
+            QueryValue::Level(level) => {
+                entry.level.severity().zip(level.severity()).map(|(a, b)| a.cmp(&b))
+            }
+            QueryValue::Time(bound) => entry.datetime.map(|dt| dt.cmp(&bound.resolve(Utc::now(), None))),
+            QueryValue::Number(n) => {
+                entry_column(entry, &self.column).trim().parse::<f64>().ok().and_then(|v| v.partial_cmp(n))
+            }
+        };
+        ordering.is_some_and(|ordering| match self.op {
+            QueryOp::Lt => ordering.is_lt(),
+            QueryOp::Le => ordering.is_le(),
+            QueryOp::Gt => ordering.is_gt(),
+            QueryOp::Ge => ordering.is_ge(),
+            QueryOp::Eq => ordering.is_eq(),
+            QueryOp::Ne | QueryOp::Match | QueryOp::NotMatch => ordering.is_ne(),
+        })
+    }
+}
+
+
+
+/* =========================
+   Public API
+   ========================= */
+
+/// Reads log lines into `LogEntry` values, as the CLI does for a file.
+///
+/// `InputFormat::Auto` is resolved by sampling the file in `parse_file`;
+/// `parse_reader` has nothing to sample and uses the default pattern.
+pub struct Parser {
+    format: InputFormat,
+    pattern: Regex,
+    inherit_timestamp: bool,
+    max_line_bytes: Option<usize>,
+    required_fields: Vec<FieldRequirement>,
+    json_keys: Vec<JsonKey>,
+    multiline: bool,
+    time_format: Option<String>,
+    input_tz: Option<Tz>,
+    encoding: Encoding,
+}
+
+impl Parser {
+    /// A parser for `format` with the default pattern and options.
+    pub fn new(format: InputFormat) -> Self {
+        Parser {
+            format,
+            pattern: compile_pattern(None).expect("default pattern is valid"),
+            inherit_timestamp: false,
+            max_line_bytes: None,
+            required_fields: Vec::new(),
+            json_keys: Vec::new(),
+            multiline: false,
+            time_format: None,
+            input_tz: None,
+            encoding: Encoding::Auto,
+        }
+    }
+
+    /// Custom regex for the default format, as with `--pattern`.
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self, String> {
+        self.pattern = compile_pattern(Some(pattern))?;
+        Ok(self)
+    }
+
+    /// Attach lines without a timestamp to the previous entry.
+    pub fn inherit_timestamp(mut self, inherit: bool) -> Self {
+        self.inherit_timestamp = inherit;
+        self
+    }
+
+    /// Cut lines longer than `max` bytes.
+    pub fn max_line_bytes(mut self, max: usize) -> Self {
+        self.max_line_bytes = Some(max);
+        self
+    }
+
+    /// Reject JSON records that fail these checks.
+    pub fn require_fields(mut self, required: Vec<FieldRequirement>) -> Self {
+        self.required_fields = required;
+        self
+    }
+
+    /// Read timestamps with this strftime layout first, as `--time-format`.
+    pub fn time_format(mut self, format: &str) -> Result<Self, String> {
+        self.time_format = Some(parse_time_format(format)?);
+        Ok(self)
+    }
+
+    /// Read timestamps without an offset as local time in `tz`.
+    pub fn input_tz(mut self, tz: Tz) -> Self {
+        self.input_tz = Some(tz);
+        self
+    }
+
+    /// Read files in this encoding instead of detecting it, as `--encoding`.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Fold lines the default pattern does not match, such as stack frames,
+    /// into the previous entry's message, as with `--multiline`.
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Read the timestamp, level or message of JSON and logfmt records from
+    /// these keys.
+    pub fn json_keys(mut self, keys: Vec<JsonKey>) -> Self {
+        self.json_keys = keys;
+        self
+    }
+
+    fn line_parser(&self, format: InputFormat) -> LineParser {
+        LineParser::new(format, self.pattern.clone())
+            .required_fields(self.required_fields.clone())
+            .json_keys(self.json_keys.clone())
+            .multiline(self.multiline)
+            .time_format(self.time_format.clone())
+            .input_tz(self.input_tz)
+            .encoding(self.encoding)
+    }
+
+    /// Parses every entry of `reader`.
+    pub fn parse_reader(&self, reader: impl BufRead) -> Result<ParsedLog, ReadError> {
+        let format = match self.format {
+            InputFormat::Auto => InputFormat::Default,
+            format => format,
+        };
+        let lines = BoundedLines::new(reader, self.max_line_bytes).map(|line| {
+            let (bytes, truncated) = line?;
+            Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+        });
+        read_log(lines, &mut self.line_parser(format), None, self.inherit_timestamp, None)
+    }
+
+    /// Parses a file, which may be gzipped.
+    pub fn parse_file(&self, path: &std::path::Path) -> Result<ParsedLog, ReadError> {
+        let format = match self.format {
+            InputFormat::Auto => detect_format(path, &self.pattern, self.encoding),
+            format => format,
+        };
+        read_log_file(
+            path,
+            &mut self.line_parser(format),
+            None,
+            self.inherit_timestamp,
+            None,
+            self.max_line_bytes,
+        )
+    }
+
+    /// `parse_file` without collecting: each entry goes to `emit`, and the
+    /// returned `ParsedLog` has no entries.
+    pub fn parse_file_each(
+        &self,
+        path: &std::path::Path,
+        emit: impl FnMut(LogEntry),
+    ) -> Result<ParsedLog, ReadError> {
+        let format = match self.format {
+            InputFormat::Auto => detect_format(path, &self.pattern, self.encoding),
+            format => format,
+        };
+        let mut parser = self.line_parser(format);
+        read_log_file_each(path, &mut parser, None, self.inherit_timestamp, self.max_line_bytes, emit)
+    }
+}
+
+/// Computes `LogStats` for parsed entries with the CLI's defaults.
+#[derive(Debug, Clone)]
+pub struct Analyzer {
+    top: usize,
+    normalize: Option<Normalizer>,
+    approx: ApproxOptions,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Analyzer {
+            top: 5,
+            normalize: None,
+            approx: ApproxOptions { forced: false, capacity: 10_000, threshold: 1_000_000 },
+        }
+    }
+}
+
+impl Analyzer {
+    /// An analyzer with the defaults: top 5 errors, no normalization.
+    pub fn new() -> Self {
+        Analyzer::default()
+    }
+
+    /// Number of top error messages to keep.
+    pub fn top(mut self, n: usize) -> Self {
+        self.top = n;
+        self
+    }
+
+    /// Group top errors by normalized template, with the built-in rules.
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize.then(Normalizer::default);
+        self
+    }
+
+    /// Group top errors by template, with `normalizer`'s rules.
+    pub fn normalize_with(mut self, normalizer: Normalizer) -> Self {
+        self.normalize = Some(normalizer);
+        self
+    }
+
+    /// When to switch to approximate top errors.
+    pub fn approx(mut self, approx: ApproxOptions) -> Self {
+        self.approx = approx;
+        self
+    }
+
+    /// The stats of `entries`.
+    pub fn analyze(&self, entries: &[LogEntry]) -> LogStats {
+        analyze_logs(entries, self.top, self.approx, self.normalize.as_ref())
+    }
+
+    /// For entries that arrive one at a time, as from `Parser::parse_file_each`.
+    pub fn builder(&self) -> StatsBuilder {
+        StatsBuilder::new(self.top, self.approx, self.normalize.clone())
+    }
+}
+
+/// The entry filters of `--errors-only`, `--level`, `--min-level`, `--search`,
+/// `--search-regex`, `--exclude-contains`, `--exclude-regex`, `--since` and
+/// `--until`, and `--query`. Text matching is case-insensitive; regexes
+/// match as written.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    levels: Vec<LogLevel>,
+    /// Lowest `LogLevel::severity` kept; pseudo-levels never pass it.
+    min_severity: Option<u8>,
+    search: Option<String>,
+    search_regex: Option<Regex>,
+    exclusions: Vec<String>,
+    exclusion_regexes: Vec<Regex>,
+    query: Option<Query>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+}
+
+impl Filter {
+    /// A filter keeping errors only with `errors_only`, entries containing
+    /// `search`, and dropping those containing any of `exclude_contains`.
+    pub fn new(errors_only: bool, search: Option<&str>, exclude_contains: &[String]) -> Self {
+        Filter {
+            levels: Vec::new(),
+            min_severity: LogLevel::Error.severity().filter(|_| errors_only),
+            search: search.map(str::to_lowercase),
+            search_regex: None,
+            exclusions: exclude_contains.iter().map(|s| s.to_lowercase()).collect(),
+            exclusion_regexes: Vec::new(),
+            query: None,
+            since: None,
+            until: None,
+        }
+    }
+
+    /// Keeps only entries at one of `levels` (any when empty) and at or
+    /// above `min_level`, on top of `errors_only`.
+    pub fn levels(mut self, levels: &[LogLevel], min_level: Option<&LogLevel>) -> Self {
+        self.levels = levels.to_vec();
+        self.min_severity = self.min_severity.max(min_level.and_then(LogLevel::severity));
+        self
+    }
+
+    /// Keeps only entries whose message, timestamp or level `search` matches,
+    /// as for the text search, and drops those whose message matches one of
+    /// `exclude`.
+    pub fn regexes(mut self, search: Option<&Regex>, exclude: &[Regex]) -> Self {
+        self.search_regex = search.cloned();
+        self.exclusion_regexes = exclude.to_vec();
+        self
+    }
+
+    /// Keeps only entries the query matches; see `Query::resolve` for its
+    /// relative times.
+    pub fn query(mut self, query: Option<Query>) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Keeps only entries timestamped at or after `since` and before `until`
+    /// (both UTC). Once either is set, entries without a timestamp are dropped.
+    pub fn between(mut self, since: Option<NaiveDateTime>, until: Option<NaiveDateTime>) -> Self {
+        self.since = since;
+        self.until = until;
+        self
+    }
+
+    /// Passes the level filters, the searches, the query and the time window.
+    pub fn includes(&self, entry: &LogEntry) -> bool {
+        self.in_window(entry)
+            && (self.levels.is_empty() || self.levels.contains(&entry.level))
+            && self.min_severity.is_none_or(|min| entry.level.severity().is_some_and(|s| s >= min))
+            && self.search.as_ref().is_none_or(|needle| {
+                entry.message.to_lowercase().contains(needle)
+                    || entry.timestamp.to_lowercase().contains(needle)
+                    || format!("{:?}", entry.level).to_lowercase().contains(needle)
+            })
+            && self.search_regex.as_ref().is_none_or(|re| {
+                re.is_match(&entry.message)
+                    || re.is_match(&entry.timestamp)
+                    || re.is_match(&format!("{:?}", entry.level))
+            })
+            && self.query.as_ref().is_none_or(|query| query.matches(entry))
+    }
+
+    fn in_window(&self, entry: &LogEntry) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        entry.datetime.is_some_and(|datetime| {
+            self.since.is_none_or(|since| datetime >= since) && self.until.is_none_or(|until| datetime < until)
+        })
+    }
+
+    /// Index of the first exclusion that drops the entry, if any: the
+    /// substrings first, then the regexes.
+    pub fn excluded_by(&self, entry: &LogEntry) -> Option<usize> {
+        if !self.has_exclusions() {
+            return None;
+        }
+        let message = entry.message.to_lowercase();
+        self.exclusions.iter().position(|needle| message.contains(needle)).or_else(|| {
+            let i = self.exclusion_regexes.iter().position(|re| re.is_match(&entry.message))?;
+            Some(self.exclusions.len() + i)
+        })
+    }
+
+    /// Whether `entry` passes every inclusion and no exclusion.
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.includes(entry) && self.excluded_by(entry).is_none()
+    }
+
+    /// Whether any exclusion is set, so excluded entries can be reported.
+    pub fn has_exclusions(&self) -> bool {
+        !self.exclusions.is_empty() || !self.exclusion_regexes.is_empty()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn iso_8601_variants_parse_to_the_same_datetime() {
+        let expected = datetime("2024-01-01 12:00:00");
+        for s in [
+            "2024-01-01 12:00:00",
+            "2024-01-01  12:00:00",
+            "2024-01-01T12:00:00",
+            "2024-01-01T12:00:00Z",
+            "2024-01-01 12:00:00Z",
+            "2024-01-01T12:00:00.000",
+            "2024-01-01T12:00:00.000000Z",
+            "2024-01-01T12:00:00+00:00",
+            "2024-01-01T12:00:00+0000",
+            "2024-01-01T14:00:00+02:00",
+            "2024-01-01T07:00:00-05:00",
+            "2024-01-01T14:00:00.000+02:00",
+        ] {
+            assert_eq!(parse_timestamp(s), Some(expected), "{}", s);
+        }
+    }
+
+    #[test]
+    fn fractional_seconds_are_kept() {
+        assert_eq!(
+            parse_timestamp("2024-01-01T12:00:00.250Z"),
+            Some(datetime("2024-01-01 12:00:00.250"))
+        );
+    }
+
+    #[test]
+    fn default_pattern_reads_iso_8601_timestamps() {
+        let pattern = compile_pattern(None).unwrap();
+        for line in [
+            "2024-01-01 12:00:00 [ERROR] disk full",
+            "2024-01-01T12:00:00Z [ERROR] disk full",
+            "2024-01-01T12:00:00.000Z [ERROR] disk full",
+            "2024-01-01T13:00:00+01:00 [ERROR] disk full",
+        ] {
+            let entry = parse_log_line(line, &pattern, false).expect(line);
+            assert_eq!(entry.datetime, Some(datetime("2024-01-01 12:00:00")), "{}", line);
+            assert_eq!(entry.level, LogLevel::Error);
+            assert_eq!(entry.message, "disk full");
+        }
+    }
+
+    fn berlin_bucket(utc: &str, width: &str) -> String {
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+        let width = parse_duration(width).unwrap();
+        local_bucket_start(datetime(utc), width, tz).to_rfc3339()
+    }
+
+    #[test]
+    fn spring_forward_day_is_23_hours() {
+        // Clocks go from 02:00 to 03:00 local, at 01:00 UTC.
+        assert_eq!(berlin_bucket("2024-03-30 23:00:00", "1d"), "2024-03-31T00:00:00+01:00");
+        assert_eq!(berlin_bucket("2024-03-31 21:59:59", "1d"), "2024-03-31T00:00:00+01:00");
+        assert_eq!(berlin_bucket("2024-03-31 22:00:00", "1d"), "2024-04-01T00:00:00+02:00");
+
+        let start = DateTime::parse_from_rfc3339("2024-03-31T00:00:00+01:00").unwrap();
+        let end = DateTime::parse_from_rfc3339("2024-04-01T00:00:00+02:00").unwrap();
+        assert_eq!(end - start, TimeDelta::hours(23));
+    }
+
+    #[test]
+    fn fall_back_day_is_25_hours() {
+        // Clocks go from 03:00 back to 02:00 local, at 01:00 UTC.
+        assert_eq!(berlin_bucket("2024-10-26 22:00:00", "1d"), "2024-10-27T00:00:00+02:00");
+        assert_eq!(berlin_bucket("2024-10-27 22:59:59", "1d"), "2024-10-27T00:00:00+02:00");
+        assert_eq!(berlin_bucket("2024-10-27 23:00:00", "1d"), "2024-10-28T00:00:00+01:00");
+
+        let start = DateTime::parse_from_rfc3339("2024-10-27T00:00:00+02:00").unwrap();
+        let end = DateTime::parse_from_rfc3339("2024-10-28T00:00:00+01:00").unwrap();
+        assert_eq!(end - start, TimeDelta::hours(25));
+    }
+
+    #[test]
+    fn repeated_hour_yields_two_buckets() {
+        assert_eq!(berlin_bucket("2024-10-26 23:59:59", "1h"), "2024-10-27T01:00:00+02:00");
+        assert_eq!(berlin_bucket("2024-10-27 00:00:00", "1h"), "2024-10-27T02:00:00+02:00");
+        assert_eq!(berlin_bucket("2024-10-27 00:59:59", "1h"), "2024-10-27T02:00:00+02:00");
+        assert_eq!(berlin_bucket("2024-10-27 01:00:00", "1h"), "2024-10-27T02:00:00+01:00");
+        assert_eq!(berlin_bucket("2024-10-27 01:59:59", "1h"), "2024-10-27T02:00:00+01:00");
+        assert_eq!(berlin_bucket("2024-10-27 02:00:00", "1h"), "2024-10-27T03:00:00+01:00");
+    }
+
+    #[test]
+    fn skipped_hour_has_no_bucket() {
+        assert_eq!(berlin_bucket("2024-03-31 00:59:59", "1h"), "2024-03-31T01:00:00+01:00");
+        assert_eq!(berlin_bucket("2024-03-31 01:00:00", "1h"), "2024-03-31T03:00:00+02:00");
+        // The 02:00 bucket of 2h would start inside the skipped hour.
+        assert_eq!(berlin_bucket("2024-03-31 01:30:00", "2h"), "2024-03-31T03:00:00+02:00");
+        assert_eq!(berlin_bucket("2024-03-31 00:30:00", "2h"), "2024-03-31T00:00:00+01:00");
+    }
+
+    fn cef_header(record: &str) -> Vec<String> {
+        split_cef_header(record.strip_prefix("CEF:").unwrap())
+    }
+
+    #[test]
+    fn cef_header_unescapes_pipes_and_backslashes() {
+        let parts = cef_header(r"CEF:0|security|threatmanager|1.0|100|detected a \| in message|10|src=10.0.0.1 act=blocked a | dst=1.1.1.1");
+        assert_eq!(parts.len(), 8);
+        assert_eq!(parts[5], "detected a | in message");
+        // Pipes need no escape in the extension.
+        assert_eq!(parts[7], "src=10.0.0.1 act=blocked a | dst=1.1.1.1");
+
+        let parts = cef_header(r"CEF:0|security|threatmanager|1.0|100|detected a \\ in packet|10|src=10.0.0.1");
+        assert_eq!(parts[5], r"detected a \ in packet");
+
+        // Only `\|` and `\\` are header escapes; other backslashes stay.
+        let parts = cef_header(r"CEF:0|security|threat\manager|1.0|100|detected a = in message|10|");
+        assert_eq!(parts[2], r"threat\manager");
+        assert_eq!(parts[5], "detected a = in message");
+        assert_eq!(parts[7], "");
+    }
+
+    #[test]
+    fn cef_header_keeps_escapes_in_the_extension() {
+        let parts = cef_header(r"CEF:0|a|b|c|d|e|f|msg=x\|y path=c:\\temp");
+        assert_eq!(parts[7], r"msg=x\|y path=c:\\temp");
+    }
+
+    #[test]
+    fn cef_extension_unescapes_values() {
+        let fields = parse_cef_extension(r"src=10.0.0.1 act=blocked a \= dst=1.1.1.1");
+        assert_eq!(fields["src"], "10.0.0.1");
+        assert_eq!(fields["act"], "blocked a =");
+        assert_eq!(fields["dst"], "1.1.1.1");
+
+        let fields = parse_cef_extension(r"act=blocked a \\ dst=1.1.1.1");
+        assert_eq!(fields["act"], r"blocked a \");
+        assert_eq!(fields["dst"], "1.1.1.1");
+
+        let fields = parse_cef_extension(r"msg=Detected a threat.\nNo action needed. cs1=a\rb");
+        assert_eq!(fields["msg"], "Detected a threat.\nNo action needed.");
+        assert_eq!(fields["cs1"], "a\rb");
+    }
+
+    #[test]
+    fn cef_extension_values_run_to_the_next_key() {
+        let fields = parse_cef_extension(r"msg=login failed for admin suser=admin request=/a?b\=c");
+        assert_eq!(fields["msg"], "login failed for admin");
+        assert_eq!(fields["suser"], "admin");
+        assert_eq!(fields["request"], "/a?b=c");
+    }
+
+    #[test]
+    fn cef_line_with_syslog_prefix() {
+        let entry = parse_cef_line(
+            r"Sep 19 08:26:10 host CEF:0|Security|threatmanager|1.0|100|worm\|virus stopped|10|src=10.0.0.1 msg=path c:\\tmp\=x",
+        )
+        .unwrap();
+        assert_eq!(entry.message, "worm|virus stopped");
+        assert_eq!(entry.level, LogLevel::Error);
+        assert_eq!(entry.fields["host"], "host");
+        assert_eq!(entry.fields["msg"], r"path c:\tmp=x");
+    }
+
+    /// A Zipf-distributed stream over 1000 messages (`msg-1` the most
+    /// frequent), shuffled with a fixed seed, and the true counts.
+    fn zipf_stream() -> (Vec<String>, HashMap<String, usize>) {
+        let counts: HashMap<String, usize> = (1..=1000)
+            .map(|rank| (format!("msg-{}", rank), (20_000.0 / (rank as f64).powf(1.1)).ceil() as usize))
+            .collect();
+        let mut stream: Vec<String> = counts
+            .iter()
+            .flat_map(|(message, &count)| std::iter::repeat_n(message.clone(), count))
+            .collect();
+        stream.sort();
+
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        for i in (1..stream.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            stream.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        (stream, counts)
+    }
+
+    fn check_top(top: &[ErrorFrequency], counts: &HashMap<String, usize>, total: usize, capacity: usize) {
+        let expected: Vec<String> = (1..=top.len()).map(|rank| format!("msg-{}", rank)).collect();
+        let found: Vec<String> = top.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(found, expected);
+
+        for error in top {
+            let truth = counts[&error.message];
+            let over = error.max_overcount.unwrap();
+            assert!(error.count >= truth, "{} counted {} < {}", error.message, error.count, truth);
+            assert!(error.count - over <= truth, "{} overcount {} too small", error.message, over);
+            assert!(over <= total / capacity, "{} overcount {} above total/capacity", error.message, over);
+        }
+    }
+
+    #[test]
+    fn space_saving_recovers_zipf_top_n() {
+        let (stream, counts) = zipf_stream();
+        let mut summary = SpaceSaving::new(100);
+        stream.iter().for_each(|message| summary.add(message));
+
+        check_top(&summary.top(10), &counts, stream.len(), 100);
+    }
+
+    #[test]
+    fn merged_space_saving_keeps_its_bounds() {
+        let (stream, counts) = zipf_stream();
+        let (first, second) = stream.split_at(stream.len() / 3);
+        let (mut a, mut b) = (SpaceSaving::new(100), SpaceSaving::new(100));
+        first.iter().for_each(|message| a.add(message));
+        second.iter().for_each(|message| b.add(message));
+
+        // Each part may add its own floor, so the bound doubles.
+        check_top(&a.merge(b).top(10), &counts, stream.len(), 50);
+    }
+
+    fn parse_fixture(format: InputFormat, fixture: &str) -> ParsedLog {
+        Parser::new(format).parse_reader(fixture.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn postgres_fixture_attaches_details_and_statements() {
+        let log = parse_fixture(InputFormat::Postgres, include_str!("../tests/fixtures/postgres.log"));
+        assert_eq!(log.unparsed_lines, 0);
+        assert_eq!(log.entries.len(), 9);
+
+        let duplicate = &log.entries[2];
+        assert_eq!(duplicate.line, 3);
+        assert_eq!(duplicate.level, LogLevel::Error);
+        assert_eq!(duplicate.fields["pid"], "12401");
+        assert_eq!(duplicate.fields["sqlstate"], "23505");
+        assert_eq!(
+            duplicate.message,
+            "duplicate key value violates unique constraint \"users_pkey\"\n\
+             DETAIL:  Key (id)=(42) already exists.\n\
+             STATEMENT:  INSERT INTO users (id, email)\n\
+             \t\tVALUES (42, 'a@example.com')\n\
+             \t\tRETURNING id;"
+        );
+        assert_eq!(duplicate.datetime, Some(datetime("2024-01-02 10:01:12.456")));
+
+        let missing = &log.entries[3];
+        assert_eq!(missing.line, 8);
+        assert!(missing.message.ends_with("STATEMENT:  SELECT *\n\t\tFROM orderz\n\t\tWHERE id = 1;"));
+
+        let levels: Vec<&LogLevel> = log.entries.iter().map(|e| &e.level).collect();
+        assert_eq!(
+            levels,
+            [
+                &LogLevel::Info,
+                &LogLevel::Info,
+                &LogLevel::Error,
+                &LogLevel::Error,
+                &LogLevel::Warning,
+                &LogLevel::Fatal,
+                &LogLevel::Error,
+                &LogLevel::Info,
+                &LogLevel::Info,
+            ]
+        );
+        assert!(log.entries[6].message.contains("\nHINT:  No function matches"));
+        assert!(!log.entries[0].fields.contains_key("sqlstate"));
+    }
+
+    #[test]
+    fn postgres_line_severities_and_zones() {
+        let entry = |line: &str| match parse_postgres_line(line) {
+            Parsed::Entry(entry) => entry,
+            other => panic!("{:?} for {}", other, line),
+        };
+
+        let offset = entry("2024-01-02 12:00:00.5 +02 [7] LOG:  autovacuum launcher started");
+        assert_eq!(offset.datetime, Some(datetime("2024-01-02 10:00:00.5")));
+        assert_eq!(offset.timestamp, "2024-01-02 12:00:00.5 +02");
+
+        let abbreviation = entry("2024-01-02 12:00:00 CET [7] PANIC:  could not write to file");
+        assert_eq!(abbreviation.datetime, Some(datetime("2024-01-02 12:00:00")));
+        assert_eq!(abbreviation.level, LogLevel::Fatal);
+
+        assert_eq!(entry("2024-01-02 12:00:00 UTC [7] DEBUG2:  snapshot").level, LogLevel::Debug);
+        assert_eq!(entry("2024-01-02 12:00:00 UTC [7-1] NOTICE:  table does not exist, skipping").fields["pid"], "7");
+
+        assert!(matches!(
+            parse_postgres_line("2024-01-02 12:00:00 UTC [7] CONTEXT:  PL/pgSQL function f() line 3"),
+            Parsed::Continuation(text) if text == "CONTEXT:  PL/pgSQL function f() line 3"
+        ));
+        assert!(matches!(parse_postgres_line("\tAND x = 1"), Parsed::Continuation(text) if text == "\tAND x = 1"));
+        assert!(matches!(parse_postgres_line("pg_ctl: server started"), Parsed::Unparsed));
+        assert!(matches!(parse_postgres_line("2024-01-02 12:00:00 UTC [7] BOGUS:  x"), Parsed::Unparsed));
+    }
+
+    const PYTHON_FIXTURES: [(&str, &str); 4] = [
+        ("python_default.log", include_str!("../tests/fixtures/python_default.log")),
+        ("python_basic.log", include_str!("../tests/fixtures/python_basic.log")),
+        ("python_level_first.log", include_str!("../tests/fixtures/python_level_first.log")),
+        ("python_time_first.log", include_str!("../tests/fixtures/python_time_first.log")),
+    ];
+
+    #[test]
+    fn python_fixtures_parse_in_every_layout() {
+        for (name, fixture) in PYTHON_FIXTURES {
+            let log = parse_fixture(InputFormat::Python, fixture);
+            assert_eq!(log.unparsed_lines, 0, "{}", name);
+
+            let levels: Vec<&LogLevel> = log.entries.iter().map(|e| &e.level).collect();
+            assert_eq!(
+                levels,
+                [
+                    &LogLevel::Debug,
+                    &LogLevel::Info,
+                    &LogLevel::Warning,
+                    &LogLevel::Error,
+                    &LogLevel::Error,
+                    &LogLevel::Fatal,
+                ],
+                "{}",
+                name
+            );
+            let modules: Vec<&str> = log.entries.iter().map(|e| e.fields["module"].as_str()).collect();
+            assert_eq!(modules, ["app", "app", "app.db", "app", "app.db", "app"], "{}", name);
+            assert_eq!(log.entries[2].message, "slow query took 2.5s", "{}", name);
+
+            let failure = &log.entries[3];
+            assert!(failure.message.starts_with("request failed\nTraceback (most recent call last):"), "{}", name);
+            assert!(failure.message.ends_with("\nKeyError: 'user_id'"), "{}", name);
+            assert_eq!(log.entries[4].line, 10, "{}", name);
+
+            if name != "python_basic.log" {
+                assert_eq!(log.entries[1].timestamp, "2024-01-02 10:00:01,500", "{}", name);
+                assert_eq!(log.entries[1].datetime, Some(datetime("2024-01-02 10:00:01.5")), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn python_fixtures_are_detected() {
+        let pattern = compile_pattern(None).unwrap();
+        for (name, _) in PYTHON_FIXTURES {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+            assert_eq!(detect_format(&path, &pattern, Encoding::Auto), InputFormat::Python, "{}", name);
+        }
+    }
+
+    #[test]
+    fn gelf_with_an_invalid_record_is_detected() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/gelf.log");
+        let pattern = compile_pattern(None).unwrap();
+        assert_eq!(detect_format(&path, &pattern, Encoding::Auto), InputFormat::Gelf);
+    }
+
+    #[test]
+    fn gelf_keys_tell_gelf_from_plain_json() {
+        assert!(!has_gelf_keys(r#"{"level":"error","message":"connection refused"}"#));
+        assert!(has_gelf_keys(r#"{"version":"1.1","host":"web-1"}"#));
+        assert!(!has_gelf_keys("version=1.1 short_message=hi"));
+    }
+
+    fn haproxy(timers: &str, status: &str, request: &str) -> LogEntry {
+        parse_haproxy_line(&format!(
+            "Feb  6 12:14:14 lb haproxy[14389]: 10.0.1.2:33317 [06/Feb/2009:12:14:14.655] http-in static/srv1 {} {} 2750 - - ---- 1/1/1/1/0 0/0 \"{}\"",
+            timers, status, request
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn requests_without_duration_are_left_out_of_timings() {
+        let entries = [
+            haproxy("10/0/30/69/109", "200", "GET /index.html HTTP/1.1"),
+            haproxy("10/0/30/120/170", "200", "GET /about HTTP/1.1"),
+            haproxy("0/0/0/-1/5000", "504", "GET /slow HTTP/1.1"),
+        ];
+        assert!(!entries[2].fields.contains_key("duration_ms"));
+
+        let report = summarize_requests(&entries, 5);
+        let endpoint = &report.endpoints[0];
+        assert_eq!(endpoint.endpoint, "static");
+        assert_eq!(endpoint.requests, 3);
+        assert_eq!(endpoint.server_errors, 1);
+        assert_eq!(endpoint.p95_ms, Some(120.0));
+        assert_eq!(endpoint.max_ms, Some(120.0));
+        // HAProxy records no database or view time.
+        assert_eq!(endpoint.avg_db_ms, None);
+        assert_eq!(endpoint.avg_view_ms, None);
+    }
+
+    #[test]
+    fn endpoint_without_any_duration_has_no_timings() {
+        let report = summarize_requests(&[haproxy("0/0/0/-1/5000", "504", "GET /slow HTTP/1.1")], 5);
+        assert_eq!(report.endpoints[0].requests, 1);
+        assert_eq!(report.endpoints[0].p95_ms, None);
+        assert_eq!(report.endpoints[0].max_ms, None);
+    }
+
+    #[test]
+    fn empty_log_has_no_health_score() {
+        let stats = Analyzer::new().analyze(&[]);
+        let health = compute_health(&stats, true, &HealthWeights::default());
+        assert_eq!(health.score, None);
+        assert!(health.components.iter().all(|c| c.value.is_none() && c.points.is_none()));
+    }
+
+    #[test]
+    fn health_score_of_a_clean_log() {
+        let pattern = compile_pattern(None).unwrap();
+        let entries: Vec<LogEntry> = ["2024-01-01 12:00:00 [INFO] up", "2024-01-01 12:00:01 [INFO] ready"]
+            .iter()
+            .filter_map(|line| parse_log_line(line, &pattern, false))
+            .collect();
+        let health = compute_health(&Analyzer::new().analyze(&entries), true, &HealthWeights::default());
+        assert_eq!(health.score, Some(100.0));
+        assert_eq!(health.components[0].weight, 40.0);
+    }
+
+    #[test]
+    fn reorder_buffer_releases_like_reorder_window() {
+        let pattern = compile_pattern(None).unwrap();
+        let entries: Vec<LogEntry> = include_str!("../tests/fixtures/out_of_order.log")
+            .lines()
+            .filter_map(|line| parse_log_line(line, &pattern, false))
+            .collect();
+        let order = |entries: Vec<LogEntry>| entries.iter().map(|e| e.timestamp[11..].to_string()).collect::<Vec<_>>();
+
+        let mut buffer = ReorderBuffer::new(3);
+        let mut pushed: Vec<LogEntry> = entries.iter().filter_map(|e| buffer.push(e.clone())).collect();
+        assert_eq!(pushed.len(), 4);
+        pushed.extend(std::iter::from_fn(|| buffer.pop()));
+
+        let windowed: Vec<LogEntry> = ReorderWindow::new(entries.into_iter(), 3).collect();
+        assert_eq!(order(pushed), order(windowed.clone()));
+        assert_eq!(order(windowed), ["10:00:00", "10:00:01", "10:00:02", "10:00:03", "10:00:04", "10:00:05"]);
+    }
+
+    #[test]
+    fn timeseries_and_volume_histogram_list_the_same_levels() {
+        let log = parse_fixture(
+            InputFormat::Default,
+            "2024-01-15 10:00:00 [ERROR] disk full\n2024-01-15 10:02:00 [INFO] retrying\n",
+        );
+        let origin = datetime("2024-01-15 10:00:00");
+        let points = timeseries(&log.entries, TimeDelta::minutes(1), origin, None);
+        let levels = |bucket: &str| -> Vec<String> {
+            points.iter().filter(|p| p.bucket_start == bucket).map(|p| p.level.clone()).collect()
+        };
+
+        assert_eq!(points.len(), 3 * LogLevel::ALL.len());
+        assert_eq!(levels("2024-01-15T10:01:00+00:00"), ["Debug", "Error", "Fatal", "Info", "Trace", "Warning"]);
+        for bucket in volume_histogram(&log.entries, TimeDelta::minutes(1), origin, None) {
+            assert_eq!(bucket.by_level.keys().cloned().collect::<Vec<_>>(), levels(&bucket.bucket_start));
+        }
+    }
+
+    #[test]
+    fn bsd_syslog_times_get_the_latest_year_not_in_the_future() {
+        let now = datetime("2026-01-01 00:10:00");
+        assert_eq!(bsd_syslog_time("Jan  1 00:05:00", now), Some(datetime("2026-01-01 00:05:00")));
+        assert_eq!(bsd_syslog_time("Dec 31 23:59:59", now), Some(datetime("2025-12-31 23:59:59")));
+        assert_eq!(bsd_syslog_time("Jan  2 00:00:00", now), Some(datetime("2026-01-02 00:00:00")));
+        assert_eq!(bsd_syslog_time("Jan  3 00:00:00", now), Some(datetime("2025-01-03 00:00:00")));
+    }
+
+    #[test]
+    fn bsd_syslog_leap_day_falls_in_the_last_leap_year() {
+        let now = datetime("2026-03-01 12:00:00");
+        assert_eq!(bsd_syslog_time("Feb 29 10:00:00", now), Some(datetime("2024-02-29 10:00:00")));
+        assert_eq!(bsd_syslog_time("Feb 28 10:00:00", now), Some(datetime("2026-02-28 10:00:00")));
+        assert_eq!(bsd_syslog_time("Feb 30 10:00:00", now), None);
+    }
+}
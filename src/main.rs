@@ -1,254 +1,4097 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use clap::parser::ValueSource;
+use clap::builder::{EnumValueParser, TypedValueParser};
 use std::path::PathBuf;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::collections::HashMap;
+use std::io::{BufRead, BufWriter, IsTerminal, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::borrow::Cow;
 
-use regex::Regex;
-use serde::Serialize;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
 use prettytable::{Table, Row, Cell};
 
+use loglyzer::*;
 
 
+
+/// Options that need every entry in memory, which `--stream` and
+/// `--follow` never hold.
+const NEEDS_ENTRIES: [&str; 29] = [
+    "cache", "sort_by_time", "dedupe", "rollup", "window", "daily", "timeseries", "bucket",
+    "correlated_within", "detect_retry_storms", "peak_report", "sessionize", "first_errors", "split_by",
+    "error_context", "template_file", "parse_stats_only", "parser_cmd", "add_field", "count_group",
+    "owners", "per_file", "alert_pattern", "emit", "show_error", "show_id", "include_entries", "top_by_level_sections",
+    "http",
+];
+
 #[derive(Parser, Debug)]
 #[command(name = "loglyzer")]
 #[command(version = "1.0")]
 #[command(about = "Analyze log files and extract patterns", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
+#[command(group(clap::ArgGroup::new("searches").args(["search", "search_regex"]).multiple(true)))]
 struct Cli {
-    /// Path to the log file to analyze
+    /// Log files to analyze; `*` and `?` in a file name match several files, `-` reads
+    /// standard input (the default when it is piped)
     #[arg(value_name = "FILE")]
-    input: PathBuf,
+    input: Vec<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 
     /// Output format: text, json, csv
-    #[arg(short, long, value_enum, default_value = "text")]
+    #[arg(short, long, value_enum, default_value = "text", env = "LOGLYZER_FORMAT")]
     format: OutputFormat,
 
-    /// Show only ERROR-level logs
+    /// Show only errors, FATAL included; short for --min-level error
     #[arg(short, long)]
     errors_only: bool,
 
+    /// Show only entries at these levels, e.g. error,warning (fatal, error, warning, info, debug, trace)
+    #[arg(long, value_name = "LEVELS", value_parser = parse_level, value_delimiter = ',')]
+    level: Vec<LogLevel>,
+
+    /// Show only entries at this level or above, from trace up to fatal, e.g. warning
+    #[arg(long, value_name = "LEVEL", value_parser = parse_level, conflicts_with = "errors_only")]
+    min_level: Option<LogLevel>,
+
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
 
     /// Show top N most frequent errors
-    #[arg(long, default_value = "5")]
+    #[arg(long, default_value = "5", env = "LOGLYZER_TOP")]
     top: usize,
 
+    /// Show as many top errors as it takes to cover this fraction of all errors, e.g. 0.8 (instead of --top)
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    top_coverage: Option<f64>,
+
+    /// Group the top errors by normalized template (numbers, IDs, IPs masked); level counts stay raw
+    #[arg(long)]
+    normalize_top_only: bool,
+
+    /// Hide top errors seen fewer than N times (templates with --normalize-top-only)
+    #[arg(long, value_name = "N", default_value = "1")]
+    min_count: usize,
+
+    /// Also list the top --top messages of every level, one section per level
+    #[arg(long)]
+    top_by_level_sections: bool,
+
     /// Filter logs containing specific text (case-insensitive)
     #[arg(long)]
     search: Option<String>,
+
+    /// Filter logs matching a regex, case-sensitive unless it starts with (?i)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    search_regex: Option<Regex>,
+
+    /// Filter logs with a boolean query, e.g. 'level=error AND (message~"timeout" OR message~"refused") AND ts>2024-05-01'
+    #[arg(long, value_name = "EXPR", value_parser = str::parse::<Query>)]
+    query: Option<Query>,
+
+    /// Highlight the --search text or --search-regex matches in messages of text output (follows --color)
+    #[arg(long, requires = "searches")]
+    highlight: bool,
+
+    /// Abort if more than N entries would be held in memory
+    #[arg(long, value_name = "N")]
+    max_entries: Option<usize>,
+
+    /// Parse, filter and count FILE in one pass without holding its entries in memory
+    #[arg(long, conflicts_with_all = NEEDS_ENTRIES)]
+    stream: bool,
+
+    /// With --stream, parse N parts of FILE in parallel and merge the counts
+    #[arg(long, value_name = "N", default_value = "1", value_parser = parse_threads, requires = "stream")]
+    threads: usize,
+
+    /// Count FILE like --stream, then keep reading what is appended to it (like `tail -F`)
+    /// and print the report again every --refresh
+    #[arg(
+        long,
+        conflicts_with_all = NEEDS_ENTRIES,
+        conflicts_with_all = ["stream", "fail_on", "require_level", "forbid_level", "min_entries"]
+    )]
+    follow: bool,
+
+    /// With --follow, how often the report is printed again while entries come in
+    #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = parse_refresh, requires = "follow")]
+    refresh: std::time::Duration,
+
+    /// Cut input lines longer than N bytes while reading; cut lines end with "[truncated]"
+    #[arg(long, value_name = "N")]
+    max_line_bytes: Option<usize>,
+
+    /// Keep parsed entries in PATH and reuse them while FILE and the parse options are unchanged
+    #[arg(long, value_name = "PATH", conflicts_with = "first_errors")]
+    cache: Option<PathBuf>,
+
+    /// Ignore --cache: neither read nor write the cache
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Sort entries by timestamp before filtering and analysis
+    #[arg(long, conflicts_with = "reorder_window")]
+    sort_by_time: bool,
+
+    /// Reorder entries by timestamp within a sliding window of N entries
+    #[arg(long, value_name = "N")]
+    reorder_window: Option<usize>,
+
+    /// Flag forward time jumps larger than this (e.g. 30m, 1h, 1d)
+    #[arg(long, value_name = "DURATION", default_value = "1h", value_parser = parse_duration)]
+    skew_threshold: TimeDelta,
+
+    /// Emit per-bucket counts per level instead of the summary (e.g. 1m, 1h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_bucket_width)]
+    rollup: Option<TimeDelta>,
+
+    /// Break the report into consecutive time windows (e.g. 15m, 1h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_bucket_width, conflicts_with = "rollup")]
+    window: Option<TimeDelta>,
+
+    /// Bucket boundaries for --rollup and --window: clock or first-entry
+    #[arg(long, default_value = "clock", value_parser = EnumValueParser::<BucketAlignArg>::new().map(BucketAlign::from))]
+    bucket_align: BucketAlign,
+
+    /// Report one row per calendar day
+    #[arg(long, conflicts_with_all = ["rollup", "window"])]
+    daily: bool,
+
+    /// Emit a dense series of per-level counts per --bucket for plotting, zeros included
+    #[arg(long, conflicts_with_all = ["rollup", "window", "daily"])]
+    timeseries: bool,
+
+    /// Histogram of entries per bucket of this width, in total and per level (e.g. 5m, 1h, 1d); with --timeseries, its bucket width (default 1m)
+    #[arg(long, value_name = "DURATION", value_parser = parse_bucket_width, conflicts_with_all = ["rollup", "window", "daily"])]
+    bucket: Option<TimeDelta>,
+
+    /// Time zone for calendar days and clock-aligned rollup buckets, e.g. Europe/Paris (timestamps are read as UTC or --input-tz)
+    #[arg(long, value_name = "ZONE")]
+    tz: Option<Tz>,
+
+    /// Layout of the log's timestamps in strftime syntax, e.g. '%d/%m/%Y %H:%M:%S', tried before the built-in ones
+    #[arg(long, value_name = "FORMAT", value_parser = parse_time_format)]
+    time_format: Option<String>,
+
+    /// Time zone of timestamps written without an offset, e.g. America/New_York (default UTC)
+    #[arg(long, value_name = "ZONE")]
+    input_tz: Option<Tz>,
+
+    /// Only analyze entries from this time on: '2024-05-01 00:00', 2024-05-01, 2h (ago), today or yesterday
+    #[arg(long, value_name = "WHEN", value_parser = str::parse::<TimeBound>)]
+    since: Option<TimeBound>,
+
+    /// Only analyze entries before this time, in the same forms as --since
+    #[arg(long, value_name = "WHEN", value_parser = str::parse::<TimeBound>)]
+    until: Option<TimeBound>,
+
+    /// Report the error templates that most often follow one another within this time, e.g. 5s
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    correlated_within: Option<TimeDelta>,
+
+    /// Flag error messages repeated more than --storm-count times within --storm-window
+    #[arg(long)]
+    detect_retry_storms: bool,
+
+    /// Repetitions within the window that make a retry storm
+    #[arg(long, value_name = "K", default_value = "10", requires = "detect_retry_storms")]
+    storm_count: usize,
+
+    /// Window for --detect-retry-storms, e.g. 10s
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "10s", requires = "detect_retry_storms")]
+    storm_window: TimeDelta,
+
+    /// Report the busiest time bucket overall and per level; the resolution defaults to 1s
+    #[arg(long, value_name = "DURATION", value_parser = parse_bucket_width, num_args = 0..=1, default_missing_value = "1s")]
+    peak_report: Option<TimeDelta>,
+
+    /// Group consecutive entries logged in the same second (see --session-gap) into sessions and report them
+    #[arg(long)]
+    sessionize: bool,
+
+    /// Longest pause between two entries of a session, e.g. 2s; 0s keeps sessions within one second
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "0s", requires = "sessionize")]
+    session_gap: TimeDelta,
+
+    /// Field that must also stay the same within a session, e.g. host
+    #[arg(long, value_name = "FIELD", requires = "sessionize")]
+    session_key: Option<String>,
+
+    /// Extra normalization rule as REGEX=PLACEHOLDER, applied in order before the built-in ones (repeatable)
+    #[arg(long, value_name = "REGEX=PLACEHOLDER", value_parser = parse_normalize_rule)]
+    normalize_rule: Vec<NormalizeRule>,
+
+    /// Print the first N errors in file order as entries (see --columns) and stop reading; no analysis
+    #[arg(long, value_name = "N", conflicts_with_all = ["emit", "show_error", "rollup", "window", "daily", "split_by"])]
+    first_errors: Option<usize>,
+
+    /// Copy the raw lines of each time bucket (e.g. 1h) to its own file in --out-dir instead of reporting
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_bucket_width,
+        requires_all = ["out_dir", "input"],
+        conflicts_with_all = ["emit", "show_error", "rollup", "window", "daily"]
+    )]
+    split_by: Option<TimeDelta>,
+
+    /// Directory for the --split-by files, created if missing
+    #[arg(long, value_name = "DIR", requires = "split_by")]
+    out_dir: Option<PathBuf>,
+
+    /// Show the N entries (any level) preceding each error
+    #[arg(long, value_name = "N")]
+    error_context: Option<usize>,
+
+    /// Keep lines without a timestamp as entries carrying the previous entry's timestamp and level
+    #[arg(long)]
+    inherit_timestamp: bool,
+
+    /// Keep unparsed lines as entries of this pseudo-level (e.g. Unknown) instead of only counting them
+    #[arg(long, value_name = "NAME", value_parser = parse_pseudo_level)]
+    unparsed_level: Option<String>,
+
+    /// Count --unparsed-level entries in the total, and so in the error rate
+    #[arg(long, requires = "unparsed_level")]
+    unparsed_in_total: bool,
+
+    /// Drop entries whose message matches any regex in this file (one per line)
+    #[arg(long, value_name = "PATH")]
+    ignore_file: Option<PathBuf>,
+
+    /// Drop entries whose message contains this text, case-insensitive like --search (repeatable)
+    #[arg(long, visible_alias = "exclude", value_name = "TEXT")]
+    exclude_contains: Vec<String>,
+
+    /// Drop entries whose message matches this regex (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    exclude_regex: Vec<Regex>,
+
+    /// Drop entries with the same timestamp, level and message as an earlier one
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Only treat entries as duplicates when they are within this time of each other
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "dedupe")]
+    dedupe_window: Option<TimeDelta>,
+
+    /// Maximum number of entry digests remembered by --dedupe
+    #[arg(long, value_name = "N", default_value = "10000000", requires = "dedupe")]
+    dedupe_max: usize,
+
+    /// Colorize text output: auto, always, never
+    #[arg(long, value_enum, default_value = "auto")]
+    color: ColorChoice,
+
+    /// Override level colors, e.g. "Error=red,Warning=magenta,Info=blue"
+    #[arg(long, value_name = "MAPPING", value_parser = parse_level_colors)]
+    level_color: Option<HashMap<String, char>>,
+
+    /// How level names are written in every output: title (Error), upper (ERROR) or lower (error)
+    #[arg(long, value_enum, default_value = "title")]
+    level_case: LevelCase,
+
+    /// Print only the report (and errors on stderr)
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print a single key=value summary line instead of the report
+    #[arg(long)]
+    summary_line: bool,
+
+    /// Print a terse `N entries | E err W warn | HH:MM–HH:MM` line for shell prompts
+    #[arg(long, conflicts_with = "summary_line")]
+    oneline: bool,
+
+    /// Render the report through a template file with {placeholders} and {#sections} (see README)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["summary_line", "oneline", "emit", "include_entries"])]
+    template_file: Option<PathBuf>,
+
+    /// Bucket width of the {#histogram} section of --template-file
+    #[arg(long, value_name = "DURATION", value_parser = parse_bucket_width, default_value = "1h", requires = "template_file")]
+    template_bucket: TimeDelta,
+
+    /// Custom line regex with named groups `message` and optionally `timestamp`, `level` and extra fields
+    #[arg(long, value_name = "REGEX", env = "LOGLYZER_PATTERN")]
+    pattern: Option<String>,
+
+    /// Treat the bracketed token (the `level` group) as a `category` field, e.g. [auth]; every entry is Info
+    #[arg(long)]
+    bracket_as_category: bool,
+
+    /// Append lines the pattern does not match (stack traces, wrapped text) to the previous entry's message
+    #[arg(long, conflicts_with = "inherit_timestamp")]
+    multiline: bool,
+
+    /// Only parse the input and report lines, entries, unparsed lines and level counts; no analysis
+    #[arg(long, conflicts_with_all = ["first_errors", "split_by", "show_id", "emit", "template_file"])]
+    parse_stats_only: bool,
+
+    /// Time the line pattern against the first lines of FILE and report its speed instead of analyzing
+    #[arg(long, requires = "input")]
+    profile_regex: bool,
+
+    /// Text encoding of FILE: auto detects UTF-8, UTF-16 (with or without BOM) or else Latin-1
+    #[arg(long, default_value = "auto", value_parser = EnumValueParser::<EncodingArg>::new().map(Encoding::from))]
+    encoding: Encoding,
+
+    /// Report the detected encoding of FILE and whether it has a BOM, then exit
+    #[arg(long, requires = "input", conflicts_with = "profile_regex")]
+    detect_encoding: bool,
+
+    /// Built-in line format for logs that --pattern cannot describe; auto picks one from the first lines
+    #[arg(long, default_value = "auto", value_parser = EnumValueParser::<InputFormatArg>::new().map(InputFormat::from))]
+    input_format: InputFormat,
+
+    /// Reject JSON records lacking these fields, as `name:type` (string, number, bool, object, array, null) or `name`
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',', value_parser = parse_field_requirement)]
+    require_fields: Vec<FieldRequirement>,
+
+    /// Read the timestamp, level or message of JSON or logfmt records from another key, as `ROLE=KEY`, e.g. message=text
+    #[arg(long, value_name = "ROLE=KEY", value_delimiter = ',', value_parser = parse_json_key)]
+    json_key: Vec<JsonKey>,
+
+    /// Decode lines with an external command: raw lines on its stdin, one JSON object (or null) per line on its stdout
+    #[arg(long, value_name = "COMMAND", conflicts_with = "input_format")]
+    parser_cmd: Option<String>,
+
+    /// Regex with named groups run against every message; the captures become fields (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = parse_extract)]
+    extract: Vec<Regex>,
+
+    /// Computed field as NAME=TEMPLATE, e.g. hour={timestamp:hour} or is_error={level}==Error (repeatable)
+    #[arg(long, value_name = "NAME=TEMPLATE", value_parser = parse_add_field)]
+    add_field: Vec<AddField>,
+
+    /// Set the level of entries whose field matches, e.g. 'message~=^ERROR:=Error'; first matching rule wins (repeatable)
+    #[arg(long, value_name = "FIELD~=REGEX=LEVEL", value_parser = parse_level_remap)]
+    level_remap: Vec<LevelRemap>,
+
+    /// Tally the values of a named capture group from --pattern or --extract, or a field of --input-format
+    #[arg(long, value_name = "NAME")]
+    count_group: Option<String>,
+
+    /// Add the number of distinct messages (templates with --normalize-top-only) to each --count-group value
+    #[arg(long, requires = "count_group")]
+    count_distinct: bool,
+
+    /// With several FILEs, also count entries per file and level
+    #[arg(long)]
+    per_file: bool,
+
+    /// Summarize the requests of an access, haproxy, w3c or rails log: status classes, top paths,
+    /// slowest endpoints and top clients
+    #[arg(long)]
+    http: bool,
+
+    /// TOML file mapping error message regexes to owning teams
+    #[arg(long, value_name = "PATH")]
+    owners: Option<PathBuf>,
+
+    /// Exit with a failure code when a condition holds, e.g. 'errors>10' (repeatable)
+    #[arg(long, value_name = "CONDITION", value_parser = parse_gate)]
+    fail_on: Vec<Gate>,
+
+    /// Alert when a text (or `re:REGEX`) occurs more than MAX times, e.g. 'OutOfMemoryError:0' (repeatable)
+    #[arg(long, value_name = "PATTERN:MAX", value_parser = parse_alert_pattern)]
+    alert_pattern: Vec<AlertPattern>,
+
+    /// Fail unless at least one entry has this level (repeatable)
+    #[arg(long, value_name = "LEVEL", value_parser = parse_level)]
+    require_level: Vec<LogLevel>,
+
+    /// Fail if any entry has this level (repeatable)
+    #[arg(long, value_name = "LEVEL", value_parser = parse_level)]
+    forbid_level: Vec<LogLevel>,
+
+    /// Fail if fewer than N entries were parsed, e.g. when the input is empty or truncated
+    #[arg(long, value_name = "N")]
+    min_entries: Option<usize>,
+
+    /// What to output: aggregated stats or the filtered entries themselves
+    #[arg(long, value_enum, default_value = "stats")]
+    emit: Emit,
+
+    /// Columns for --emit entries: line, timestamp, level, message or any field name
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',', default_value = "line,timestamp,level,message")]
+    columns: Vec<String>,
+
+    /// Field separator for CSV output, a single character such as ';'
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = parse_csv_delimiter)]
+    csv_delimiter: char,
+
+    /// Omit the header row of --emit entries CSV output
+    #[arg(long)]
+    no_header: bool,
+
+    /// Print the entries behind one top error: its rank (1 = most frequent) or its message text
+    #[arg(long, value_name = "RANK|MESSAGE", value_parser = parse_error_selector, conflicts_with = "emit")]
+    show_error: Option<ErrorSelector>,
+
+    /// Print the timeline of the entries carrying this ID in a field (see --id-field), with time offsets
+    #[arg(long, value_name = "ID", conflicts_with_all = ["emit", "show_error", "first_errors", "split_by", "rollup", "window", "daily"])]
+    show_id: Option<String>,
+
+    /// Field holding the ID for --show-id, e.g. request_id (default: any field)
+    #[arg(long, value_name = "FIELD", requires = "show_id")]
+    id_field: Option<String>,
+
+    /// Include every filtered entry in JSON output (written incrementally)
+    #[arg(long)]
+    include_entries: bool,
+
+    /// Write JSON reports on one line instead of pretty-printed
+    #[arg(long)]
+    json_compact: bool,
+
+    /// Write entry exports (--emit entries, --show-error, --include-entries) to FILE instead of stdout
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Gzip the --output file (implied by a .gz extension)
+    #[arg(long, requires = "output")]
+    compress: bool,
+
+    /// Count top errors approximately in bounded memory (Space-Saving)
+    #[arg(long)]
+    approx_top: bool,
+
+    /// Number of distinct messages tracked in approximate mode
+    #[arg(long, value_name = "N", default_value = "10000")]
+    approx_capacity: usize,
+
+    /// Switch to approximate mode once this many distinct error messages are seen
+    #[arg(long, value_name = "N", default_value = "1000000")]
+    approx_threshold: usize,
+
+    /// Mask emails, IP addresses, bearer tokens and card numbers in all output
+    #[arg(long)]
+    redact: bool,
+
+    /// Additional regex whose matches are masked in output (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    redact_pattern: Vec<Regex>,
+
+    /// Print raw numbers and timestamps in text output instead of humanized ones
+    #[arg(long)]
+    no_humanize: bool,
+
+    /// TOML configuration file (health score weights, ...)
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Print the effective settings and where each comes from (CLI, environment, config, default), then exit
+    #[arg(long)]
+    dump_config: bool,
 }
 
-#[derive(Debug, Clone, clap::ValueEnum)]
-enum OutputFormat {
-    Text,
-    Json,
-    Csv,
+/// Settings read from `--config`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    health: HealthWeights,
+    /// Normalization rules applied before the `--normalize-rule` ones.
+    #[serde(default)]
+    normalize: Vec<NormalizeRuleConfig>,
 }
 
-/* =========================
-   Log structures — Part 2
-   ========================= */
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NormalizeRuleConfig {
+    pattern: String,
+    placeholder: String,
+}
 
-#[derive(Debug, Clone)]
-struct LogEntry {
-    timestamp: String,
-    level: LogLevel,
-    message: String,
+fn load_config(path: &std::path::Path) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    toml::from_str(&content).map_err(|e| e.to_string())
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum LogLevel {
-    Info,
-    Warning,
-    Error,
-    Debug,
+/// One effective setting for `--dump-config`.
+#[derive(Debug, Serialize)]
+struct Setting {
+    name: String,
+    value: String,
+    source: &'static str,
 }
 
-impl LogLevel {
-    fn from_str(s: &str) -> Option<Self> {
-        match s.to_uppercase().as_str() {
-            "INFO" => Some(LogLevel::Info),
-            "WARNING" | "WARN" => Some(LogLevel::Warning),
-            "ERROR" => Some(LogLevel::Error),
-            "DEBUG" => Some(LogLevel::Debug),
-            _ => None,
+/// Every option that has a value, as given or defaulted, with where it came
+/// from; switches left off are skipped. The `--config` settings follow; a
+/// health weight equal to the built-in one counts as a default.
+fn resolved_settings(matches: &clap::ArgMatches, config: Option<&Config>) -> Vec<Setting> {
+    let mut settings = Vec::new();
+    for arg in Cli::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == "dump_config" {
+            continue;
         }
+        let (Some(source), Some(values)) = (matches.value_source(id), matches.get_raw(id)) else {
+            continue;
+        };
+        let value: Vec<String> = values.map(|v| v.to_string_lossy().into_owned()).collect();
+        let value = value.join(",");
+        if source == ValueSource::DefaultValue && value == "false" {
+            continue;
+        }
+        settings.push(Setting {
+            name: match arg.get_long() {
+                Some(long) => format!("--{}", long),
+                None => arg
+                    .get_value_names()
+                    .and_then(|names| names.first())
+                    .map_or_else(|| id.to_uppercase(), |name| name.to_string()),
+            },
+            value,
+            source: match source {
+                ValueSource::CommandLine => "CLI",
+                ValueSource::EnvVariable => "env",
+                _ => "default",
+            },
+        });
+    }
+
+    let default = Config::default();
+    let config = config.unwrap_or(&default);
+    let (health, defaults) = (&config.health, &default.health);
+    for (name, weight, default_weight) in [
+        ("error_rate", health.error_rate, defaults.error_rate),
+        ("fatal", health.fatal, defaults.fatal),
+        ("new_errors", health.new_errors, defaults.new_errors),
+        ("parse_failures", health.parse_failures, defaults.parse_failures),
+        ("gaps", health.gaps, defaults.gaps),
+    ] {
+        settings.push(Setting {
+            name: format!("health.{}", name),
+            value: weight.to_string(),
+            source: if weight == default_weight { "default" } else { "config" },
+        });
+    }
+    for (i, rule) in config.normalize.iter().enumerate() {
+        settings.push(Setting {
+            name: format!("normalize[{}]", i),
+            value: format!("{}={}", rule.pattern, rule.placeholder),
+            source: "config",
+        });
+    }
+    settings
+}
+
+fn output_settings_text(settings: &[Setting]) {
+    for setting in settings {
+        let source = match setting.source {
+            "default" => "(default)".to_string(),
+            source => format!("(from {})", source),
+        };
+        println!("{} = {} {}", setting.name, setting.value, source);
     }
 }
 
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Run a command, echo its output to stderr and analyze it when it exits
+    Run {
+        /// The command and its arguments, after `--`
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Emit {
+    Stats,
+    Entries,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum LevelCase {
+    Title,
+    Upper,
+    Lower,
+}
 
-fn read_log_file(path: &std::path::Path) -> Result<Vec<String>, std::io::Error> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+impl LevelCase {
+    /// Level names are title case internally, so `Title` keeps them as is.
+    fn apply(self, name: &str) -> String {
+        match self {
+            LevelCase::Title => name.to_string(),
+            LevelCase::Upper => name.to_uppercase(),
+            LevelCase::Lower => name.to_lowercase(),
+        }
+    }
 
-    let mut lines = Vec::new();
-    for line in reader.lines() {
-        lines.push(line?);
+    /// Rekeys a per-level count map, `HashMap` or `BTreeMap`.
+    fn recase_levels<M>(self, by_level: &mut M)
+    where
+        M: Default + IntoIterator<Item = (String, usize)> + FromIterator<(String, usize)>,
+    {
+        *by_level = std::mem::take(by_level)
+            .into_iter()
+            .map(|(level, count)| (self.apply(&level), count))
+            .collect();
     }
 
-    Ok(lines)
+    /// Only for output: the entry's level becomes a pseudo-level carrying
+    /// the recased name, so it no longer compares equal to the real one.
+    fn recase_entry(self, entry: &mut LogEntry) {
+        if self != LevelCase::Title {
+            entry.level = LogLevel::Other(self.apply(&format!("{:?}", entry.level)));
+        }
+    }
 }
 
+/// Supported color names and their prettytable style letters.
+const PALETTE: &[(&str, char)] = &[
+    ("black", 'd'),
+    ("red", 'r'),
+    ("green", 'g'),
+    ("yellow", 'y'),
+    ("blue", 'b'),
+    ("magenta", 'm'),
+    ("cyan", 'c'),
+    ("white", 'w'),
+    ("bright-black", 'D'),
+    ("bright-red", 'R'),
+    ("bright-green", 'G'),
+    ("bright-yellow", 'Y'),
+    ("bright-blue", 'B'),
+    ("bright-magenta", 'M'),
+    ("bright-cyan", 'C'),
+    ("bright-white", 'W'),
+];
 
+fn parse_pseudo_level(s: &str) -> Result<String, String> {
+    match LogLevel::from_name(s) {
+        Some(level) => Err(format!("'{}' is the real level {:?}", s, level)),
+        None if s.trim().is_empty() => Err("the level name is empty".to_string()),
+        None => Ok(s.to_string()),
+    }
+}
 
-fn parse_log_line(line: &str) -> Option<LogEntry> {
-    let re = Regex::new(
-        r"^(\d{4}-\d{2}-\d{2}\s+\d{2}:\d{2}:\d{2})\s+\[(\w+)\]\s+(.+)$"
-    ).ok()?;
+fn parse_threads(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid thread count '{}': expected a whole number of at least 1", s)),
+    }
+}
 
-    let caps = re.captures(line)?;
+fn parse_refresh(s: &str) -> Result<std::time::Duration, String> {
+    match parse_duration(s)?.to_std() {
+        Ok(interval) if !interval.is_zero() => Ok(interval),
+        _ => Err(format!("invalid refresh interval '{}': expected at least 1s", s)),
+    }
+}
 
-    Some(LogEntry {
-        timestamp: caps.get(1)?.as_str().to_string(),
-        level: LogLevel::from_str(caps.get(2)?.as_str())?,
-        message: caps.get(3)?.as_str().to_string(),
-    })
+fn parse_bucket_width(s: &str) -> Result<TimeDelta, String> {
+    match parse_duration(s)? {
+        width if width >= TimeDelta::seconds(1) => Ok(width),
+        _ => Err(format!("invalid bucket width '{}': expected at least 1s", s)),
+    }
+}
+
+fn parse_csv_delimiter(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() && !matches!(c, '"' | '\n' | '\r') => Ok(c),
+        (Some(_), None) => Err(format!("'{}' cannot separate CSV fields", s)),
+        _ => Err(format!("expected a single character, got '{}'", s)),
+    }
+}
+
+fn parse_extract(s: &str) -> Result<Regex, String> {
+    let re = parse_regex(s)?;
+    if re.capture_names().flatten().next().is_none() {
+        return Err(format!("'{}' has no named group, e.g. (?P<name>...)", s));
+    }
+    Ok(re)
 }
 
+/// Parses `Level=color` pairs separated by commas into level name → style letter.
+fn parse_level_colors(s: &str) -> Result<HashMap<String, char>, String> {
+    let mut colors = HashMap::new();
 
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (level, color) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected Level=color, got '{}'", pair))?;
+        let level = LogLevel::from_name(level.trim())
+            .ok_or_else(|| format!("unknown level '{}'", level.trim()))?;
+        let color = color.trim().to_lowercase();
+        let (_, spec) = PALETTE
+            .iter()
+            .find(|(name, _)| *name == color)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PALETTE.iter().map(|(name, _)| *name).collect();
+                format!("unknown color '{}': expected one of {}", color, names.join(", "))
+            })?;
+        colors.insert(format!("{:?}", level), *spec);
+    }
 
-#[derive(Debug, Serialize)]
-struct ErrorFrequency {
-    message: String,
-    count: usize,
+    Ok(colors)
 }
 
-#[derive(Debug, Serialize)]
-struct LogStats {
-    total_entries: usize,
-    by_level: HashMap<String, usize>,
-    top_errors: Vec<ErrorFrequency>,
+fn parse_error_selector(s: &str) -> Result<ErrorSelector, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("ranks start at 1".to_string()),
+        Ok(rank) => Ok(ErrorSelector::Rank(rank)),
+        Err(_) => Ok(ErrorSelector::Message(s.to_string())),
+    }
 }
 
-fn analyze_logs(entries: &[LogEntry], top_n: usize) -> LogStats {
-    let mut by_level: HashMap<String, usize> = HashMap::new();
-    let mut error_messages: HashMap<String, usize> = HashMap::new();
+/// Parses a `--json-key` entry such as `message=text`.
+fn parse_json_key(s: &str) -> Result<JsonKey, String> {
+    let Some((role, key)) = s.trim().split_once('=') else {
+        return Err(format!("expected ROLE=KEY, e.g. message=text, got '{}'", s));
+    };
+    let role = match [JsonRole::Timestamp, JsonRole::Level, JsonRole::Message].into_iter().find(|r| r.name() == role) {
+        Some(role) => role,
+        None => return Err(format!("unknown role '{}' in '{}': use timestamp, level or message", role, s)),
+    };
+    if key.is_empty() {
+        return Err(format!("missing key in '{}'", s));
+    }
+    Ok(JsonKey { role, key: key.to_string() })
+}
 
-    for entry in entries {
-        let level_name = format!("{:?}", entry.level);
-        *by_level.entry(level_name).or_insert(0) += 1;
+fn parse_field_requirement(s: &str) -> Result<FieldRequirement, String> {
+    let (name, kind) = match s.trim().split_once(':') {
+        Some((name, kind)) => (name, Some(kind)),
+        None => (s.trim(), None),
+    };
+    if name.is_empty() {
+        return Err(format!("missing field name in '{}'", s));
+    }
+    const TYPES: [JsonType; 6] = [
+        JsonType::String,
+        JsonType::Number,
+        JsonType::Bool,
+        JsonType::Object,
+        JsonType::Array,
+        JsonType::Null,
+    ];
+    let kind = match kind {
+        None => None,
+        Some(kind) => match TYPES.into_iter().find(|t| t.name() == kind) {
+            Some(t) => Some(t),
+            None => return Err(format!("unknown type '{}' in '{}'", kind, s)),
+        },
+    };
+
+    Ok(FieldRequirement { name: name.to_string(), kind })
+}
+
+/// Parses `name=template`. References are `{column}` with an optional
+/// `:date`, `:hour`, `:minute` or `:weekday` on `timestamp`, or `:lower` /
+/// `:upper` on anything; `==TEXT` or `!=TEXT` at the end turns the field
+/// into a `true`/`false` comparison.
+fn parse_add_field(s: &str) -> Result<AddField, String> {
+    let (name, expr) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=TEMPLATE in '{}'", s))?;
+    let name = name.trim();
+    if name.is_empty() || BUILTIN_COLUMNS.contains(&name) {
+        return Err(format!("'{}' cannot be used as a field name", name));
+    }
+
+    let (expr, compare) = match (expr.split_once("=="), expr.split_once("!=")) {
+        (Some((lhs, rhs)), _) => (lhs, Some((true, rhs.to_string()))),
+        (None, Some((lhs, rhs))) => (lhs, Some((false, rhs.to_string()))),
+        (None, None) => (expr, None),
+    };
 
-        if entry.level == LogLevel::Error {
-            *error_messages.entry(entry.message.clone()).or_insert(0) += 1;
+    let mut template = Vec::new();
+    let mut rest = expr;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err(format!("unmatched '}}' in '{}'", expr));
+        }
+        if open > 0 {
+            template.push(TemplatePart::Text(rest[..open].to_string()));
         }
+        let close = rest[open..].find('}').ok_or_else(|| format!("unclosed '{{' in '{}'", expr))?;
+        let reference = &rest[open + 1..open + close];
+        let (column, modifier) = match reference.split_once(':') {
+            Some((column, modifier)) => (column, Some(modifier)),
+            None => (reference, None),
+        };
+        if column.is_empty() {
+            return Err(format!("empty reference in '{}'", expr));
+        }
+        let modifier = match modifier {
+            None => None,
+            Some("date") => Some(Modifier::Date),
+            Some("hour") => Some(Modifier::Hour),
+            Some("minute") => Some(Modifier::Minute),
+            Some("weekday") => Some(Modifier::Weekday),
+            Some("lower") => Some(Modifier::Lower),
+            Some("upper") => Some(Modifier::Upper),
+            Some(other) => return Err(format!("unknown modifier ':{}' in '{}'", other, expr)),
+        };
+        if column != "timestamp"
+            && matches!(modifier, Some(Modifier::Date | Modifier::Hour | Modifier::Minute | Modifier::Weekday))
+        {
+            return Err(format!("'{}' only applies to timestamp", reference));
+        }
+        template.push(TemplatePart::Ref(column.to_string(), modifier));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        template.push(TemplatePart::Text(rest.to_string()));
     }
 
-    let mut top_errors: Vec<ErrorFrequency> = error_messages
-        .into_iter()
-        .map(|(message, count)| ErrorFrequency { message, count })
-        .collect();
+    Ok(AddField { name: name.to_string(), template, compare })
+}
 
-    top_errors.sort_by(|a, b| b.count.cmp(&a.count));
-    top_errors.truncate(top_n);
+/// Parses `column~=regex=level`, splitting the level off at the last `=`.
+fn parse_level_remap(s: &str) -> Result<LevelRemap, String> {
+    let (column, rest) = s
+        .split_once("~=")
+        .ok_or_else(|| format!("expected FIELD~=REGEX=LEVEL in '{}'", s))?;
+    let (pattern, level) = rest
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected FIELD~=REGEX=LEVEL in '{}'", s))?;
+    if column.is_empty() || pattern.is_empty() {
+        return Err(format!("expected FIELD~=REGEX=LEVEL in '{}'", s));
+    }
+    Ok(LevelRemap {
+        spec: s.to_string(),
+        column: column.to_string(),
+        regex: parse_regex(pattern)?,
+        level: parse_level(level)?,
+    })
+}
 
-    LogStats {
-        total_entries: entries.len(),
-        by_level,
-        top_errors,
+/// Parses `regex=placeholder`, splitting at the last `=`.
+fn parse_normalize_rule(s: &str) -> Result<NormalizeRule, String> {
+    let (pattern, placeholder) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected REGEX=PLACEHOLDER in '{}'", s))?;
+    if pattern.is_empty() {
+        return Err(format!("empty regex in '{}'", s));
     }
+    Ok(NormalizeRule {
+        regex: parse_regex(pattern)?,
+        placeholder: placeholder.to_string(),
+    })
 }
 
+/// Parses `metric<op>number` with op one of `>=`, `<=`, `>`, `<`, `==`.
+fn parse_gate(s: &str) -> Result<Gate, String> {
+    let expr: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let (pos, op) = [">=", "<=", "==", ">", "<"]
+        .iter()
+        .find_map(|op| expr.find(op).map(|pos| (pos, *op)))
+        .ok_or_else(|| format!("expected metric<op>value in '{}'", s))?;
 
+    let metric = &expr[..pos];
+    if !GATE_METRICS.contains(&metric) {
+        return Err(format!(
+            "unknown metric '{}': expected one of {}",
+            metric,
+            GATE_METRICS.join(", ")
+        ));
+    }
+    let threshold = expr[pos + op.len()..]
+        .parse()
+        .map_err(|_| format!("invalid threshold in '{}'", s))?;
 
-fn output_text(stats: &LogStats) {
-    println!("\nLog Analysis Results");
-    println!("====================");
-    println!("Total entries: {}\n", stats.total_entries);
+    Ok(Gate {
+        metric: metric.to_string(),
+        op: op.to_string(),
+        threshold,
+        expr,
+    })
+}
 
-    let mut table = Table::new();
-    table.add_row(Row::new(vec![
-        Cell::new("Level"),
-        Cell::new("Count"),
-    ]));
+/// Parses `text:max` or `re:regex:max`; plain text is matched literally.
+fn parse_alert_pattern(s: &str) -> Result<AlertPattern, String> {
+    let (pattern, max) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected PATTERN:MAX in '{}'", s))?;
+    let max = max.trim().parse().map_err(|_| format!("invalid count in '{}'", s))?;
+    let regex = match pattern.strip_prefix("re:") {
+        Some(re) => parse_regex(re)?,
+        None => parse_regex(&regex::escape(pattern))?,
+    };
+    if regex.as_str().is_empty() {
+        return Err(format!("empty pattern in '{}'", s));
+    }
 
-    for (level, count) in &stats.by_level {
-        table.add_row(Row::new(vec![
-            Cell::new(level),
-            Cell::new(&count.to_string()),
-        ]));
+    Ok(AlertPattern { spec: pattern.to_string(), regex, max })
+}
+
+/// Parses a fraction in `(0, 1]`, such as `0.8`.
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(f) if f > 0.0 && f <= 1.0 => Ok(f),
+        _ => Err(format!("invalid fraction '{}': expected a number above 0 and at most 1", s)),
     }
+}
 
-    table.printstd();
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
 
-    if !stats.top_errors.is_empty() {
-        println!("\nTop errors:");
-        let mut err_table = Table::new();
-        err_table.add_row(Row::new(vec![
-            Cell::new("Message"),
-            Cell::new("Occurrences"),
-        ]));
+/// `--input-format` values, mapped onto the library's `InputFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum InputFormatArg {
+    /// Whichever format parses most of the first lines (default with --pattern)
+    Auto,
+    /// `TIMESTAMP [LEVEL] message`, or whatever --pattern describes
+    Default,
+    /// PostgreSQL server log with `log_line_prefix = '%m [%p] '` (optionally `%e`)
+    Postgres,
+    /// MySQL slow query log; one entry per query record
+    MysqlSlow,
+    /// Python `logging` output in its common layouts, tracebacks included
+    Python,
+    /// Ruby on Rails request log; one entry per request
+    Rails,
+    /// ArcSight Common Event Format, bare or behind a syslog header
+    Cef,
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs, as Go services often log them
+    Logfmt,
+    /// Graylog Extended Log Format messages, one per line
+    Gelf,
+    /// HAProxy HTTP log (`option httplog`), behind a syslog header or not
+    Haproxy,
+    /// Syslog, RFC 5424 or the classic BSD layout of RFC 3164
+    Syslog,
+    /// Apache / nginx access log in the Common or Combined Log Format
+    #[value(alias = "combined", alias = "clf")]
+    Access,
+    /// IIS / W3C extended log, columns given by `#Fields:` directives
+    #[value(alias = "iis")]
+    W3c,
+}
 
-        for err in &stats.top_errors {
-            err_table.add_row(Row::new(vec![
-                Cell::new(&err.message),
-                Cell::new(&err.count.to_string()),
-            ]));
+impl From<InputFormatArg> for InputFormat {
+    fn from(arg: InputFormatArg) -> Self {
+        match arg {
+            InputFormatArg::Auto => InputFormat::Auto,
+            InputFormatArg::Default => InputFormat::Default,
+            InputFormatArg::Postgres => InputFormat::Postgres,
+            InputFormatArg::MysqlSlow => InputFormat::MysqlSlow,
+            InputFormatArg::Python => InputFormat::Python,
+            InputFormatArg::Rails => InputFormat::Rails,
+            InputFormatArg::Cef => InputFormat::Cef,
+            InputFormatArg::Json => InputFormat::Json,
+            InputFormatArg::Logfmt => InputFormat::Logfmt,
+            InputFormatArg::Gelf => InputFormat::Gelf,
+            InputFormatArg::Haproxy => InputFormat::Haproxy,
+            InputFormatArg::Syslog => InputFormat::Syslog,
+            InputFormatArg::Access => InputFormat::Access,
+            InputFormatArg::W3c => InputFormat::W3c,
         }
-
-        err_table.printstd();
     }
 }
 
-fn output_json(stats: &LogStats) {
-    let json = serde_json::to_string_pretty(stats).unwrap();
-    println!("{}", json);
+/// `--encoding` values, mapped onto the library's `Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum EncodingArg {
+    Auto,
+    #[value(name = "utf-8")]
+    Utf8,
+    #[value(name = "utf-16le")]
+    Utf16le,
+    #[value(name = "utf-16be")]
+    Utf16be,
+    #[value(name = "latin-1")]
+    Latin1,
 }
 
-fn output_csv(stats: &LogStats) {
-    println!("level,count");
-    for (level, count) in &stats.by_level {
-        println!("{},{}", level, count);
+impl From<EncodingArg> for Encoding {
+    fn from(arg: EncodingArg) -> Self {
+        match arg {
+            EncodingArg::Auto => Encoding::Auto,
+            EncodingArg::Utf8 => Encoding::Utf8,
+            EncodingArg::Utf16le => Encoding::Utf16le,
+            EncodingArg::Utf16be => Encoding::Utf16be,
+            EncodingArg::Latin1 => Encoding::Latin1,
+        }
     }
 }
 
+/// `--bucket-align` values, mapped onto the library's `BucketAlign`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum BucketAlignArg {
+    /// Multiples of the width since the Unix epoch: `5m` gives :00, :05, :10...
+    Clock,
+    /// Offsets from the first timestamped entry.
+    FirstEntry,
+}
 
+impl From<BucketAlignArg> for BucketAlign {
+    fn from(arg: BucketAlignArg) -> Self {
+        match arg {
+            BucketAlignArg::Clock => BucketAlign::Clock,
+            BucketAlignArg::FirstEntry => BucketAlign::FirstEntry,
+        }
+    }
+}
 
-fn main() {
-    let cli = Cli::parse();
+/// The options that decide which entries a file parses into; a cache made
+/// with different ones is stale.
+fn parse_settings(cli: &Cli, input_format: InputFormat, pattern: &Regex) -> String {
+    format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+        input_format,
+        cli.encoding,
+        pattern.as_str(),
+        cli.parser_cmd,
+        cli.inherit_timestamp,
+        cli.unparsed_level,
+        cli.bracket_as_category,
+        cli.max_line_bytes,
+        cli.require_fields,
+        cli.json_key,
+        cli.multiline,
+        cli.time_format,
+        cli.input_tz,
+    )
+}
 
-    if cli.verbose {
-        println!("Analysing file: {:?}", cli.input);
-        println!("Format: {:?}", cli.format);
-        println!("Top errors: {}", cli.top);
-        println!("Search filter: {:?}", cli.search);
+/// `1234567` → `1,234,567`.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
     }
+    out
+}
 
-    let lines = match read_log_file(&cli.input) {
-        Ok(lines) => lines,
-        Err(e) => {
-            eprintln!("❌ Failed to read file: {}", e);
-            std::process::exit(1);
-        }
-    };
+/// Two most significant units: `3h 12m`, `5m 10s`, `2d 3h`, `45s`.
+fn human_duration(secs: i64) -> String {
+    let sign = if secs < 0 { "-" } else { "" };
+    let secs = secs.unsigned_abs();
+    let units = [(86_400, "d"), (3_600, "h"), (60, "m"), (1, "s")];
 
-    let parsed: Vec<LogEntry> = lines
+    let parts: Vec<String> = units
         .iter()
-        .filter_map(|line| parse_log_line(line))
-        .collect();
-
-    let filtered: Vec<LogEntry> = parsed
-        .into_iter()
-        .filter(|e| !cli.errors_only || e.level == LogLevel::Error)
-        .filter(|e| {
-            if let Some(ref needle) = cli.search {
-                let needle = needle.to_lowercase();
-                e.message.to_lowercase().contains(&needle)
-                    || e.timestamp.to_lowercase().contains(&needle)
-                    || format!("{:?}", e.level).to_lowercase().contains(&needle)
-            } else {
-                true
-            }
+        .scan(secs, |rest, &(size, unit)| {
+            let amount = *rest / size;
+            *rest %= size;
+            Some((amount, unit))
         })
+        .skip_while(|(amount, _)| *amount == 0)
+        .take(2)
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
         .collect();
 
-    let stats = analyze_logs(&filtered, cli.top);
-
-    match cli.format {
-        OutputFormat::Text => output_text(&stats),
-        OutputFormat::Json => output_json(&stats),
-        OutputFormat::Csv => output_csv(&stats),
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        format!("{}{}", sign, parts.join(" "))
     }
-}
\ No newline at end of file
+}
+
+/// Bold red, like grep's match color.
+const HIGHLIGHT_START: &str = "\x1b[1;31m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// Wraps every match of `re` in `text` in ANSI codes. prettytable skips
+/// such escapes when measuring columns, so tables stay aligned.
+fn highlight<'a>(text: &'a str, re: &Regex) -> Cow<'a, str> {
+    re.replace_all(text, |caps: &regex::Captures| format!("{}{}{}", HIGHLIGHT_START, &caps[0], HIGHLIGHT_END))
+}
+
+/// How tables are colored and numbers rendered in text output.
+struct TextStyle {
+    color: ColorChoice,
+    level_colors: HashMap<String, char>,
+    humanize: bool,
+    level_case: LevelCase,
+    /// `--highlight`: the search text, case-insensitive; only set when colors are on.
+    highlight: Option<Regex>,
+}
+
+impl TextStyle {
+    fn new(
+        color: ColorChoice,
+        overrides: Option<&HashMap<String, char>>,
+        humanize: bool,
+        level_case: LevelCase,
+    ) -> Self {
+        let mut level_colors: HashMap<String, char> = [
+            ("Fatal", 'R'),
+            ("Error", 'r'),
+            ("Warning", 'y'),
+            ("Info", 'g'),
+            ("Debug", 'c'),
+            ("Trace", 'D'),
+        ]
+        .into_iter()
+        .map(|(level, spec)| (level.to_string(), spec))
+        .collect();
+
+        if let Some(overrides) = overrides {
+            level_colors.extend(overrides.iter().map(|(k, v)| (k.clone(), *v)));
+        }
+
+        TextStyle {
+            color,
+            level_colors,
+            humanize,
+            level_case,
+            highlight: None,
+        }
+    }
+
+    /// Whether stdout gets colors: with `auto`, only when it is a terminal.
+    fn colors_enabled(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => std::io::stdout().is_terminal(),
+        }
+    }
+
+    fn message<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        match &self.highlight {
+            Some(re) => highlight(message, re),
+            None => Cow::Borrowed(message),
+        }
+    }
+
+    fn count(&self, n: usize) -> String {
+        if self.humanize { thousands(n) } else { n.to_string() }
+    }
+
+    fn duration(&self, secs: i64) -> String {
+        if self.humanize { human_duration(secs) } else { format!("{}s", secs) }
+    }
+
+    /// A timestamp, followed when humanized by its age relative to the
+    /// newest entry in the log (not the wall clock, so old files read well).
+    fn seen(&self, timestamp: &str, newest: Option<NaiveDateTime>) -> String {
+        match (self.humanize, parse_timestamp(timestamp), newest) {
+            (true, Some(dt), Some(newest)) => {
+                format!("{} ({} ago)", timestamp, human_duration((newest - dt).num_seconds()))
+            }
+            _ => timestamp.to_string(),
+        }
+    }
+
+    /// `level` is the title-case name, which the colors are keyed by.
+    fn level_cell(&self, level: &str) -> Cell {
+        let name = self.level_case.apply(level);
+        match self.level_colors.get(level) {
+            Some(spec) if self.color != ColorChoice::Never => {
+                Cell::new(&name).style_spec(&format!("F{}", spec))
+            }
+            _ => Cell::new(&name),
+        }
+    }
+
+    /// With `auto`, prettytable only emits colors when stdout is a terminal.
+    fn print(&self, table: &Table) {
+        if self.color == ColorChoice::Always {
+            let _ = table.print_tty(true);
+        } else {
+            table.printstd();
+        }
+    }
+}
+
+/* =========================
+   Report templates
+   ========================= */
+
+/// Values available everywhere in a `--template-file`, besides the gate
+/// metrics (`GATE_METRICS`).
+const REPORT_VALUES: &[&str] = &["start", "end", "duration", "health"];
+
+/// `{#name}...{/name}` sections and the values of each of their rows.
+const REPORT_SECTIONS: &[(&str, &[&str])] = &[
+    ("levels", &["level", "count"]),
+    ("top_errors", &["rank", "message", "count", "percent", "first_seen", "last_seen"]),
+    ("histogram", &["bucket", "count", "bar"]),
+];
+
+/// Width of the fullest `{bar}` in the histogram section.
+const REPORT_BAR_WIDTH: usize = 40;
+
+#[derive(Debug)]
+enum ReportPart {
+    Text(String),
+    Value(String),
+    /// A section name and the parts repeated for each of its rows.
+    Section(String, Vec<ReportPart>),
+}
+
+/// Parses a report template: `{name}` is replaced by a value, text between
+/// `{#section}` and `{/section}` is repeated once per row of the section,
+/// and `{{` / `}}` are literal braces. Sections do not nest; unknown names
+/// are errors.
+fn parse_report_template(text: &str) -> Result<Vec<ReportPart>, String> {
+    let mut parts = Vec::new();
+    // The open section's name and the parts before it.
+    let mut open: Option<(String, Vec<ReportPart>)> = None;
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find(['{', '}']) {
+        literal.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            literal.push(rest.as_bytes()[0] as char);
+            rest = after;
+            continue;
+        }
+        if rest.starts_with('}') {
+            return Err("unmatched '}' (write '}}' for a literal brace)".to_string());
+        }
+        let close = rest.find('}').ok_or("unclosed '{'")?;
+        let tag = &rest[1..close];
+        rest = &rest[close + 1..];
+        if !literal.is_empty() {
+            parts.push(ReportPart::Text(std::mem::take(&mut literal)));
+        }
+
+        if let Some(name) = tag.strip_prefix('#') {
+            if let Some((outer, _)) = &open {
+                return Err(format!("section '{}' inside '{}': sections do not nest", name, outer));
+            }
+            if !REPORT_SECTIONS.iter().any(|(section, _)| *section == name) {
+                return Err(format!("unknown section '{}'", name));
+            }
+            open = Some((name.to_string(), std::mem::take(&mut parts)));
+        } else if let Some(name) = tag.strip_prefix('/') {
+            match open.take() {
+                Some((section, outer)) if section == name => {
+                    let body = std::mem::replace(&mut parts, outer);
+                    parts.push(ReportPart::Section(section, body));
+                }
+                Some((section, _)) => return Err(format!("'{{/{}}}' closes section '{}'", name, section)),
+                None => return Err(format!("'{{/{}}}' without '{{#{}}}'", name, name)),
+            }
+        } else {
+            let row_values = open
+                .as_ref()
+                .and_then(|(section, _)| REPORT_SECTIONS.iter().find(|(name, _)| name == section))
+                .map_or(&[][..], |(_, values)| *values);
+            if !GATE_METRICS.contains(&tag) && !REPORT_VALUES.contains(&tag) && !row_values.contains(&tag) {
+                return Err(format!("unknown placeholder '{{{}}}'", tag));
+            }
+            parts.push(ReportPart::Value(tag.to_string()));
+        }
+    }
+    literal.push_str(rest);
+    if let Some((section, _)) = open {
+        return Err(format!("section '{}' is never closed", section));
+    }
+    if !literal.is_empty() {
+        parts.push(ReportPart::Text(literal));
+    }
+    Ok(parts)
+}
+
+fn load_report_template(path: &std::path::Path) -> Result<Vec<ReportPart>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_report_template(&text)
+}
+
+/// Renders a `--template-file` report. `histogram` holds the entry count
+/// of every `--template-bucket` bucket, in time order.
+fn render_report(
+    parts: &[ReportPart],
+    stats: &LogStats,
+    histogram: &[(String, usize)],
+    style: &TextStyle,
+) -> String {
+    let value = |name: &str| -> String {
+        match name {
+            "start" => stats.time_range.as_ref().map(|r| r.start.clone()).unwrap_or_default(),
+            "end" => stats.time_range.as_ref().map(|r| r.end.clone()).unwrap_or_default(),
+            "duration" => stats.time_range.as_ref().map(|r| style.duration(r.seconds)).unwrap_or_default(),
+            "health" => match stats.health.as_ref().map(|h| h.score) {
+                Some(Some(score)) => score.to_string(),
+                Some(None) => "n/a".to_string(),
+                None => String::new(),
+            },
+            "error_rate" => format!("{:.4}", metric_value(stats, name)),
+            metric => style.count(metric_value(stats, metric) as usize),
+        }
+    };
+
+    let mut out = String::new();
+    for part in parts {
+        match part {
+            ReportPart::Text(text) => out.push_str(text),
+            ReportPart::Value(name) => out.push_str(&value(name)),
+            ReportPart::Section(section, body) => {
+                for row in report_rows(section, stats, histogram, style) {
+                    for part in body {
+                        match part {
+                            ReportPart::Text(text) => out.push_str(text),
+                            ReportPart::Value(name) => match row.iter().find(|(key, _)| key == name) {
+                                Some((_, v)) => out.push_str(v),
+                                None => out.push_str(&value(name)),
+                            },
+                            ReportPart::Section(..) => unreachable!("sections do not nest"),
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The rows of one report section, as (placeholder, value) pairs.
+fn report_rows(
+    section: &str,
+    stats: &LogStats,
+    histogram: &[(String, usize)],
+    style: &TextStyle,
+) -> Vec<Vec<(&'static str, String)>> {
+    match section {
+        "levels" => {
+            let mut levels: Vec<(&String, &usize)> = stats.by_level.iter().collect();
+            levels.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            levels
+                .into_iter()
+                .map(|(level, count)| vec![("level", style.level_case.apply(level)), ("count", style.count(*count))])
+                .collect()
+        }
+        "top_errors" => stats
+            .top_errors
+            .iter()
+            .enumerate()
+            .map(|(i, err)| {
+                vec![
+                    ("rank", (i + 1).to_string()),
+                    ("message", err.message.clone()),
+                    ("count", style.count(err.count)),
+                    ("percent", err.percent.map(|p| p.to_string()).unwrap_or_default()),
+                    ("first_seen", err.first_seen.clone().unwrap_or_default()),
+                    ("last_seen", err.last_seen.clone().unwrap_or_default()),
+                ]
+            })
+            .collect(),
+        "histogram" => {
+            let max = histogram.iter().map(|(_, count)| *count).max().unwrap_or(0).max(1);
+            histogram
+                .iter()
+                .map(|(bucket, count)| {
+                    let bar = (count * REPORT_BAR_WIDTH).div_ceil(max);
+                    vec![("bucket", bucket.clone()), ("count", style.count(*count)), ("bar", "#".repeat(bar))]
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Entries per bucket over all levels, from `rollup`'s per-level rows.
+fn histogram(rows: Vec<RollupRow>) -> Vec<(String, usize)> {
+    let mut buckets: Vec<(String, usize)> = Vec::new();
+    for row in rows {
+        match buckets.last_mut() {
+            Some((bucket, count)) if *bucket == row.bucket => *count += row.count,
+            _ => buckets.push((row.bucket, row.count)),
+        }
+    }
+    buckets
+}
+
+fn output_days_text(days: &[WindowStats], style: &TextStyle) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Date"),
+        Cell::new("Total"),
+        style.level_cell("Error"),
+        style.level_cell("Warning"),
+        Cell::new("Error rate"),
+        Cell::new("Top error"),
+        Cell::new("Biggest gap"),
+    ]));
+
+    for day in days {
+        let count = |level: &str| style.count(day.by_level.get(level).copied().unwrap_or(0));
+        let date = day.start.get(..10).unwrap_or(&day.start);
+        let date = if day.total == 0 {
+            format!("{} (no entries)", date)
+        } else {
+            date.to_string()
+        };
+        table.add_row(Row::new(vec![
+            Cell::new(&date),
+            Cell::new(&style.count(day.total)),
+            Cell::new(&style.count(error_count(&day.by_level))),
+            Cell::new(&count("Warning")),
+            Cell::new(&format!("{:.1}%", day.error_rate * 100.0)),
+            Cell::new(
+                &day.top_error
+                    .as_ref()
+                    .map(|e| format!("{} ({})", e.message, style.count(e.count)))
+                    .unwrap_or_default(),
+            ),
+            Cell::new(
+                &day.biggest_gap_seconds
+                    .map(|g| style.duration(g))
+                    .unwrap_or_default(),
+            ),
+        ]));
+    }
+
+    style.print(&table);
+}
+
+fn output_days_csv(days: &[WindowStats], delimiter: char) {
+    let header = [
+        "date", "total", "errors", "warnings", "error_rate", "top_error", "top_error_count", "biggest_gap_seconds",
+    ];
+
+    let records = days.iter().map(|day| {
+        let count = |level: &str| day.by_level.get(level).copied().unwrap_or(0).to_string();
+        let (message, top_count) = day
+            .top_error
+            .as_ref()
+            .map_or((String::new(), String::new()), |e| (e.message.clone(), e.count.to_string()));
+        [
+            day.start.get(..10).unwrap_or(&day.start).to_string(),
+            day.total.to_string(),
+            error_count(&day.by_level).to_string(),
+            count("Warning"),
+            format!("{:.4}", day.error_rate),
+            message,
+            top_count,
+            day.biggest_gap_seconds.map(|g| g.to_string()).unwrap_or_default(),
+        ]
+    });
+    print_csv(header, records, delimiter);
+}
+
+fn output_windows_text(windows: &[WindowStats], style: &TextStyle) {
+    let mut table = Table::new();
+    let mut header = vec![Cell::new("Window start"), Cell::new("Total")];
+    header.extend(LogLevel::ALL.iter().map(|l| style.level_cell(&format!("{:?}", l))));
+    header.extend([Cell::new("Error rate"), Cell::new("Top error")]);
+    table.add_row(Row::new(header));
+
+    for window in windows {
+        let start = if window.partial {
+            format!("{} *", window.start)
+        } else {
+            window.start.clone()
+        };
+        let mut row = vec![Cell::new(&start), Cell::new(&style.count(window.total))];
+        row.extend(LogLevel::ALL.iter().map(|l| {
+            let count = window.by_level.get(&format!("{:?}", l)).copied().unwrap_or(0);
+            Cell::new(&style.count(count))
+        }));
+        row.push(Cell::new(&format!("{:.1}%", window.error_rate * 100.0)));
+        row.push(Cell::new(
+            &window
+                .top_error
+                .as_ref()
+                .map(|e| format!("{} ({})", e.message, style.count(e.count)))
+                .unwrap_or_default(),
+        ));
+        table.add_row(Row::new(row));
+    }
+
+    style.print(&table);
+    if windows.iter().any(|w| w.partial) {
+        println!("* partial window: the log does not cover the whole window");
+    }
+}
+
+fn output_windows_json(windows: &[WindowStats], compact: bool) {
+    println!("{}", to_json(windows, compact));
+}
+
+fn output_windows_csv(windows: &[WindowStats], delimiter: char) {
+    let mut header: Vec<String> = ["start", "end", "partial", "total"].map(String::from).to_vec();
+    header.extend(LogLevel::ALL.iter().map(|l| format!("{:?}", l).to_lowercase()));
+    header.extend(["error_rate", "top_error", "top_error_count"].map(String::from));
+
+    let records = windows.iter().map(|window| {
+        let (message, count) = window
+            .top_error
+            .as_ref()
+            .map_or((String::new(), String::new()), |e| (e.message.clone(), e.count.to_string()));
+        let mut record = vec![
+            window.start.clone(),
+            window.end.clone(),
+            window.partial.to_string(),
+            window.total.to_string(),
+        ];
+        record.extend(
+            LogLevel::ALL
+                .iter()
+                .map(|l| window.by_level.get(&format!("{:?}", l)).copied().unwrap_or(0).to_string()),
+        );
+        record.extend([format!("{:.4}", window.error_rate), message, count]);
+        record
+    });
+    print_csv(header, records, delimiter);
+}
+
+/// Prints `header` and then `records` to standard output through the same
+/// `csv` writer as `write_entries_csv`.
+fn print_csv<H, R>(header: H, records: impl IntoIterator<Item = R>, delimiter: char)
+where
+    H: IntoIterator,
+    H::Item: AsRef<[u8]>,
+    R: IntoIterator,
+    R::Item: AsRef<[u8]>,
+{
+    // `parse_csv_delimiter` only accepts ASCII.
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter as u8)
+        .from_writer(std::io::stdout().lock());
+    let result = writer
+        .write_record(header)
+        .and_then(|_| records.into_iter().try_for_each(|record| writer.write_record(record)))
+        .and_then(|_| writer.flush().map_err(Into::into));
+    if let Err(e) = result {
+        eprintln!("❌ Failed to write output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+
+
+fn output_text(stats: &LogStats, style: &TextStyle) {
+    println!("\nLog Analysis Results");
+    println!("====================");
+    println!("Total entries: {}", style.count(stats.total_entries));
+    if let Some(range) = &stats.time_range {
+        println!(
+            "Time range: {} – {} ({})",
+            range.start,
+            range.end,
+            style.duration(range.seconds)
+        );
+    }
+    println!();
+
+    if let Some(health) = &stats.health {
+        match health.score {
+            Some(score) => println!("Health score: {}/100", score),
+            None => println!("Health score: n/a (nothing to score)"),
+        }
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("Component"),
+            Cell::new("Weight"),
+            Cell::new("Value"),
+            Cell::new("Points"),
+        ]));
+
+        for component in &health.components {
+            let na = || "n/a".to_string();
+            table.add_row(Row::new(vec![
+                Cell::new(component.name),
+                Cell::new(&component.weight.to_string()),
+                Cell::new(&component.value.map_or_else(na, |v| format!("{:.4}", v))),
+                Cell::new(&component.points.map_or_else(na, |p| p.to_string())),
+            ]));
+        }
+
+        style.print(&table);
+        println!();
+    }
+
+    let quality = &stats.quality;
+    if quality.unparsed_lines > 0 {
+        println!(
+            "Note: {} line(s) could not be parsed",
+            style.count(quality.unparsed_lines)
+        );
+    }
+    if quality.invalid_records > 0 {
+        println!(
+            "Note: {} of them were invalid records (malformed JSON or --require-fields)",
+            style.count(quality.invalid_records)
+        );
+    }
+    if quality.truncated_lines > 0 {
+        println!(
+            "Note: {} line(s) longer than --max-line-bytes were truncated",
+            style.count(quality.truncated_lines)
+        );
+    }
+    if quality.inherited_entries > 0 {
+        println!(
+            "Note: {} entries inherited the timestamp of the previous entry",
+            style.count(quality.inherited_entries)
+        );
+    }
+    if quality.duplicates_removed > 0 {
+        println!(
+            "Note: {} duplicate entries removed",
+            style.count(quality.duplicates_removed)
+        );
+    }
+    if let Some(jump) = &quality.largest_backward_jump {
+        println!(
+            "Note: timestamps go backwards {} time(s); largest jump {} between line {} ({}) and line {} ({})",
+            style.count(quality.backward_steps),
+            style.duration(jump.seconds),
+            jump.from_line,
+            jump.from,
+            jump.to_line,
+            jump.to
+        );
+    }
+    for jump in &quality.forward_jumps {
+        println!(
+            "Note: timestamps jump forward {} between line {} ({}) and line {} ({})",
+            style.duration(jump.seconds),
+            jump.from_line,
+            jump.from,
+            jump.to_line,
+            jump.to
+        );
+    }
+    if quality.unparsed_lines > 0
+        || quality.invalid_records > 0
+        || quality.truncated_lines > 0
+        || quality.inherited_entries > 0
+        || quality.duplicates_removed > 0
+        || quality.largest_backward_jump.is_some()
+        || !quality.forward_jumps.is_empty()
+    {
+        println!();
+    }
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Level"),
+        Cell::new("Count"),
+    ]));
+
+    for (level, count) in &stats.by_level {
+        table.add_row(Row::new(vec![
+            style.level_cell(level),
+            Cell::new(&style.count(*count)),
+        ]));
+    }
+
+    style.print(&table);
+
+    if !stats.top_errors.is_empty() {
+        if stats.top_errors_approximate {
+            println!("\nTop errors (approximate):");
+        } else {
+            println!("\nTop errors:");
+        }
+        let mut err_table = Table::new();
+        err_table.add_row(Row::new(vec![
+            Cell::new("Message"),
+            Cell::new("Occurrences"),
+            Cell::new("Share"),
+            Cell::new("First seen"),
+            Cell::new("Last seen"),
+        ]));
+
+        let newest = stats.time_range.as_ref().and_then(|r| parse_timestamp(&r.end));
+        let seen = |ts: &Option<String>| {
+            ts.as_deref().map(|ts| style.seen(ts, newest)).unwrap_or_default()
+        };
+        for err in &stats.top_errors {
+            let count = match err.max_overcount {
+                Some(over) if over > 0 => format!("{} (overcount ≤ {})", style.count(err.count), style.count(over)),
+                _ => style.count(err.count),
+            };
+            err_table.add_row(Row::new(vec![
+                Cell::new(&style.message(&err.message)),
+                Cell::new(&count),
+                Cell::new(&err.percent.map(|p| format!("{}%", p)).unwrap_or_default()),
+                Cell::new(&seen(&err.first_seen)),
+                Cell::new(&seen(&err.last_seen)),
+            ]));
+        }
+
+        style.print(&err_table);
+    }
+
+    if let Some(levels) = &stats.top_by_level {
+        println!("\nTop messages by level:");
+        let newest = stats.time_range.as_ref().and_then(|r| parse_timestamp(&r.end));
+        let seen = |ts: &Option<String>| {
+            ts.as_deref().map(|ts| style.seen(ts, newest)).unwrap_or_default()
+        };
+        for level in levels {
+            println!("\n{}:", style.level_case.apply(&level.level));
+            let mut table = Table::new();
+            table.add_row(Row::new(vec![
+                Cell::new("Message"),
+                Cell::new("Occurrences"),
+                Cell::new("Share"),
+                Cell::new("First seen"),
+                Cell::new("Last seen"),
+            ]));
+            for err in &level.top {
+                table.add_row(Row::new(vec![
+                    Cell::new(&style.message(&err.message)),
+                    Cell::new(&style.count(err.count)),
+                    Cell::new(&err.percent.map(|p| format!("{}%", p)).unwrap_or_default()),
+                    Cell::new(&seen(&err.first_seen)),
+                    Cell::new(&seen(&err.last_seen)),
+                ]));
+            }
+            style.print(&table);
+        }
+    }
+
+    if let Some(owners) = &stats.owners {
+        println!("\nErrors by owner:");
+        let mut owner_table = Table::new();
+        owner_table.add_row(Row::new(vec![
+            Cell::new("Owner"),
+            Cell::new("Errors"),
+            Cell::new("Top message"),
+        ]));
+
+        for owner in owners {
+            owner_table.add_row(Row::new(vec![
+                Cell::new(&owner.owner),
+                Cell::new(&style.count(owner.errors)),
+                Cell::new(owner.top_message.as_deref().unwrap_or("")),
+            ]));
+        }
+
+        style.print(&owner_table);
+
+        if let Some(unassigned) = owners.iter().find(|o| !o.groups.is_empty()) {
+            println!("\nLargest unassigned errors:");
+            let mut group_table = Table::new();
+            group_table.add_row(Row::new(vec![
+                Cell::new("Template"),
+                Cell::new("Occurrences"),
+            ]));
+
+            for group in &unassigned.groups {
+                group_table.add_row(Row::new(vec![
+                    Cell::new(&group.message),
+                    Cell::new(&style.count(group.count)),
+                ]));
+            }
+
+            style.print(&group_table);
+        }
+    }
+
+    if let Some(files) = &stats.files {
+        println!("\nEntries by file:");
+        let mut file_table = Table::new();
+        file_table.add_row(Row::new(vec![
+            Cell::new("File"),
+            Cell::new("Entries"),
+            Cell::new("Errors"),
+            Cell::new("Warnings"),
+        ]));
+
+        for file in files {
+            let level = |name: &str| style.count(file.by_level.get(name).copied().unwrap_or(0));
+            file_table.add_row(Row::new(vec![
+                Cell::new(&file.file),
+                Cell::new(&style.count(file.total_entries)),
+                Cell::new(&style.count(error_count(&file.by_level))),
+                Cell::new(&level("Warning")),
+            ]));
+        }
+
+        style.print(&file_table);
+    }
+
+    if let Some(groups) = &stats.group_counts {
+        println!("\nCounts by {}:", groups.group);
+        let mut group_table = Table::new();
+        let distinct = groups.counts.iter().any(|v| v.distinct_messages.is_some());
+        let mut header = vec![Cell::new(&groups.group), Cell::new("Count")];
+        if distinct {
+            header.push(Cell::new("Distinct"));
+        }
+        group_table.add_row(Row::new(header));
+
+        for value in &groups.counts {
+            let mut row = vec![Cell::new(&value.value), Cell::new(&style.count(value.count))];
+            if let Some(n) = value.distinct_messages {
+                row.push(Cell::new(&style.count(n)));
+            }
+            group_table.add_row(Row::new(row));
+        }
+
+        style.print(&group_table);
+    }
+
+    if let Some(alerts) = &stats.alerts {
+        println!("\nAlerts:");
+        let mut alert_table = Table::new();
+        alert_table.add_row(Row::new(vec![
+            Cell::new("Pattern"),
+            Cell::new("Count"),
+            Cell::new("Max"),
+            Cell::new("Status"),
+            Cell::new("First seen"),
+            Cell::new("Last seen"),
+            Cell::new("Sample"),
+        ]));
+
+        let newest = stats.time_range.as_ref().and_then(|r| parse_timestamp(&r.end));
+        let seen = |ts: &Option<String>| {
+            ts.as_deref().map(|ts| style.seen(ts, newest)).unwrap_or_default()
+        };
+        for alert in alerts {
+            let sample = match (alert.sample_line, &alert.sample) {
+                (Some(line), Some(message)) => format!("line {}: {}", line, message),
+                _ => String::new(),
+            };
+            alert_table.add_row(Row::new(vec![
+                Cell::new(&alert.pattern),
+                Cell::new(&style.count(alert.count)),
+                Cell::new(&style.count(alert.max)),
+                Cell::new(if alert.triggered { "ALERT" } else { "ok" }),
+                Cell::new(&seen(&alert.first_seen)),
+                Cell::new(&seen(&alert.last_seen)),
+                Cell::new(&sample),
+            ]));
+        }
+
+        style.print(&alert_table);
+    }
+
+    if let Some(queries) = stats.slow_queries.as_ref().filter(|q| !q.is_empty()) {
+        println!("\nTop slow queries:");
+        let mut query_table = Table::new();
+        query_table.add_row(Row::new(vec![
+            Cell::new("Template"),
+            Cell::new("Count"),
+            Cell::new("Total (s)"),
+            Cell::new("p95 (s)"),
+            Cell::new("Max (s)"),
+            Cell::new("Avg lock (s)"),
+            Cell::new("Rows examined"),
+        ]));
+
+        for query in queries {
+            query_table.add_row(Row::new(vec![
+                Cell::new(&query.template),
+                Cell::new(&style.count(query.count)),
+                Cell::new(&format!("{:.3}", query.total_query_time)),
+                Cell::new(&format!("{:.3}", query.p95_query_time)),
+                Cell::new(&format!("{:.3}", query.max_query_time)),
+                Cell::new(&format!("{:.3}", query.avg_lock_time)),
+                Cell::new(&style.count(query.rows_examined as usize)),
+            ]));
+        }
+
+        style.print(&query_table);
+    }
+
+    if let Some(requests) = &stats.requests {
+        if !requests.endpoints.is_empty() {
+            println!("\nEndpoints:");
+            let mut endpoint_table = Table::new();
+            endpoint_table.add_row(Row::new(vec![
+                Cell::new("Endpoint"),
+                Cell::new("Requests"),
+                Cell::new("5xx"),
+                Cell::new("4xx"),
+                Cell::new("p95 (ms)"),
+                Cell::new("Max (ms)"),
+                Cell::new("Avg DB (ms)"),
+                Cell::new("Avg view (ms)"),
+            ]));
+
+            let ms = |value: Option<f64>| value.map_or_else(|| "-".to_string(), |v| v.to_string());
+            for endpoint in &requests.endpoints {
+                endpoint_table.add_row(Row::new(vec![
+                    Cell::new(&endpoint.endpoint),
+                    Cell::new(&style.count(endpoint.requests)),
+                    Cell::new(&style.count(endpoint.server_errors)),
+                    Cell::new(&style.count(endpoint.client_errors)),
+                    Cell::new(&ms(endpoint.p95_ms)),
+                    Cell::new(&ms(endpoint.max_ms)),
+                    Cell::new(&ms(endpoint.avg_db_ms)),
+                    Cell::new(&ms(endpoint.avg_view_ms)),
+                ]));
+            }
+
+            style.print(&endpoint_table);
+        }
+        if requests.unfinished > 0 {
+            println!("\nNote: {} request(s) started but never completed", requests.unfinished);
+        }
+    }
+
+    if let Some(http) = &stats.http {
+        print!("\nHTTP requests: {}", style.count(http.requests));
+        match http.bytes_sent {
+            Some(bytes) => println!(" ({} bytes sent)", thousands(bytes as usize)),
+            None => println!(),
+        }
+        let mut status_table = Table::new();
+        status_table.add_row(Row::new(vec![Cell::new("Status"), Cell::new("Requests"), Cell::new("%")]));
+        for (class, count) in &http.status_classes {
+            let percent = round1(100.0 * *count as f64 / http.requests as f64);
+            status_table.add_row(Row::new(vec![
+                Cell::new(class),
+                Cell::new(&style.count(*count)),
+                Cell::new(&percent.to_string()),
+            ]));
+        }
+        style.print(&status_table);
+
+        for (title, heading, counts) in [
+            ("Top paths", "Path", &http.top_paths),
+            ("Top clients", "Client", &http.top_clients),
+        ] {
+            if counts.is_empty() {
+                continue;
+            }
+            println!("\n{}:", title);
+            let mut table = Table::new();
+            table.add_row(Row::new(vec![Cell::new(heading), Cell::new("Requests"), Cell::new("%")]));
+            for count in counts {
+                table.add_row(Row::new(vec![
+                    Cell::new(&count.value),
+                    Cell::new(&style.count(count.requests)),
+                    Cell::new(&count.percent.to_string()),
+                ]));
+            }
+            style.print(&table);
+        }
+
+        if !http.slowest_endpoints.is_empty() {
+            println!("\nSlowest endpoints:");
+            let mut slow_table = Table::new();
+            slow_table.add_row(Row::new(vec![
+                Cell::new("Endpoint"),
+                Cell::new("Requests"),
+                Cell::new("p95 (ms)"),
+                Cell::new("Max (ms)"),
+                Cell::new("Avg (ms)"),
+            ]));
+            for endpoint in &http.slowest_endpoints {
+                slow_table.add_row(Row::new(vec![
+                    Cell::new(&endpoint.endpoint),
+                    Cell::new(&style.count(endpoint.requests)),
+                    Cell::new(&endpoint.p95_ms.to_string()),
+                    Cell::new(&endpoint.max_ms.to_string()),
+                    Cell::new(&endpoint.avg_ms.to_string()),
+                ]));
+            }
+            style.print(&slow_table);
+        }
+    }
+
+    if let Some(pairs) = &stats.correlated_pairs {
+        println!("\nCorrelated errors:");
+        let mut pair_table = Table::new();
+        pair_table.add_row(Row::new(vec![
+            Cell::new("First"),
+            Cell::new("Then"),
+            Cell::new("Count"),
+        ]));
+
+        for pair in pairs {
+            pair_table.add_row(Row::new(vec![
+                Cell::new(&pair.first),
+                Cell::new(&pair.then),
+                Cell::new(&style.count(pair.count)),
+            ]));
+        }
+
+        style.print(&pair_table);
+    }
+
+    if let Some(storms) = &stats.retry_storms {
+        if storms.is_empty() {
+            println!("\nRetry storms: none");
+        } else {
+            println!("\nRetry storms:");
+            let mut storm_table = Table::new();
+            storm_table.add_row(Row::new(vec![
+                Cell::new("Message"),
+                Cell::new("Start"),
+                Cell::new("End"),
+                Cell::new("Count"),
+                Cell::new("Rate"),
+            ]));
+
+            for storm in storms {
+                storm_table.add_row(Row::new(vec![
+                    Cell::new(&storm.message),
+                    Cell::new(&storm.start),
+                    Cell::new(&storm.end),
+                    Cell::new(&style.count(storm.count)),
+                    Cell::new(&format!("{:.1}/s", storm.per_second)),
+                ]));
+            }
+
+            style.print(&storm_table);
+        }
+    }
+
+    if let Some(peaks) = &stats.peaks {
+        let rate = |peak: &Peak| {
+            if peaks.resolution_seconds == 1 {
+                format!("{} entries/s", style.count(peak.count))
+            } else {
+                format!(
+                    "{} entries in {} ({:.2}/s)",
+                    style.count(peak.count),
+                    style.duration(peaks.resolution_seconds),
+                    peak.per_second
+                )
+            }
+        };
+        match &peaks.overall {
+            Some(peak) => println!("\nPeak: {} with {}", peak.start, rate(peak)),
+            None => println!("\nPeak: no timestamped entries"),
+        }
+        if !peaks.by_level.is_empty() {
+            let mut peak_table = Table::new();
+            peak_table.add_row(Row::new(vec![
+                Cell::new("Level"),
+                Cell::new("Peak"),
+                Cell::new("Rate"),
+            ]));
+
+            for (level, peak) in &peaks.by_level {
+                peak_table.add_row(Row::new(vec![
+                    style.level_cell(level),
+                    Cell::new(&peak.start),
+                    Cell::new(&rate(peak)),
+                ]));
+            }
+
+            style.print(&peak_table);
+        }
+    }
+
+    if let Some(sessions) = &stats.sessions {
+        println!(
+            "\nSessions: {} ({} with errors, {:.1} entries on average)",
+            style.count(sessions.sessions),
+            style.count(sessions.error_sessions),
+            sessions.mean_entries
+        );
+        let with_key = sessions.largest.iter().any(|s| s.key.is_some());
+        let mut session_table = Table::new();
+        let mut header = vec![Cell::new("Start"), Cell::new("End")];
+        if with_key {
+            header.push(Cell::new("Key"));
+        }
+        header.extend([Cell::new("Entries"), Cell::new("Errors")]);
+        session_table.add_row(Row::new(header));
+
+        for session in &sessions.largest {
+            let mut row = vec![Cell::new(&session.start), Cell::new(&session.end)];
+            if with_key {
+                row.push(Cell::new(session.key.as_deref().unwrap_or_default()));
+            }
+            row.extend([
+                Cell::new(&style.count(session.entries)),
+                Cell::new(&style.count(session.errors)),
+            ]);
+            session_table.add_row(Row::new(row));
+        }
+
+        style.print(&session_table);
+    }
+
+    if let Some(contexts) = &stats.error_contexts {
+        println!("\nError context:");
+        for (i, context) in contexts.iter().enumerate() {
+            if i > 0 {
+                println!("--");
+            }
+            for entry in &context.entries {
+                let marker = if entry.level.is_error() { ">" } else { " " };
+                let level = style.level_case.apply(&format!("{:?}", entry.level));
+                println!(
+                    "{} {:>6}  {} [{}] {}",
+                    marker,
+                    entry.line,
+                    entry.timestamp,
+                    level,
+                    style.message(&entry.message)
+                );
+            }
+        }
+    }
+}
+
+/// Pretty-printed unless `compact` (`--json-compact`).
+fn to_json<T: Serialize + ?Sized>(value: &T, compact: bool) -> String {
+    let json = if compact { serde_json::to_string(value) } else { serde_json::to_string_pretty(value) };
+    json.unwrap()
+}
+
+fn output_json(stats: &LogStats, compact: bool) {
+    println!("{}", to_json(stats, compact));
+}
+
+/// Writes `{"entries": [...], "stats": {...}}`, serializing one entry at a
+/// time so the document is never built in memory. Stats come last because
+/// they need the full pass. With `compact` the whole document is one line.
+fn write_json_with_entries<'a, W: Write>(
+    out: &mut W,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    stats: &LogStats,
+    compact: bool,
+) -> std::io::Result<()> {
+    let (open, first, next, close) = if compact {
+        ("{\"entries\":[", "", ",", "],\"stats\":")
+    } else {
+        ("{\n  \"entries\": [", "\n    ", ",\n    ", "\n  ],\n  \"stats\": ")
+    };
+    out.write_all(open.as_bytes())?;
+
+    for (i, entry) in entries.into_iter().enumerate() {
+        out.write_all(if i == 0 { first } else { next }.as_bytes())?;
+        serde_json::to_writer(&mut *out, entry)?;
+    }
+
+    out.write_all(close.as_bytes())?;
+    if compact {
+        serde_json::to_writer(&mut *out, stats)?;
+        writeln!(out, "}}")?;
+    } else {
+        serde_json::to_writer_pretty(&mut *out, stats)?;
+        writeln!(out, "\n}}")?;
+    }
+    out.flush()
+}
+
+fn output_json_with_entries(entries: &[LogEntry], stats: &LogStats, compact: bool, mut out: Output) {
+    if let Err(e) = write_json_with_entries(&mut out, entries, stats, compact).and_then(|_| out.finish()) {
+        eprintln!("❌ Failed to write output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/* =========================
+   Entry export
+   ========================= */
+
+/// Destination of entry exports: stdout, a file, or a gzipped file.
+enum Output {
+    Stdout(BufWriter<std::io::StdoutLock<'static>>),
+    File(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    fn open(path: Option<&std::path::Path>, compress: bool) -> std::io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Output::Stdout(BufWriter::new(std::io::stdout().lock())));
+        };
+        let file = BufWriter::new(File::create(path)?);
+        if compress || path.extension().is_some_and(|ext| ext == "gz") {
+            Ok(Output::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Output::File(file))
+        }
+    }
+
+    /// Writes the gzip trailer and flushes everything. Dropping a `GzEncoder`
+    /// would finish it too, but silently, leaving a truncated file on error.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(mut out) => out.flush(),
+            Output::File(mut out) => out.flush(),
+            Output::Gzip(out) => out.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stdout(out) => out.write(buf),
+            Output::File(out) => out.write(buf),
+            Output::Gzip(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(out) => out.flush(),
+            Output::File(out) => out.flush(),
+            Output::Gzip(out) => out.flush(),
+        }
+    }
+}
+
+/// Writes one CSV record per entry as it goes, with proper quoting.
+fn write_entries_csv<'a, W: Write>(
+    out: W,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    columns: &[String],
+    header: bool,
+    delimiter: u8,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(out);
+    if header {
+        writer.write_record(columns)?;
+    }
+    for entry in entries {
+        writer.write_record(columns.iter().map(|c| entry_column(entry, c)))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// One JSON object per line, restricted to the selected columns. Values are
+/// typed as in `--include-entries`: `line` is a number, and inherited entries
+/// carry `"inherited": true`.
+fn write_entries_jsonl<'a, W: Write>(
+    out: &mut W,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    columns: &[String],
+) -> std::io::Result<()> {
+    for entry in entries {
+        let mut object: serde_json::Map<String, serde_json::Value> = columns
+            .iter()
+            .map(|c| {
+                let value = match c.as_str() {
+                    "line" => entry.line.into(),
+                    "level" => serde_json::to_value(&entry.level).expect("a level serializes"),
+                    _ => entry_column(entry, c).into(),
+                };
+                (c.clone(), value)
+            })
+            .collect();
+        if entry.inherited {
+            object.insert("inherited".to_string(), true.into());
+        }
+        serde_json::to_writer(&mut *out, &object)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Matches of `highlight_re` in the message column are highlighted.
+fn write_entries_text<'a, W: Write>(
+    out: &mut W,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    columns: &[String],
+    highlight_re: Option<&Regex>,
+) -> std::io::Result<()> {
+    for entry in entries {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|c| match highlight_re {
+                Some(re) if c == "message" => highlight(&entry.message, re).into_owned(),
+                _ => entry_column(entry, c),
+            })
+            .collect();
+        writeln!(out, "{}", values.join(" "))?;
+    }
+    out.flush()
+}
+
+fn output_entries(
+    entries: &[LogEntry],
+    format: &OutputFormat,
+    columns: &[String],
+    header: bool,
+    delimiter: char,
+    highlight: Option<&Regex>,
+    mut out: Output,
+) {
+    let result = match format {
+        OutputFormat::Text => write_entries_text(&mut out, entries, columns, highlight),
+        OutputFormat::Json => write_entries_jsonl(&mut out, entries, columns),
+        // `parse_csv_delimiter` only accepts ASCII.
+        OutputFormat::Csv => {
+            write_entries_csv(&mut out, entries, columns, header, delimiter as u8).map_err(Into::into)
+        }
+    };
+    if let Err(e) = result.and_then(|_| out.finish()) {
+        eprintln!("❌ Failed to write output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+
+
+/// `--parse-stats-only`: how much of the input the parser understood.
+#[derive(Debug, Serialize)]
+struct ParseStats {
+    lines: usize,
+    entries: usize,
+    unparsed_lines: usize,
+    invalid_records: usize,
+    truncated_lines: usize,
+    by_level: BTreeMap<String, usize>,
+}
+
+fn parse_stats(log: &ParsedLog) -> ParseStats {
+    let mut by_level = BTreeMap::new();
+    for entry in &log.entries {
+        *by_level.entry(format!("{:?}", entry.level)).or_insert(0) += 1;
+    }
+    ParseStats {
+        lines: log.lines,
+        entries: log.entries.len(),
+        unparsed_lines: log.unparsed_lines,
+        invalid_records: log.invalid_records,
+        truncated_lines: log.truncated_lines,
+        by_level,
+    }
+}
+
+fn output_parse_stats_text(stats: &ParseStats, style: &TextStyle) {
+    println!("Lines: {}", style.count(stats.lines));
+    println!("Entries: {}", style.count(stats.entries));
+    let share = if stats.lines == 0 { 0.0 } else { 100.0 * stats.unparsed_lines as f64 / stats.lines as f64 };
+    println!("Unparsed lines: {} ({:.1}%)", style.count(stats.unparsed_lines), share);
+    if stats.invalid_records > 0 {
+        println!("Invalid records: {}", style.count(stats.invalid_records));
+    }
+    if stats.truncated_lines > 0 {
+        println!("Truncated lines: {}", style.count(stats.truncated_lines));
+    }
+    if stats.by_level.is_empty() {
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![Cell::new("Level"), Cell::new("Count")]));
+    for (level, count) in &stats.by_level {
+        table.add_row(Row::new(vec![style.level_cell(level), Cell::new(&style.count(*count))]));
+    }
+    style.print(&table);
+}
+
+/// `metric,value` rows; level counts are named `by_level.<level>`.
+fn output_parse_stats_csv(stats: &ParseStats, delimiter: char) {
+    let totals = [
+        ("lines", stats.lines),
+        ("entries", stats.entries),
+        ("unparsed_lines", stats.unparsed_lines),
+        ("invalid_records", stats.invalid_records),
+        ("truncated_lines", stats.truncated_lines),
+    ];
+    let records = totals
+        .into_iter()
+        .map(|(metric, value)| [metric.to_string(), value.to_string()])
+        .chain(stats.by_level.iter().map(|(level, count)| [format!("by_level.{}", level), count.to_string()]));
+    print_csv(["metric", "value"], records, delimiter);
+}
+
+fn output_csv(stats: &LogStats, delimiter: char) {
+    let records = stats.by_level.iter().map(|(level, count)| [level.clone(), count.to_string()]);
+    print_csv(["level", "count"], records, delimiter);
+}
+
+
+/// One human-readable line for status bars and prompts, such as
+/// `1234 entries | 12 err 45 warn | 03:00–03:59`. The time segment is left
+/// out when there are no timestamps and carries dates when the range spans
+/// more than one day.
+fn output_oneline(stats: &LogStats) {
+    let count = |level: &str| stats.by_level.get(level).copied().unwrap_or(0);
+    let mut line = format!(
+        "{} entries | {} err {} warn",
+        stats.total_entries,
+        error_count(&stats.by_level),
+        count("Warning")
+    );
+
+    if let Some(TimeRange { first: start, last: end, .. }) = stats.time_range {
+        let format = if start.date() == end.date() { "%H:%M" } else { "%Y-%m-%d %H:%M" };
+        line.push_str(&format!(" | {}–{}", start.format(format), end.format(format)));
+    }
+
+    println!("{}", line);
+}
+
+/// Exactly one `key=value` line, for trivial shell consumption.
+fn output_summary_line(stats: &LogStats) {
+    let count = |level: &str| stats.by_level.get(level).copied().unwrap_or(0);
+    let errors = error_count(&stats.by_level);
+    let error_rate = if stats.total_entries == 0 {
+        0.0
+    } else {
+        errors as f64 / stats.total_entries as f64
+    };
+
+    println!(
+        "total={} errors={} warnings={} parse_failures={} error_rate={:.4}",
+        stats.total_entries,
+        errors,
+        count("Warning"),
+        stats.quality.unparsed_lines,
+        error_rate
+    );
+}
+
+fn output_rollup_text(rows: &[RollupRow], style: &TextStyle) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Bucket"),
+        Cell::new("Level"),
+        Cell::new("Count"),
+    ]));
+
+    for row in rows {
+        table.add_row(Row::new(vec![
+            Cell::new(&row.bucket),
+            style.level_cell(&row.level),
+            Cell::new(&style.count(row.count)),
+        ]));
+    }
+
+    style.print(&table);
+}
+
+fn output_trace_text(trace: &Trace, style: &TextStyle) {
+    println!("Trace {}: {} entries", trace.id, style.count(trace.steps.len()));
+    if trace.steps.is_empty() {
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Offset"),
+        Cell::new("Line"),
+        Cell::new("Timestamp"),
+        Cell::new("Level"),
+        Cell::new("Message"),
+    ]));
+
+    for step in &trace.steps {
+        table.add_row(Row::new(vec![
+            Cell::new(&step.offset_seconds.map(|o| format!("+{:.3}s", o)).unwrap_or_default()),
+            Cell::new(&step.line.to_string()),
+            Cell::new(&step.timestamp),
+            style.level_cell(&format!("{:?}", step.level)),
+            Cell::new(&step.message),
+        ]));
+    }
+
+    style.print(&table);
+    if let Some(duration) = trace.duration_seconds {
+        println!("Total duration: {:.3}s", duration);
+    }
+}
+
+fn output_trace_csv(trace: &Trace, delimiter: char) {
+    let records = trace.steps.iter().map(|step| {
+        [
+            step.offset_seconds.map(|o| format!("{:.3}", o)).unwrap_or_default(),
+            step.line.to_string(),
+            step.timestamp.clone(),
+            format!("{:?}", step.level),
+            step.message.clone(),
+        ]
+    });
+    print_csv(["offset_seconds", "line", "timestamp", "level", "message"], records, delimiter);
+}
+
+fn output_split_text(files: &[SplitFile], style: &TextStyle) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("File"),
+        Cell::new("Entries"),
+        Cell::new("Lines"),
+    ]));
+
+    for file in files {
+        table.add_row(Row::new(vec![
+            Cell::new(&file.file),
+            Cell::new(&style.count(file.entries)),
+            Cell::new(&style.count(file.lines)),
+        ]));
+    }
+
+    style.print(&table);
+    let entries: usize = files.iter().map(|f| f.entries).sum();
+    println!("{} files, {} entries written", style.count(files.len()), style.count(entries));
+}
+
+fn output_split_json(files: &[SplitFile], compact: bool) {
+    println!("{}", to_json(files, compact));
+}
+
+fn output_split_csv(files: &[SplitFile], delimiter: char) {
+    let records = files
+        .iter()
+        .map(|file| [file.file.clone(), file.entries.to_string(), file.lines.to_string()]);
+    print_csv(["file", "entries", "lines"], records, delimiter);
+}
+
+fn output_timeseries_text(points: &[SeriesPoint], style: &TextStyle) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Bucket start"),
+        Cell::new("Level"),
+        Cell::new("Count"),
+    ]));
+
+    for point in points {
+        table.add_row(Row::new(vec![
+            Cell::new(&point.bucket_start),
+            style.level_cell(&point.level),
+            Cell::new(&style.count(point.count)),
+        ]));
+    }
+
+    style.print(&table);
+}
+
+fn output_timeseries_csv(points: &[SeriesPoint], delimiter: char) {
+    let records = points
+        .iter()
+        .map(|point| [point.bucket_start.clone(), point.level.clone(), point.count.to_string()]);
+    print_csv(["bucket_start", "level", "count"], records, delimiter);
+}
+
+fn output_volume_text(buckets: &[VolumeBucket], style: &TextStyle) {
+    let mut table = Table::new();
+    let mut header = vec![Cell::new("Bucket start"), Cell::new("Total")];
+    header.extend(LogLevel::ALL.iter().map(|l| style.level_cell(&format!("{:?}", l))));
+    header.push(Cell::new(""));
+    table.add_row(Row::new(header));
+
+    let max = buckets.iter().map(|b| b.total).max().unwrap_or(0).max(1);
+    for bucket in buckets {
+        let mut row = vec![Cell::new(&bucket.bucket_start), Cell::new(&style.count(bucket.total))];
+        row.extend(LogLevel::ALL.iter().map(|l| {
+            let count = bucket.by_level.get(&format!("{:?}", l)).copied().unwrap_or(0);
+            Cell::new(&style.count(count))
+        }));
+        row.push(Cell::new(&"#".repeat((bucket.total * REPORT_BAR_WIDTH).div_ceil(max))));
+        table.add_row(Row::new(row));
+    }
+
+    style.print(&table);
+}
+
+/// Level columns are named as `--level-case` writes levels, like the level
+/// values of `--timeseries`.
+fn output_volume_csv(buckets: &[VolumeBucket], level_case: LevelCase, delimiter: char) {
+    let mut header: Vec<String> = ["bucket_start", "total"].map(String::from).to_vec();
+    header.extend(LogLevel::ALL.iter().map(|l| level_case.apply(&format!("{:?}", l))));
+
+    let records = buckets.iter().map(|bucket| {
+        let mut record = vec![bucket.bucket_start.clone(), bucket.total.to_string()];
+        record.extend(
+            LogLevel::ALL
+                .iter()
+                .map(|l| bucket.by_level.get(&format!("{:?}", l)).copied().unwrap_or(0).to_string()),
+        );
+        record
+    });
+    print_csv(header, records, delimiter);
+}
+
+fn output_rollup_json(rows: &[RollupRow], compact: bool) {
+    println!("{}", to_json(rows, compact));
+}
+
+fn output_rollup_csv(rows: &[RollupRow], delimiter: char) {
+    let records = rows.iter().map(|row| [row.bucket.clone(), row.level.clone(), row.count.to_string()]);
+    print_csv(["bucket", "level", "count"], records, delimiter);
+}
+
+
+
+/// Exits with the wrapped command's code after `run` when it failed.
+fn exit_like_child(code: Option<i32>) {
+    if let Some(code) = code.filter(|&code| code != 0) {
+        std::process::exit(code);
+    }
+}
+
+/// Parses the command line on top of the `LOGLYZER_*` variables. clap
+/// counts environment values in conflicts, so those checks are done here:
+/// a flag given on the command line silently overrides a conflicting
+/// environment value, while two conflicting flags are still an error.
+fn parse_cli() -> (Cli, clap::ArgMatches) {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let source = |id: &str| matches.value_source(id);
+    let conflict = |a: &str, b: &str| {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("the argument '{}' cannot be used with '{}'", a, b),
+            )
+            .exit()
+    };
+
+    let replaces_pattern = source("input_format") == Some(ValueSource::CommandLine)
+        || cli.parser_cmd.is_some();
+    match source("pattern") {
+        Some(ValueSource::EnvVariable) if replaces_pattern => cli.pattern = None,
+        Some(ValueSource::CommandLine) if cli.parser_cmd.is_some() => conflict("--parser-cmd", "--pattern"),
+        Some(ValueSource::CommandLine) if replaces_pattern => conflict("--input-format", "--pattern"),
+        _ => {}
+    }
+    if cli.top_coverage.is_some() && source("top") == Some(ValueSource::CommandLine) {
+        conflict("--top-coverage", "--top");
+    }
+    if cli.input.is_empty() && cli.command.is_none() && !cli.dump_config {
+        if std::io::stdin().is_terminal() {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "the following required arguments were not provided:\n  <FILE>...",
+                )
+                .exit();
+        }
+        cli.input.push(PathBuf::from(STDIN_PATH));
+    }
+
+    (cli, matches)
+}
+
+/// How verbose mode names each exclusion, in `Filter::excluded_by` order.
+fn exclusion_names(cli: &Cli) -> Vec<String> {
+    let texts = cli.exclude_contains.iter().map(|needle| format!("Exclude '{}'", needle));
+    let regexes = cli.exclude_regex.iter().map(|re| format!("Exclude regex '{}'", re));
+    texts.chain(regexes).collect()
+}
+
+/// The filter of --errors-only, --level, --min-level, --search,
+/// --search-regex, --query, --exclude-contains, --exclude-regex and
+/// --since / --until.
+fn entry_filter(cli: &Cli) -> Filter {
+    let now = Utc::now();
+    let since = cli.since.map(|bound| bound.resolve(now, cli.input_tz));
+    let until = cli.until.map(|bound| bound.resolve(now, cli.input_tz));
+    if let (Some(since), Some(until)) = (since, until)
+        && since >= until
+    {
+        eprintln!("❌ --since ({}) must be before --until ({})", since, until);
+        std::process::exit(1);
+    }
+    Filter::new(cli.errors_only, cli.search.as_deref(), &cli.exclude_contains)
+        .levels(&cli.level, cli.min_level.as_ref())
+        .regexes(cli.search_regex.as_ref(), &cli.exclude_regex)
+        .query(cli.query.clone().map(|query| query.resolve(now, cli.input_tz)))
+        .between(since, until)
+}
+
+fn main() {
+    let (mut cli, matches) = parse_cli();
+
+    if cli.dump_config {
+        let config = match cli.config.as_deref().map(load_config).transpose() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("❌ Failed to load config: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let settings = resolved_settings(&matches, config.as_ref());
+        match cli.format {
+            OutputFormat::Json => println!("{}", to_json(&settings, cli.json_compact)),
+            OutputFormat::Text | OutputFormat::Csv => output_settings_text(&settings),
+        }
+        return;
+    }
+
+    let mut inputs = Vec::new();
+    for input in &cli.input {
+        match expand_glob(input) {
+            Ok(files) if files.is_empty() => {
+                eprintln!("❌ No files match {}", input.display());
+                std::process::exit(1);
+            }
+            Ok(files) => inputs.extend(files),
+            Err(e) => {
+                eprintln!("❌ Failed to expand {}: {}", input.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    cli.input = inputs;
+
+    if cli.input.iter().filter(|input| is_stdin(input)).count() > 1 {
+        eprintln!("❌ {} (standard input) can only be given once", STDIN_PATH);
+        std::process::exit(1);
+    }
+    if cli.input.iter().any(|input| is_stdin(input)) {
+        let seekable = [
+            ("--split-by", cli.split_by.is_some()),
+            ("--cache", cli.cache.is_some()),
+            ("--threads", cli.threads > 1),
+        ];
+        if let Some((flag, _)) = seekable.iter().find(|(_, set)| *set) {
+            eprintln!("❌ {} needs a FILE, it cannot read standard input", flag);
+            std::process::exit(1);
+        }
+    }
+
+    if cli.input.len() > 1 {
+        let single = [
+            ("--detect-encoding", cli.detect_encoding),
+            ("--profile-regex", cli.profile_regex),
+            ("--split-by", cli.split_by.is_some()),
+            ("--cache", cli.cache.is_some()),
+            ("--stream", cli.stream),
+            ("--follow", cli.follow),
+        ];
+        if let Some((flag, _)) = single.iter().find(|(_, set)| *set) {
+            eprintln!("❌ {} takes a single FILE, got {}", flag, cli.input.len());
+            std::process::exit(1);
+        }
+    }
+
+    let writes_entries = cli.emit == Emit::Entries
+        || cli.show_error.is_some()
+        || (cli.include_entries
+            && matches!(cli.format, OutputFormat::Json)
+            && !cli.summary_line
+            && !cli.oneline);
+    let time_series = cli.rollup.is_some() || cli.window.is_some() || cli.daily;
+    if cli.output.is_some() && (!writes_entries || time_series) {
+        eprintln!("❌ --output only applies to entry exports (--emit entries, --show-error, --format json --include-entries)");
+        std::process::exit(1);
+    }
+
+    let pattern = match compile_pattern(cli.pattern.as_deref()) {
+        Ok(re) => re,
+        Err(e) => {
+            eprintln!("❌ Invalid pattern: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let (true, Some(input)) = (cli.detect_encoding, cli.input.first()) {
+        match detect_encoding(input) {
+            Ok((encoding, bom)) => {
+                println!("Encoding: {}{}", encoding, if bom { " (BOM)" } else { "" });
+                if cli.encoding != Encoding::Auto && cli.encoding != encoding {
+                    println!("Reading as: {} (--encoding)", cli.encoding);
+                }
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to read file: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let (true, Some(input)) = (cli.profile_regex, cli.input.first()) {
+        let sample: Vec<String> = match open_input(input, cli.encoding) {
+            Ok(reader) => reader
+                .lines()
+                .map_while(Result::ok)
+                .filter(|line| !line.trim().is_empty())
+                .take(PROFILE_SAMPLE_LINES)
+                .collect(),
+            Err(e) => {
+                eprintln!("❌ Failed to read file: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let profile = profile_regex(&pattern, &sample);
+        let micros = 1e6 / profile.lines_per_second;
+        println!("Pattern: {}", pattern.as_str());
+        println!(
+            "Sample: {} lines, {} matched ({:.1}%)",
+            thousands(profile.lines),
+            thousands(profile.matched),
+            if profile.lines == 0 { 0.0 } else { 100.0 * profile.matched as f64 / profile.lines as f64 }
+        );
+        println!("Speed: {} lines/sec ({:.2} µs/line)", thousands(profile.lines_per_second as usize), micros);
+        if micros > SLOW_MATCH_MICROS {
+            eprintln!(
+                "⚠️  Slow pattern: {:.1} µs per line (over {} µs); it is likely the bottleneck",
+                micros, SLOW_MATCH_MICROS
+            );
+        }
+        if has_nested_quantifiers(pattern.as_str()) {
+            eprintln!(
+                "⚠️  Nested quantifiers, as in (a+)*: fine here, but catastrophic backtracking in PCRE-style engines"
+            );
+        }
+        return;
+    }
+
+    let input_format = match (cli.input_format, cli.input.first()) {
+        (InputFormat::Auto, Some(input))
+            if cli.pattern.is_none() && cli.parser_cmd.is_none() && !cli.bracket_as_category =>
+        {
+            detect_format(input, &pattern, cli.encoding)
+        }
+        (InputFormat::Auto, _) => InputFormat::Default,
+        (format, _) => format,
+    };
+
+    if cli.bracket_as_category && input_format != InputFormat::Default {
+        eprintln!("❌ --bracket-as-category only applies to the default format or --pattern");
+        std::process::exit(1);
+    }
+
+    if cli.multiline && input_format != InputFormat::Default {
+        eprintln!("❌ --multiline only applies to the default format or --pattern");
+        std::process::exit(1);
+    }
+
+    if let Some(group) = &cli.count_group
+        && input_format == InputFormat::Default
+        && cli.parser_cmd.is_none()
+        && !(cli.bracket_as_category && group == CATEGORY_FIELD)
+        && !(cli.input.len() > 1 && group == SOURCE_FIELD)
+        && !cli.add_field.iter().any(|field| field.name == *group)
+        && !cli.extract.iter().any(|re| re.capture_names().any(|name| name == Some(group.as_str())))
+        && (RESERVED_GROUPS.contains(&group.as_str())
+            || !pattern.capture_names().any(|name| name == Some(group.as_str())))
+    {
+        eprintln!("❌ Pattern has no extra named group '{}' to count", group);
+        std::process::exit(1);
+    }
+
+    if cli.http
+        && !matches!(input_format, InputFormat::Access | InputFormat::Haproxy | InputFormat::W3c | InputFormat::Rails)
+    {
+        eprintln!("❌ --http only applies to request logs (--input-format access, haproxy, w3c or rails)");
+        std::process::exit(1);
+    }
+
+    if !cli.json_key.is_empty() && !matches!(input_format, InputFormat::Json | InputFormat::Logfmt) {
+        eprintln!("❌ --json-key only applies to --input-format json or logfmt");
+        std::process::exit(1);
+    }
+
+    if !cli.require_fields.is_empty() && !matches!(input_format, InputFormat::Json | InputFormat::Gelf) {
+        eprintln!("❌ --require-fields only applies to JSON input (--input-format json or gelf)");
+        std::process::exit(1);
+    }
+
+    if cli.parser_cmd.is_some() && cli.command.is_some() {
+        eprintln!("❌ --parser-cmd only applies to files, not to run");
+        std::process::exit(1);
+    }
+
+    if cli.cache.is_some() && cli.command.is_some() {
+        eprintln!("❌ --cache only applies to files, not to run");
+        std::process::exit(1);
+    }
+
+    if cli.stream && cli.command.is_some() {
+        eprintln!("❌ --stream only applies to files, not to run");
+        std::process::exit(1);
+    }
+
+    if cli.follow && cli.command.is_some() {
+        eprintln!("❌ --follow only applies to files, not to run");
+        std::process::exit(1);
+    }
+
+    if cli.threads > 1 && !SPLITTABLE_FORMATS.contains(&input_format) {
+        eprintln!("❌ --threads cannot split mysql-slow, rails or w3c input, whose records depend on earlier lines");
+        std::process::exit(1);
+    }
+
+    let mut parser = LineParser::new(input_format, pattern)
+        .required_fields(cli.require_fields.clone())
+        .json_keys(cli.json_key.clone())
+        .unparsed_level(cli.unparsed_level.clone())
+        .bracket_as_category(cli.bracket_as_category)
+        .multiline(cli.multiline)
+        .time_format(cli.time_format.clone())
+        .input_tz(cli.input_tz)
+        .encoding(cli.encoding);
+
+    let ignore = match cli.ignore_file.as_deref().map(load_ignore_patterns) {
+        Some(Ok(set)) => Some(set),
+        Some(Err(e)) => {
+            eprintln!("❌ Failed to load ignore file: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let report_template = match cli.template_file.as_deref().map(load_report_template) {
+        Some(Ok(template)) => Some(template),
+        Some(Err(e)) => {
+            eprintln!("❌ Invalid template file: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let config = match cli.config.as_deref().map(load_config) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            eprintln!("❌ Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+        None => Config::default(),
+    };
+
+    let mut normalize_rules = Vec::new();
+    for rule in &config.normalize {
+        match parse_regex(&rule.pattern) {
+            Ok(regex) => normalize_rules.push(NormalizeRule { regex, placeholder: rule.placeholder.clone() }),
+            Err(e) => {
+                eprintln!("❌ Failed to load config: normalize rule '{}': {}", rule.pattern, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    normalize_rules.append(&mut cli.normalize_rule);
+    if !cli.quiet {
+        for conflict in normalize_conflicts(&normalize_rules) {
+            eprintln!("⚠️  Normalize rule conflict: {}", conflict);
+        }
+    }
+    // Config rules go first, then the command line's.
+    cli.normalize_rule = normalize_rules;
+
+    let owners = match cli.owners.as_deref().map(load_owners) {
+        Some(Ok(owners)) => Some(owners),
+        Some(Err(e)) => {
+            eprintln!("❌ Failed to load owners file: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    if cli.verbose {
+        match &cli.command {
+            Some(Command::Run { command }) => eprintln!("Running: {}", command.join(" ")),
+            None => {
+                for input in &cli.input {
+                    eprintln!("Analysing file: {:?}", input);
+                    match (cli.encoding, detect_encoding(input)) {
+                        (Encoding::Auto, Ok((encoding, bom))) => {
+                            eprintln!("Encoding: {}{}", encoding, if bom { " (BOM)" } else { "" })
+                        }
+                        (Encoding::Auto, Err(_)) => {}
+                        (forced, _) => eprintln!("Encoding: {} (--encoding)", forced),
+                    }
+                }
+            }
+        }
+        eprintln!("Format: {:?}", cli.format);
+        eprintln!("Input format: {:?}", input_format);
+        eprintln!("Top errors: {}", cli.top);
+        eprintln!("Search filter: {:?}", cli.search);
+    }
+
+    if cli.follow {
+        // One JSON object per line, so each refresh can be read as it comes.
+        cli.json_compact = true;
+        let input = cli.input.first().expect("FILE is required without a subcommand");
+        let style = TextStyle::new(cli.color, cli.level_color.as_ref(), !cli.no_humanize, cli.level_case);
+        follow_analysis(&cli, input, &mut parser, ignore.as_ref(), &style, &config.health);
+        return;
+    }
+
+    if cli.stream {
+        let input = cli.input.first().expect("FILE is required without a subcommand");
+        let analysis = stream_analysis(&cli, input, &mut parser, ignore.as_ref());
+        let style = TextStyle::new(cli.color, cli.level_color.as_ref(), !cli.no_humanize, cli.level_case);
+        finish_report(&cli, analysis, &style, None, &config.health, None);
+        return;
+    }
+
+    let (log, child_exit) = match &cli.command {
+        Some(Command::Run { command }) => {
+            let result = run_command(
+                command,
+                &mut parser,
+                cli.max_entries,
+                cli.inherit_timestamp,
+                cli.first_errors,
+                cli.max_line_bytes,
+                cli.quiet,
+            );
+            match result {
+                Ok((log, code)) => (log, Some(code)),
+                Err(e) => {
+                    eprintln!("❌ Failed to run {}: {}", command[0], e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None if cli.input.len() > 1 => {
+            let mut log = ParsedLog::default();
+            for input in &cli.input {
+                let mut part = match read_file(&cli, input, &mut parser.fresh()) {
+                    Ok(part) => part,
+                    Err(e) => {
+                        eprintln!("❌ Failed to read {}: {}", input.display(), e);
+                        std::process::exit(1);
+                    }
+                };
+                let source = input.display().to_string();
+                for entry in &mut part.entries {
+                    entry.fields.insert(SOURCE_FIELD.to_string(), source.clone());
+                }
+                log.append(part);
+                if let Some(max) = cli.max_entries.filter(|&max| log.entries.len() > max) {
+                    eprintln!("❌ Failed to read file: {}", ReadError::TooManyEntries(max));
+                    std::process::exit(1);
+                }
+            }
+            (log, None)
+        }
+        None => {
+            let input = cli.input.first().expect("FILE is required without a subcommand");
+            let cache = cli.cache.as_deref().filter(|_| !cli.no_cache);
+            let settings = parse_settings(&cli, input_format, parser.pattern());
+            let cached = cache.and_then(|cache| match load_cache(cache, input, &settings) {
+                Ok(log) => {
+                    if cli.verbose {
+                        eprintln!("Cache: reusing {:?}", cache);
+                    }
+                    Some(log)
+                }
+                Err(CacheMiss::Missing) => None,
+                Err(CacheMiss::Stale) => {
+                    if cli.verbose {
+                        eprintln!("Cache: {:?} is stale, parsing again", cache);
+                    }
+                    None
+                }
+                Err(CacheMiss::Unreadable(e)) => {
+                    if !cli.quiet {
+                        eprintln!("⚠️  Ignoring unreadable cache {:?}: {}", cache, e);
+                    }
+                    None
+                }
+            });
+            let log = match cached {
+                Some(log) => match cli.max_entries {
+                    Some(max) if log.entries.len() > max => Err(ReadError::TooManyEntries(max)),
+                    _ => Ok(log),
+                },
+                None => {
+                    let log = read_file(&cli, input, &mut parser);
+                    if let (Some(cache), Ok(log)) = (cache, &log)
+                        && let Err(e) = save_cache(cache, input, &settings, log)
+                        && !cli.quiet
+                    {
+                        eprintln!("⚠️  Failed to write cache {:?}: {}", cache, e);
+                    }
+                    log
+                }
+            };
+            match log {
+                Ok(log) => (log, None),
+                Err(e) => {
+                    eprintln!("❌ Failed to read file: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    if cli.verbose {
+        for (line, reason) in &log.invalid_samples {
+            eprintln!("Invalid record, line {}: {}", line, reason);
+        }
+    }
+
+    // Only the first lines of entries are needed to split the file again.
+    let starts: Vec<usize> = if cli.split_by.is_some() {
+        log.entries.iter().map(|e| e.line).collect::<BTreeSet<_>>().into_iter().collect()
+    } else {
+        Vec::new()
+    };
+
+    if let Some(n) = cli.first_errors {
+        let mut errors: Vec<LogEntry> =
+            log.entries.into_iter().filter(|e| e.level.is_error()).take(n).collect();
+        let redactor = Redactor::new(cli.redact, &cli.redact_pattern);
+        for entry in &mut errors {
+            if let Some(redactor) = &redactor {
+                redactor.redact(&mut entry.message);
+                entry.fields.values_mut().for_each(|v| redactor.redact(v));
+            }
+            cli.level_case.recase_entry(entry);
+        }
+        output_entries(&errors, &cli.format, &cli.columns, !cli.no_header, cli.csv_delimiter, None, open_output(&cli));
+        exit_like_child(child_exit);
+        return;
+    }
+
+    if cli.parse_stats_only {
+        let mut stats = parse_stats(&log);
+        match cli.format {
+            OutputFormat::Text => {
+                let style = TextStyle::new(cli.color, cli.level_color.as_ref(), !cli.no_humanize, cli.level_case);
+                output_parse_stats_text(&stats, &style);
+            }
+            OutputFormat::Json => {
+                cli.level_case.recase_levels(&mut stats.by_level);
+                println!("{}", to_json(&stats, cli.json_compact));
+            }
+            OutputFormat::Csv => {
+                cli.level_case.recase_levels(&mut stats.by_level);
+                output_parse_stats_csv(&stats, cli.csv_delimiter);
+            }
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    let parsed = log.entries;
+    let parsed_count = parsed.len();
+    let out_of_order = count_out_of_order(&parsed);
+    let mut quality = QualityReport {
+        unparsed_lines: log.unparsed_lines,
+        invalid_records: log.invalid_records,
+        truncated_lines: log.truncated_lines,
+        inherited_entries: parsed.iter().filter(|e| e.inherited).count(),
+        ..time_quality(&parsed, cli.skew_threshold)
+    };
+
+    let parsed: Vec<LogEntry> = if cli.sort_by_time {
+        let mut parsed = parsed;
+        sort_by_time(&mut parsed);
+        parsed
+    } else if let Some(window) = cli.reorder_window {
+        ReorderWindow::new(parsed.into_iter(), window).collect()
+    } else {
+        parsed
+    };
+
+    if (cli.sort_by_time || cli.reorder_window.is_some()) && !cli.quiet {
+        eprintln!("Out-of-order entries: {}", out_of_order);
+    }
+
+    let parsed = if cli.dedupe {
+        let result = dedupe(parsed, cli.dedupe_window, cli.dedupe_max);
+        if result.truncated && !cli.quiet {
+            eprintln!(
+                "⚠️  Deduplication stopped remembering entries after {} digests; later duplicates may be kept",
+                cli.dedupe_max
+            );
+        }
+        quality.duplicates_removed = result.removed;
+        result.entries
+    } else {
+        parsed
+    };
+
+    let before_ignore = parsed.len();
+    let parsed: Vec<LogEntry> = match &ignore {
+        Some(set) => parsed.into_iter().filter(|e| !set.is_match(&e.message)).collect(),
+        None => parsed,
+    };
+
+    if cli.verbose && ignore.is_some() {
+        eprintln!("Suppressed by ignore file: {}", before_ignore - parsed.len());
+    }
+
+    let mut parsed = parsed;
+    let hits = extract_fields(&mut parsed, &cli.extract);
+    if cli.verbose {
+        for (re, hits) in cli.extract.iter().zip(hits) {
+            let rate = if parsed.is_empty() { 0.0 } else { 100.0 * hits as f64 / parsed.len() as f64 };
+            eprintln!("Extract '{}': {}/{} messages ({:.1}%)", re, hits, parsed.len(), rate);
+        }
+    }
+    if let Err(e) = add_fields(&mut parsed, &cli.add_field) {
+        eprintln!("❌ Invalid --add-field: {}", e);
+        std::process::exit(1);
+    }
+    let remapped = remap_levels(&mut parsed, &cli.level_remap);
+    if cli.verbose {
+        for (rule, remapped) in cli.level_remap.iter().zip(remapped) {
+            eprintln!("Level remap '{}': {} entries", rule.spec, remapped);
+        }
+    }
+
+    let filter = entry_filter(&cli);
+    let keep = |e: &LogEntry| filter.matches(e);
+    if cli.verbose && filter.has_exclusions() {
+        let mut removed = vec![0; exclusion_names(&cli).len()];
+        for i in parsed.iter().filter(|e| filter.includes(e)).filter_map(|e| filter.excluded_by(e)) {
+            removed[i] += 1;
+        }
+        for (name, removed) in exclusion_names(&cli).iter().zip(removed) {
+            eprintln!("{}: {} entries removed", name, removed);
+        }
+    }
+
+    let error_contexts = cli
+        .error_context
+        .map(|n| error_contexts(&parsed, n, |e| e.level.is_error() && keep(e)));
+
+    let filtered: Vec<LogEntry> = parsed.into_iter().filter(|e| keep(e)).collect();
+
+    let mut style = TextStyle::new(cli.color, cli.level_color.as_ref(), !cli.no_humanize, cli.level_case);
+    if cli.highlight && style.colors_enabled() {
+        let searches: Vec<String> = cli
+            .search
+            .iter()
+            .map(|needle| format!("(?i:{})", regex::escape(needle)))
+            .chain(cli.search_regex.iter().map(|re| format!("(?:{})", re.as_str())))
+            .collect();
+        style.highlight = Regex::new(&searches.join("|")).ok();
+    }
+
+    if let Some(id) = &cli.show_id {
+        let mut trace = trace(&filtered, id, cli.id_field.as_deref());
+        if let Some(redactor) = Redactor::new(cli.redact, &cli.redact_pattern) {
+            trace.steps.iter_mut().for_each(|step| redactor.redact(&mut step.message));
+        }
+        if !matches!(cli.format, OutputFormat::Text) && cli.level_case != LevelCase::Title {
+            for step in &mut trace.steps {
+                step.level = LogLevel::Other(cli.level_case.apply(&format!("{:?}", step.level)));
+            }
+        }
+        match cli.format {
+            OutputFormat::Text => output_trace_text(&trace, &style),
+            OutputFormat::Json => println!("{}", to_json(&trace, cli.json_compact)),
+            OutputFormat::Csv => output_trace_csv(&trace, cli.csv_delimiter),
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    if let (Some(width), Some(dir), Some(input)) = (cli.split_by, &cli.out_dir, cli.input.first()) {
+        let origin = bucket_origin(&filtered, cli.bucket_align);
+        let tz = cli.tz.filter(|_| cli.bucket_align == BucketAlign::Clock);
+        let name = if tz.is_some() { "%Y-%m-%dT%H-%M-%S%z" } else { "%Y-%m-%dT%H-%M-%S" };
+        let buckets: HashMap<usize, String> = filtered
+            .iter()
+            .filter_map(|e| {
+                let start = zoned_bucket_start(e.datetime?, width, origin, tz);
+                Some((e.line, start.format(name).to_string()))
+            })
+            .collect();
+        let redactor = Redactor::new(cli.redact, &cli.redact_pattern);
+        let files = match split_by_time(input, cli.encoding, &starts, &buckets, dir, redactor.as_ref()) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("❌ Failed to split {}: {}", input.display(), e);
+                std::process::exit(1);
+            }
+        };
+        match cli.format {
+            OutputFormat::Text => output_split_text(&files, &style),
+            OutputFormat::Json => output_split_json(&files, cli.json_compact),
+            OutputFormat::Csv => output_split_csv(&files, cli.csv_delimiter),
+        }
+        return;
+    }
+
+    if cli.timeseries {
+        let tz = cli.tz.filter(|_| cli.bucket_align == BucketAlign::Clock);
+        let width = cli.bucket.unwrap_or(TimeDelta::minutes(1));
+        let mut points = timeseries(&filtered, width, bucket_origin(&filtered, cli.bucket_align), tz);
+        if !matches!(cli.format, OutputFormat::Text) {
+            points.iter_mut().for_each(|point| point.level = cli.level_case.apply(&point.level));
+        }
+        match cli.format {
+            OutputFormat::Text => output_timeseries_text(&points, &style),
+            OutputFormat::Json => println!("{}", to_json(&points, cli.json_compact)),
+            OutputFormat::Csv => output_timeseries_csv(&points, cli.csv_delimiter),
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    if let Some(width) = cli.bucket {
+        let tz = cli.tz.filter(|_| cli.bucket_align == BucketAlign::Clock);
+        let mut buckets = volume_histogram(&filtered, width, bucket_origin(&filtered, cli.bucket_align), tz);
+        if matches!(cli.format, OutputFormat::Json) {
+            for bucket in &mut buckets {
+                cli.level_case.recase_levels(&mut bucket.by_level);
+            }
+        }
+        match cli.format {
+            OutputFormat::Text => output_volume_text(&buckets, &style),
+            OutputFormat::Json => println!("{}", to_json(&buckets, cli.json_compact)),
+            OutputFormat::Csv => output_volume_csv(&buckets, cli.level_case, cli.csv_delimiter),
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    if let Some(width) = cli.rollup {
+        let tz = cli.tz.filter(|_| cli.bucket_align == BucketAlign::Clock);
+        let mut rows = rollup(&filtered, width, bucket_origin(&filtered, cli.bucket_align), tz);
+        if !matches!(cli.format, OutputFormat::Text) {
+            rows.iter_mut().for_each(|row| row.level = cli.level_case.apply(&row.level));
+        }
+        match cli.format {
+            OutputFormat::Text => output_rollup_text(&rows, &style),
+            OutputFormat::Json => output_rollup_json(&rows, cli.json_compact),
+            OutputFormat::Csv => output_rollup_csv(&rows, cli.csv_delimiter),
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    if let Some(width) = cli.window {
+        let mut windows = analyze_windows(&filtered, width, bucket_origin(&filtered, cli.bucket_align));
+        match cli.format {
+            OutputFormat::Text => output_windows_text(&windows, &style),
+            OutputFormat::Json => {
+                windows.iter_mut().for_each(|w| cli.level_case.recase_levels(&mut w.by_level));
+                output_windows_json(&windows, cli.json_compact)
+            }
+            OutputFormat::Csv => output_windows_csv(&windows, cli.csv_delimiter),
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    if cli.daily {
+        let mut days = analyze_days(&filtered, cli.tz);
+        match cli.format {
+            OutputFormat::Text => output_days_text(&days, &style),
+            OutputFormat::Json => {
+                days.iter_mut().for_each(|d| cli.level_case.recase_levels(&mut d.by_level));
+                output_windows_json(&days, cli.json_compact)
+            }
+            OutputFormat::Csv => output_days_csv(&days, cli.csv_delimiter),
+        }
+        exit_like_child(child_exit);
+        return;
+    }
+
+    let normalizer = normalizer(&cli);
+    let normalize = cli.normalize_top_only.then_some(&normalizer);
+    let mut stats = analyze_logs(&filtered, top_limit(&cli), approx_options(&cli), normalize);
+    stats.group_counts = cli
+        .count_group
+        .as_deref()
+        .map(|g| count_group(&filtered, g, cli.count_distinct, normalize));
+    stats.owners = owners.map(|o| summarize_owners(&filtered, &o, cli.top, &normalizer));
+    if cli.per_file && cli.input.len() > 1 {
+        let files: Vec<String> = cli.input.iter().map(|input| input.display().to_string()).collect();
+        stats.files = Some(per_file(&filtered, &files));
+    }
+    stats.correlated_pairs = cli.correlated_within.map(|w| correlated_pairs(&filtered, w, cli.top, &normalizer));
+    if cli.top_by_level_sections {
+        let mut levels = top_by_level(&filtered, cli.top, normalize);
+        for level in &mut levels {
+            level.top.retain(|err| err.count >= cli.min_count);
+        }
+        stats.top_by_level = Some(levels);
+    }
+    if cli.detect_retry_storms {
+        stats.retry_storms =
+            Some(retry_storms(&filtered, cli.storm_count, cli.storm_window, normalize));
+    }
+    stats.peaks = cli.peak_report.map(|width| peak_report(&filtered, width));
+    if cli.sessionize {
+        stats.sessions = Some(sessionize(&filtered, cli.session_gap, cli.session_key.as_deref(), cli.top));
+    }
+    if !cli.alert_pattern.is_empty() {
+        stats.alerts = Some(check_alerts(&filtered, &cli.alert_pattern));
+    }
+    stats.error_contexts = error_contexts;
+    if input_format == InputFormat::MysqlSlow {
+        stats.slow_queries = Some(summarize_queries(&filtered, cli.top));
+    }
+    // Access logs record no durations, so their endpoints have no timings.
+    if matches!(input_format, InputFormat::Rails | InputFormat::W3c | InputFormat::Haproxy | InputFormat::Access) {
+        stats.requests = Some(summarize_requests(&filtered, cli.top));
+    }
+    if cli.http {
+        stats.http = Some(summarize_http(&filtered, cli.top));
+    }
+
+    let analysis = Analysis {
+        stats,
+        quality,
+        unparsed_entries: filtered.iter().filter(|e| matches!(e.level, LogLevel::Other(_))).count(),
+        has_timestamps: filtered.iter().any(|e| e.datetime.is_some()),
+        parsed_count,
+        entries: filtered,
+    };
+    finish_report(&cli, analysis, &style, report_template.as_deref(), &config.health, child_exit);
+}
+
+/// `check_time_quality` for each input file on its own, so going from one
+/// file to the next (say `app.log` to the older `app.log.1`) is no jump.
+fn time_quality(entries: &[LogEntry], skew_threshold: TimeDelta) -> QualityReport {
+    let mut report = QualityReport::default();
+    for file in entries.chunk_by(|a, b| a.fields.get(SOURCE_FIELD) == b.fields.get(SOURCE_FIELD)) {
+        report.add_time_checks(check_time_quality(file, skew_threshold));
+    }
+    report
+}
+
+/// Reads one FILE, through the `--parser-cmd` decoder if there is one.
+fn read_file(cli: &Cli, input: &std::path::Path, parser: &mut LineParser) -> Result<ParsedLog, ReadError> {
+    match &cli.parser_cmd {
+        Some(command) => read_log_decoded(
+            input,
+            command,
+            parser,
+            cli.max_entries,
+            cli.inherit_timestamp,
+            cli.first_errors,
+            cli.max_line_bytes,
+        ),
+        None => read_log_file(
+            input,
+            parser,
+            cli.max_entries,
+            cli.inherit_timestamp,
+            cli.first_errors,
+            cli.max_line_bytes,
+        ),
+    }
+}
+
+/// Error count cap for the analysis: everything for `--top-coverage`, at
+/// least the rank picked by `--show-error`.
+fn top_limit(cli: &Cli) -> usize {
+    match (&cli.show_error, cli.top_coverage) {
+        (_, Some(_)) => usize::MAX,
+        (Some(ErrorSelector::Rank(rank)), None) => cli.top.max(*rank),
+        (_, None) => cli.top,
+    }
+}
+
+fn approx_options(cli: &Cli) -> ApproxOptions {
+    ApproxOptions {
+        forced: cli.approx_top,
+        capacity: cli.approx_capacity,
+        threshold: cli.approx_threshold,
+    }
+}
+
+/// The config file's normalize rules, then `--normalize-rule`'s; `main`
+/// merges both into `cli.normalize_rule`.
+fn normalizer(cli: &Cli) -> Normalizer {
+    Normalizer::new(cli.normalize_rule.clone())
+}
+
+fn open_output(cli: &Cli) -> Output {
+    match Output::open(cli.output.as_deref(), cli.compress) {
+        Ok(out) => out,
+        Err(e) => {
+            eprintln!("❌ Failed to create output file: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// What the report is built from, whether the entries were held in memory
+/// or streamed.
+struct Analysis {
+    stats: LogStats,
+    quality: QualityReport,
+    /// `--unparsed-level` entries among those analyzed.
+    unparsed_entries: usize,
+    has_timestamps: bool,
+    /// Entries parsed, before any filtering, for `--min-entries`.
+    parsed_count: usize,
+    /// The filtered entries; empty with `--stream`.
+    entries: Vec<LogEntry>,
+}
+
+/// Running totals of `--stream` over the whole file or, with `--threads`,
+/// over one part of it.
+#[derive(Clone)]
+struct StreamTotals {
+    stats: StatsBuilder,
+    quality: TimeQuality,
+    parsed_count: usize,
+    inherited: usize,
+    suppressed: usize,
+    /// Per `--extract` pattern, `--level-remap` rule and exclusion (see `exclusion_names`).
+    extracted: Vec<usize>,
+    remapped: Vec<usize>,
+    removed: Vec<usize>,
+    unparsed_entries: usize,
+    has_timestamps: bool,
+    unparsed_lines: usize,
+    invalid_records: usize,
+    invalid_samples: Vec<(usize, String)>,
+    truncated_lines: usize,
+    lines: usize,
+    /// `--reorder-window`: entries waiting to be counted in timestamp order.
+    reorder: Option<ReorderBuffer>,
+    /// Latest timestamp so far and the entries earlier than it, in file order.
+    latest: Option<NaiveDateTime>,
+    out_of_order: usize,
+}
+
+impl StreamTotals {
+    fn new(cli: &Cli) -> Self {
+        StreamTotals {
+            stats: StatsBuilder::new(top_limit(cli), approx_options(cli), cli.normalize_top_only.then(|| normalizer(cli))),
+            quality: TimeQuality::new(cli.skew_threshold),
+            parsed_count: 0,
+            inherited: 0,
+            suppressed: 0,
+            extracted: vec![0; cli.extract.len()],
+            remapped: vec![0; cli.level_remap.len()],
+            removed: vec![0; exclusion_names(cli).len()],
+            unparsed_entries: 0,
+            has_timestamps: false,
+            unparsed_lines: 0,
+            invalid_records: 0,
+            invalid_samples: Vec::new(),
+            truncated_lines: 0,
+            lines: 0,
+            reorder: cli.reorder_window.map(ReorderBuffer::new),
+            latest: None,
+            out_of_order: 0,
+        }
+    }
+
+    /// Runs one entry through the per-entry steps of the batch pipeline.
+    /// As there, the time quality checks see the entries in file order and
+    /// everything else sees them after `--reorder-window`.
+    fn add(&mut self, cli: &Cli, filter: &Filter, ignore: Option<&RegexSet>, entry: LogEntry) {
+        self.parsed_count += 1;
+        self.inherited += usize::from(entry.inherited);
+        self.quality.add(&entry);
+        if let Some(dt) = entry.datetime {
+            match self.latest {
+                Some(latest) if dt < latest => self.out_of_order += 1,
+                _ => self.latest = Some(dt),
+            }
+        }
+        let entry = match self.reorder.as_mut() {
+            Some(buffer) => match buffer.push(entry) {
+                Some(earliest) => earliest,
+                None => return,
+            },
+            None => entry,
+        };
+        self.count(cli, filter, ignore, entry);
+    }
+
+    /// Counts the entries `--reorder-window` still holds, at the end of the
+    /// input or before a `--follow` report.
+    fn flush(&mut self, cli: &Cli, filter: &Filter, ignore: Option<&RegexSet>) {
+        while let Some(entry) = self.reorder.as_mut().and_then(ReorderBuffer::pop) {
+            self.count(cli, filter, ignore, entry);
+        }
+    }
+
+    fn count(&mut self, cli: &Cli, filter: &Filter, ignore: Option<&RegexSet>, mut entry: LogEntry) {
+        if ignore.is_some_and(|set| set.is_match(&entry.message)) {
+            self.suppressed += 1;
+            return;
+        }
+        let one = std::slice::from_mut(&mut entry);
+        for (total, hits) in self.extracted.iter_mut().zip(extract_fields(one, &cli.extract)) {
+            *total += hits;
+        }
+        for (total, hits) in self.remapped.iter_mut().zip(remap_levels(one, &cli.level_remap)) {
+            *total += hits;
+        }
+        if !filter.includes(&entry) {
+            return;
+        }
+        if let Some(i) = filter.excluded_by(&entry) {
+            self.removed[i] += 1;
+            return;
+        }
+        self.unparsed_entries += usize::from(matches!(entry.level, LogLevel::Other(_)));
+        self.has_timestamps |= entry.datetime.is_some();
+        self.stats.add(&entry);
+    }
+
+    fn read(&mut self, log: ParsedLog) {
+        self.unparsed_lines = log.unparsed_lines;
+        self.invalid_records = log.invalid_records;
+        self.invalid_samples = log.invalid_samples;
+        self.truncated_lines = log.truncated_lines;
+        self.lines = log.lines;
+    }
+
+    fn into_analysis(self) -> Analysis {
+        Analysis {
+            stats: self.stats.finish(),
+            quality: QualityReport {
+                unparsed_lines: self.unparsed_lines,
+                invalid_records: self.invalid_records,
+                truncated_lines: self.truncated_lines,
+                inherited_entries: self.inherited,
+                ..self.quality.finish()
+            },
+            unparsed_entries: self.unparsed_entries,
+            has_timestamps: self.has_timestamps,
+            parsed_count: self.parsed_count,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds the totals of the part of the file that follows this one.
+    fn merge(&mut self, next: StreamTotals) {
+        let offset = self.lines;
+        self.stats.merge(next.stats);
+        self.quality.merge(next.quality, offset);
+        self.parsed_count += next.parsed_count;
+        self.inherited += next.inherited;
+        self.suppressed += next.suppressed;
+        for (totals, next) in [
+            (&mut self.extracted, next.extracted),
+            (&mut self.remapped, next.remapped),
+            (&mut self.removed, next.removed),
+        ] {
+            totals.iter_mut().zip(next).for_each(|(total, n)| *total += n);
+        }
+        self.unparsed_entries += next.unparsed_entries;
+        self.has_timestamps |= next.has_timestamps;
+        self.unparsed_lines += next.unparsed_lines;
+        self.invalid_records += next.invalid_records;
+        self.invalid_samples.extend(next.invalid_samples.into_iter().map(|(line, reason)| (line + offset, reason)));
+        self.invalid_samples.truncate(INVALID_SAMPLES);
+        self.truncated_lines += next.truncated_lines;
+        self.lines += next.lines;
+        // Entries of `next` earlier than the end of this part are not caught.
+        self.out_of_order += next.out_of_order;
+        self.latest = self.latest.max(next.latest);
+    }
+}
+
+/// Where `--follow` reads: a file it polls, or standard input, which blocks
+/// until more is written and ends when the writer closes it.
+enum FollowSource {
+    File(FollowLines),
+    Stdin(Box<dyn Iterator<Item = std::io::Result<RawLine>> + Send>),
+}
+
+/// What the `--follow` reader thread hands to the counting one.
+enum FollowEvent {
+    Entry(LogEntry),
+    /// The line counts so far, sent when they change or the file goes quiet.
+    Lines(ParsedLog),
+}
+
+/// `--follow`: counts FILE like `--stream` on a reader thread that keeps
+/// waiting for new lines, and prints the report every `--refresh` while
+/// entries come in, over the previous one when stdout is a terminal. An
+/// entry is counted once the next one starts, since until then more
+/// continuation lines may come, or once a file has been quiet for a whole
+/// poll, which also ends a last line missing its newline; a continuation
+/// line written after that counts as unparsed.
+/// Unparsed, invalid and truncated lines are counted as they are read.
+fn follow_analysis(
+    cli: &Cli,
+    input: &std::path::Path,
+    parser: &mut LineParser,
+    ignore: Option<&RegexSet>,
+    style: &TextStyle,
+    health: &HealthWeights,
+) {
+    let mut source = if is_stdin(input) {
+        let reader = match open_input(input, cli.encoding) {
+            Ok(reader) => reader,
+            Err(e) => {
+                eprintln!("❌ Failed to read file: {}", e);
+                std::process::exit(1);
+            }
+        };
+        FollowSource::Stdin(Box::new(BoundedLines::new(reader, cli.max_line_bytes).map(|line| {
+            let (bytes, truncated) = line?;
+            Ok(RawLine { text: line_text(bytes, truncated)?, fallback: None, truncated })
+        })))
+    } else {
+        match FollowLines::open(input, cli.max_line_bytes) {
+            Ok(lines) => FollowSource::File(lines),
+            Err(e) => {
+                eprintln!("❌ Failed to read file: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let filter = entry_filter(cli);
+    let clear = matches!(cli.format, OutputFormat::Text) && std::io::stdout().is_terminal();
+    let report = |totals: &StreamTotals| {
+        if clear {
+            print!("\x1b[2J\x1b[H");
+        }
+        let mut totals = totals.clone();
+        totals.flush(cli, &filter, ignore);
+        finish_report(cli, totals.into_analysis(), style, None, health, None);
+        let _ = std::io::stdout().flush();
+    };
+
+    let (sender, events) = std::sync::mpsc::sync_channel(DECODER_QUEUE);
+    let mut totals = StreamTotals::new(cli);
+    let log = std::thread::scope(|scope| {
+        let reader = scope.spawn(move || -> Result<ParsedLog, ReadError> {
+            let mut reader = LogReader::new(parser, cli.max_entries, cli.inherit_timestamp, None);
+            let mut emit = |entry| {
+                let _ = sender.send(FollowEvent::Entry(entry));
+            };
+            let mut idle_polls = 0;
+            loop {
+                let line = match &mut source {
+                    FollowSource::File(lines) => lines.poll()?,
+                    FollowSource::Stdin(lines) => match lines.next() {
+                        Some(line) => Some(line?),
+                        None => break,
+                    },
+                };
+                let Some(line) = line else {
+                    // Quiet for a whole poll: the last line and the held
+                    // entry are complete, as far as anyone watching can tell.
+                    idle_polls += 1;
+                    if idle_polls == 2 {
+                        if let FollowSource::File(lines) = &mut source
+                            && let Some(line) = lines.take_partial()?
+                        {
+                            reader.push(line, &mut emit)?;
+                        }
+                        reader.flush(&mut emit);
+                        let _ = sender.send(FollowEvent::Lines(reader.progress()));
+                    }
+                    std::thread::sleep(FOLLOW_POLL);
+                    continue;
+                };
+                idle_polls = 0;
+                let before = reader.progress();
+                reader.push(line, &mut emit)?;
+                let after = reader.progress();
+                if (after.unparsed_lines, after.truncated_lines) != (before.unparsed_lines, before.truncated_lines) {
+                    let _ = sender.send(FollowEvent::Lines(after));
+                }
+            }
+            Ok(reader.finish(&mut emit))
+        });
+        let mut due = std::time::Instant::now() + cli.refresh;
+        let mut changed = false;
+        loop {
+            match events.recv_timeout(due.saturating_duration_since(std::time::Instant::now())) {
+                Ok(FollowEvent::Entry(entry)) => {
+                    totals.add(cli, &filter, ignore, entry);
+                    changed = true;
+                }
+                Ok(FollowEvent::Lines(log)) => {
+                    totals.read(log);
+                    changed = true;
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            if std::time::Instant::now() >= due {
+                if changed {
+                    report(&totals);
+                    changed = false;
+                }
+                due = std::time::Instant::now() + cli.refresh;
+            }
+        }
+        reader.join().expect("reader thread panicked")
+    });
+    match log {
+        Ok(log) => totals.read(log),
+        Err(e) => {
+            eprintln!("❌ Failed to read file: {}", e);
+            std::process::exit(1);
+        }
+    }
+    report(&totals);
+}
+
+/// `--stream`: parses, filters and counts FILE in one pass, holding one
+/// entry at a time. Memory is bounded by the distinct error messages (see
+/// `--approx-threshold`), not by the size of FILE. Options that need every
+/// entry conflict with it; the MySQL slow query and request summaries are
+/// left out.
+///
+/// With `--threads N`, FILE is cut into N parts at entry boundaries, each
+/// part is counted on its own thread, and the totals are merged in file
+/// order, so the report does not depend on which thread finishes first.
+fn stream_analysis(
+    cli: &Cli,
+    input: &std::path::Path,
+    parser: &mut LineParser,
+    ignore: Option<&RegexSet>,
+) -> Analysis {
+    let filter = entry_filter(cli);
+    let read_part = |parser: &mut LineParser, range: Option<std::ops::Range<u64>>| {
+        let mut totals = StreamTotals::new(cli);
+        let emit = |entry| totals.add(cli, &filter, ignore, entry);
+        let log = match range {
+            Some(range) => read_log_range_each(input, range, parser, cli.inherit_timestamp, cli.max_line_bytes, emit),
+            None => read_log_file_each(input, parser, cli.max_entries, cli.inherit_timestamp, cli.max_line_bytes, emit),
+        };
+        match log {
+            Ok(log) => totals.read(log),
+            Err(e) => {
+                eprintln!("❌ Failed to read file: {}", e);
+                std::process::exit(1);
+            }
+        }
+        totals.flush(cli, &filter, ignore);
+        totals
+    };
+
+    let totals = if cli.threads > 1 {
+        let points = match split_points(input, parser, cli.threads) {
+            Ok(Some(points)) => points,
+            Ok(None) => {
+                eprintln!("❌ --threads needs an uncompressed UTF-8 file");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to read file: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let parts: Vec<StreamTotals> = std::thread::scope(|scope| {
+            let workers: Vec<_> = points
+                .windows(2)
+                .map(|range| {
+                    let mut parser = parser.fresh();
+                    let read_part = &read_part;
+                    scope.spawn(move || read_part(&mut parser, Some(range[0]..range[1])))
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().expect("parser thread panicked")).collect()
+        });
+        let totals = parts.into_iter().reduce(|mut totals, next| {
+            totals.merge(next);
+            totals
+        });
+        let totals = totals.unwrap_or_else(|| StreamTotals::new(cli));
+        if let Some(max) = cli.max_entries.filter(|&max| totals.parsed_count > max) {
+            eprintln!("❌ Failed to read file: {}", ReadError::TooManyEntries(max));
+            std::process::exit(1);
+        }
+        totals
+    } else {
+        read_part(parser, None)
+    };
+
+    if cli.reorder_window.is_some() && !cli.quiet {
+        eprintln!("Out-of-order entries: {}", totals.out_of_order);
+    }
+    if cli.verbose {
+        if cli.threads > 1 {
+            eprintln!("Threads: {}", cli.threads);
+        }
+        for (line, reason) in &totals.invalid_samples {
+            eprintln!("Invalid record, line {}: {}", line, reason);
+        }
+        if ignore.is_some() {
+            eprintln!("Suppressed by ignore file: {}", totals.suppressed);
+        }
+        let kept = totals.parsed_count - totals.suppressed;
+        for (re, hits) in cli.extract.iter().zip(&totals.extracted) {
+            let rate = if kept == 0 { 0.0 } else { 100.0 * *hits as f64 / kept as f64 };
+            eprintln!("Extract '{}': {}/{} messages ({:.1}%)", re, hits, kept, rate);
+        }
+        for (rule, remapped) in cli.level_remap.iter().zip(&totals.remapped) {
+            eprintln!("Level remap '{}': {} entries", rule.spec, remapped);
+        }
+        if filter.has_exclusions() {
+            for (name, removed) in exclusion_names(cli).iter().zip(&totals.removed) {
+                eprintln!("{}: {} entries removed", name, removed);
+            }
+        }
+    }
+
+    totals.into_analysis()
+}
+
+/// Everything after the analysis: top-error trimming, health, redaction,
+/// gates and the report itself.
+fn finish_report(
+    cli: &Cli,
+    analysis: Analysis,
+    style: &TextStyle,
+    report_template: Option<&[ReportPart]>,
+    health: &HealthWeights,
+    child_exit: Option<i32>,
+) {
+    let Analysis { mut stats, quality, unparsed_entries, has_timestamps, parsed_count, entries: filtered } = analysis;
+    if let Some(fraction) = cli.top_coverage {
+        let total_errors = error_count(&stats.by_level);
+        truncate_to_coverage(&mut stats.top_errors, total_errors, fraction);
+    }
+    stats.top_errors.retain(|err| err.count >= cli.min_count);
+    if stats.top_errors_approximate && !cli.approx_top && !cli.quiet {
+        eprintln!(
+            "⚠️  More than {} distinct error messages; top errors are approximate",
+            cli.approx_threshold
+        );
+    }
+    if cli.unparsed_in_total {
+        stats.quality = QualityReport { unparsed_entries, ..quality };
+    } else {
+        stats.total_entries -= unparsed_entries;
+        stats.quality = quality;
+    }
+    stats.health = Some(compute_health(&stats, has_timestamps, health));
+
+    let mut filtered = match &cli.show_error {
+        Some(selector) => match error_entries(
+            &filtered,
+            &stats.top_errors,
+            selector,
+            cli.normalize_top_only,
+            &normalizer(cli),
+        ) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("❌ Cannot show error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => filtered,
+    };
+    let emit_entries = cli.emit == Emit::Entries || cli.show_error.is_some();
+    if let Some(redactor) = Redactor::new(cli.redact, &cli.redact_pattern) {
+        redactor.redact_stats(&mut stats);
+        if cli.include_entries || emit_entries {
+            for entry in &mut filtered {
+                redactor.redact(&mut entry.message);
+                entry.fields.values_mut().for_each(|v| redactor.redact(v));
+            }
+        }
+    }
+
+    // Gates read the title-case level names, so they are checked before
+    // the levels are recased for output.
+    let failed = failed_gates(&stats, &cli.fail_on);
+    let level_failures = failed_level_assertions(&stats, &cli.require_level, &cli.forbid_level);
+    if cli.level_case != LevelCase::Title {
+        if cli.include_entries || emit_entries {
+            filtered.iter_mut().for_each(|entry| cli.level_case.recase_entry(entry));
+        }
+        if !matches!(cli.format, OutputFormat::Text) && !cli.summary_line && !cli.oneline && report_template.is_none() {
+            cli.level_case.recase_levels(&mut stats.by_level);
+            for level in stats.top_by_level.iter_mut().flatten() {
+                level.level = cli.level_case.apply(&level.level);
+            }
+            for file in stats.files.iter_mut().flatten() {
+                cli.level_case.recase_levels(&mut file.by_level);
+            }
+            if let Some(peaks) = &mut stats.peaks {
+                peaks.by_level = std::mem::take(&mut peaks.by_level)
+                    .into_iter()
+                    .map(|(level, peak)| (cli.level_case.apply(&level), peak))
+                    .collect();
+            }
+            for context in stats.error_contexts.iter_mut().flatten() {
+                context.entries.iter_mut().for_each(|entry| cli.level_case.recase_entry(entry));
+            }
+        }
+    }
+
+    if emit_entries {
+        // With --output, `auto` leaves the file uncolored.
+        let highlight = style.highlight.as_ref().filter(|_| cli.output.is_none() || cli.color == ColorChoice::Always);
+        output_entries(&filtered, &cli.format, &cli.columns, !cli.no_header, cli.csv_delimiter, highlight, open_output(cli));
+    } else if cli.summary_line {
+        output_summary_line(&stats);
+    } else if cli.oneline {
+        output_oneline(&stats);
+    } else if let Some(template) = &report_template {
+        let tz = cli.tz.filter(|_| cli.bucket_align == BucketAlign::Clock);
+        let rows = rollup(&filtered, cli.template_bucket, bucket_origin(&filtered, cli.bucket_align), tz);
+        print!("{}", render_report(template, &stats, &histogram(rows), style));
+    } else {
+        match cli.format {
+            OutputFormat::Text => output_text(&stats, style),
+            OutputFormat::Json if cli.include_entries => {
+                output_json_with_entries(&filtered, &stats, cli.json_compact, open_output(cli))
+            }
+            OutputFormat::Json => output_json(&stats, cli.json_compact),
+            OutputFormat::Csv => output_csv(&stats, cli.csv_delimiter),
+        }
+    }
+
+    for (gate, value) in &failed {
+        eprintln!("❌ Gate failed: {} (actual {})", gate.expr, value);
+    }
+    for failure in &level_failures {
+        eprintln!("❌ Level check failed: {}", failure);
+    }
+    let alerts: Vec<&Alert> = stats.alerts.iter().flatten().filter(|a| a.triggered).collect();
+    for alert in &alerts {
+        eprintln!("❌ Alert: '{}' occurred {} time(s) (max {})", alert.pattern, alert.count, alert.max);
+    }
+    let too_few = cli.min_entries.filter(|&min| parsed_count < min);
+    if let Some(min) = too_few {
+        eprintln!("❌ Too few entries: {} parsed, expected at least {}", parsed_count, min);
+    }
+    if !failed.is_empty() || !level_failures.is_empty() || !alerts.is_empty() || too_few.is_some() {
+        std::process::exit(GATE_FAILURE_EXIT);
+    }
+    exit_like_child(child_exit);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(humanize: bool) -> TextStyle {
+        TextStyle::new(ColorChoice::Never, None, humanize, LevelCase::Title)
+    }
+
+    #[test]
+    fn thousands_groups_by_three() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1_000), "1,000");
+        assert_eq!(thousands(18_234_912), "18,234,912");
+        assert_eq!(thousands(100_000), "100,000");
+    }
+
+    #[test]
+    fn human_duration_keeps_two_units() {
+        assert_eq!(human_duration(0), "0s");
+        assert_eq!(human_duration(45), "45s");
+        assert_eq!(human_duration(310), "5m 10s");
+        assert_eq!(human_duration(3_600), "1h");
+        assert_eq!(human_duration(11_520), "3h 12m");
+        // The third unit is dropped, not rounded.
+        assert_eq!(human_duration(11_579), "3h 12m");
+        assert_eq!(human_duration(3_601), "1h");
+        assert_eq!(human_duration(183_600), "2d 3h");
+        assert_eq!(human_duration(-90), "-1m 30s");
+    }
+
+    #[test]
+    fn humanize_only_changes_text() {
+        assert_eq!(style(true).count(18_234_912), "18,234,912");
+        assert_eq!(style(false).count(18_234_912), "18234912");
+        assert_eq!(style(true).duration(11_520), "3h 12m");
+        assert_eq!(style(false).duration(11_520), "11520s");
+    }
+
+    #[test]
+    fn seen_is_relative_to_the_newest_entry() {
+        let newest = parse_timestamp("2024-01-02 05:14:02");
+        assert_eq!(
+            style(true).seen("2024-01-02 03:14:02", newest),
+            "2024-01-02 03:14:02 (2h ago)"
+        );
+        assert_eq!(style(true).seen("2024-01-02 05:14:02", newest), "2024-01-02 05:14:02 (0s ago)");
+        assert_eq!(style(false).seen("2024-01-02 03:14:02", newest), "2024-01-02 03:14:02");
+        assert_eq!(style(true).seen("2024-01-02 03:14:02", None), "2024-01-02 03:14:02");
+        assert_eq!(style(true).seen("not a time", newest), "not a time");
+    }
+}
@@ -0,0 +1,175 @@
+//! Runs the binary and checks stdout and stderr separately: the report is
+//! the only thing on stdout, diagnostics go to stderr, and `--quiet`
+//! silences everything but the report and errors.
+
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rust-td3"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_remove("LOGLYZER_FORMAT")
+        .env_remove("LOGLYZER_TOP")
+        .env_remove("LOGLYZER_PATTERN")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn streams(output: &Output) -> (String, String) {
+    (
+        String::from_utf8(output.stdout.clone()).unwrap(),
+        String::from_utf8(output.stderr.clone()).unwrap(),
+    )
+}
+
+#[test]
+fn json_report_is_alone_on_stdout() {
+    let output = run(&["sample.log", "--format", "json", "--verbose"]);
+    let (stdout, stderr) = streams(&output);
+
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(report["total_entries"], 10);
+    assert!(stderr.contains("Analysing file: \"sample.log\""), "{}", stderr);
+}
+
+#[test]
+fn diagnostics_go_to_stderr() {
+    let output = run(&["sample.log", "--sort-by-time"]);
+    let (stdout, stderr) = streams(&output);
+
+    assert!(stdout.contains("Log Analysis Results"));
+    assert!(!stdout.contains("Out-of-order"));
+    assert_eq!(stderr, "Out-of-order entries: 0\n");
+}
+
+#[test]
+fn quiet_keeps_only_the_report() {
+    let loud = run(&["sample.log", "--sort-by-time", "--normalize-rule", r"\d+=<N>", "--normalize-rule", r"\d+=<X>"]);
+    let quiet = run(&["sample.log", "--sort-by-time", "--normalize-rule", r"\d+=<N>", "--normalize-rule", r"\d+=<X>", "--quiet"]);
+    let ((loud_stdout, loud_stderr), (quiet_stdout, quiet_stderr)) = (streams(&loud), streams(&quiet));
+
+    assert!(loud_stderr.contains("Normalize rule conflict"), "{}", loud_stderr);
+    assert!(quiet.status.success());
+    assert_eq!(quiet_stderr, "");
+    // Same report; levels are listed in no fixed order.
+    let sorted = |s: &str| {
+        let mut lines: Vec<String> = s.lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(sorted(&quiet_stdout), sorted(&loud_stdout));
+}
+
+#[test]
+fn quiet_still_reports_errors() {
+    let output = run(&["missing.log", "--quiet"]);
+    let (stdout, stderr) = streams(&output);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("Failed to read file"), "{}", stderr);
+}
+
+#[test]
+fn quiet_conflicts_with_verbose() {
+    let output = run(&["sample.log", "--quiet", "--verbose"]);
+    let (stdout, stderr) = streams(&output);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+}
+
+#[test]
+fn summary_line_is_one_line() {
+    let output = run(&["sample.log", "--summary-line"]);
+    let (stdout, stderr) = streams(&output);
+
+    assert!(output.status.success());
+    assert_eq!(stdout, "total=10 errors=2 warnings=2 parse_failures=0 error_rate=0.2000\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn access_log_endpoints_have_no_latency() {
+    let output = run(&["tests/fixtures/access.log", "--input-format", "access", "--format", "json"]);
+    let (stdout, _) = streams(&output);
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    let endpoints = report["requests"]["endpoints"].as_array().unwrap();
+    assert_eq!(endpoints.len(), 2);
+    for endpoint in endpoints {
+        for timing in ["p95_ms", "max_ms", "avg_db_ms", "avg_view_ms"] {
+            assert!(endpoint[timing].is_null(), "{} of {}", timing, endpoint["endpoint"]);
+        }
+    }
+
+    let output = run(&["tests/fixtures/access.log", "--input-format", "access"]);
+    let (stdout, _) = streams(&output);
+    let endpoints = &stdout[stdout.find("Endpoints:").unwrap()..];
+    let row = endpoints.lines().find(|line| line.contains("POST /login")).unwrap();
+    assert_eq!(row.split('|').map(str::trim).collect::<Vec<_>>(), ["", "POST /login", "1", "1", "0", "-", "-", "-", "-", ""]);
+}
+
+#[test]
+fn reorder_window_works_with_stream() {
+    let batch = run(&["tests/fixtures/out_of_order.log", "--reorder-window", "3", "--format", "json"]);
+    let stream = run(&["tests/fixtures/out_of_order.log", "--reorder-window", "3", "--format", "json", "--stream"]);
+    let ((batch_stdout, batch_stderr), (stream_stdout, stream_stderr)) = (streams(&batch), streams(&stream));
+
+    assert!(stream.status.success(), "{}", stream_stderr);
+    assert_eq!(stream_stderr, "Out-of-order entries: 3\n");
+    assert_eq!(stream_stderr, batch_stderr);
+
+    let batch: serde_json::Value = serde_json::from_str(&batch_stdout).unwrap();
+    let stream: serde_json::Value = serde_json::from_str(&stream_stdout).unwrap();
+    for key in ["total_entries", "by_level", "top_errors", "time_range", "quality"] {
+        assert_eq!(stream[key], batch[key], "{}", key);
+    }
+}
+
+fn assert_rejects_zero_width(args: &[&str]) {
+    let output = run(args);
+    let (stdout, stderr) = streams(&output);
+
+    assert_eq!(output.status.code(), Some(2), "{:?}", args);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("invalid bucket width '0s': expected at least 1s"), "{}", stderr);
+}
+
+#[test]
+fn peak_report_rejects_widths_under_one_second() {
+    assert_rejects_zero_width(&["sample.log", "--peak-report", "0s"]);
+}
+
+#[test]
+fn split_by_and_template_bucket_reject_widths_under_one_second() {
+    assert_rejects_zero_width(&["sample.log", "--split-by", "0s", "--out-dir", "target"]);
+    assert_rejects_zero_width(&["sample.log", "--template-file", "report.tpl", "--template-bucket", "0s"]);
+}
+
+#[test]
+fn csv_reports_quote_fields_holding_the_delimiter() {
+    let output = run(&["sample.log", "--daily", "--format", "csv", "--csv-delimiter", ":"]);
+    let (stdout, _) = streams(&output);
+
+    assert!(output.status.success());
+    assert_eq!(
+        stdout.lines().nth(1),
+        Some(r#"2024-01-15:10:2:2:0.2000:"Database query failed: syntax error":1:59"#)
+    );
+}
+
+#[test]
+fn bucket_csv_names_levels_like_timeseries() {
+    let volume = run(&["sample.log", "--bucket", "1h", "--format", "csv"]);
+    let series = run(&["sample.log", "--timeseries", "--bucket", "1h", "--format", "csv"]);
+    let ((volume, _), (series, _)) = (streams(&volume), streams(&series));
+
+    let columns: Vec<&str> = volume.lines().next().unwrap().split(',').skip(2).collect();
+    let mut levels: Vec<&str> = series.lines().skip(1).map(|line| line.split(',').nth(1).unwrap()).collect();
+    assert_eq!(columns, ["Fatal", "Error", "Warning", "Info", "Debug", "Trace"]);
+    levels.sort();
+    assert_eq!(levels, ["Debug", "Error", "Fatal", "Info", "Trace", "Warning"]);
+}
@@ -0,0 +1,63 @@
+"""Writes the python_*.log fixtures with the standard `logging` module, one
+file per layout `PYTHON_LINES` reads. Timestamps are fixed so that the
+output does not change between runs. From the repository root:
+
+    python3 tests/fixtures/python_logging.py
+"""
+
+import logging
+import time
+from pathlib import Path
+
+LAYOUTS = {
+    "python_default.log": "%(asctime)s - %(name)s - %(levelname)s - %(message)s",
+    "python_basic.log": "%(levelname)s:%(name)s:%(message)s",
+    "python_level_first.log": "[%(levelname)s] %(asctime)s %(name)s: %(message)s",
+    "python_time_first.log": "%(asctime)s [%(levelname)s] %(name)s: %(message)s",
+}
+
+START = 1704189600.0  # 2024-01-02 10:00:00 UTC
+
+
+class FixedClock(logging.Filter):
+    def __init__(self):
+        super().__init__()
+        self.tick = 0
+
+    def filter(self, record):
+        record.created = START + self.tick * 1.5
+        record.msecs = (record.created % 1) * 1000
+        self.tick += 1
+        return True
+
+
+def write(path, layout):
+    formatter = logging.Formatter(layout)
+    formatter.converter = time.gmtime
+    handler = logging.FileHandler(path, mode="w")
+    handler.setFormatter(formatter)
+    handler.addFilter(FixedClock())
+
+    app = logging.getLogger("app")
+    db = logging.getLogger("app.db")
+    for logger in (app, db):
+        logger.handlers = [handler]
+        logger.setLevel(logging.DEBUG)
+        logger.propagate = False
+
+    app.debug("loading settings from %s", "/etc/app.toml")
+    app.info("server listening on port %d", 8000)
+    db.warning("slow query took %.1fs", 2.5)
+    try:
+        {}["user_id"]
+    except KeyError:
+        app.exception("request failed")
+    db.error("connection lost: %s", "timeout")
+    app.critical("shutting down")
+    handler.close()
+
+
+if __name__ == "__main__":
+    here = Path(__file__).parent
+    for name, layout in LAYOUTS.items():
+        write(here / name, layout)